@@ -0,0 +1,215 @@
+use color_eyre::{eyre::eyre, Report};
+use std::ops::RangeInclusive;
+
+use crate::editor::search;
+use crate::editor::Editor;
+
+impl Editor {
+    /// Splits the line range at the start of an Ex command off the rest of it, as in `:%s` or `:'<,'>d`. Addresses
+    /// are separated by `,`, or by `;` to make the next one relative to the previous instead of the cursor line, and
+    /// the last two make up the range.
+    pub(crate) fn parse_range<'a>(
+        &mut self,
+        command: &'a str,
+    ) -> Result<(Option<RangeInclusive<usize>>, &'a str), Report> {
+        let mut current = self.display.cursor.position.1;
+        let mut addresses = Vec::new();
+        let mut separated = false;
+
+        let mut rest = command.trim_start();
+        if let Some(after) = rest.strip_prefix('%') {
+            addresses.extend([1, self.buffer.len_lines()]);
+            rest = after;
+        }
+
+        loop {
+            let (address, after) = self.parse_address(rest, current)?;
+            rest = after.trim_start();
+
+            let separator = rest.chars().next().filter(|&c| c == ',' || c == ';');
+            match (separator, address) {
+                (Some(separator), _) => {
+                    let address = address.unwrap_or(current + 1);
+                    if separator == ';' {
+                        current = address.saturating_sub(1);
+                    }
+                    addresses.push(address);
+                    rest = &rest[1..];
+                    separated = true;
+                }
+                (None, Some(address)) => {
+                    addresses.push(address);
+                    break;
+                }
+                (None, None) => {
+                    // A trailing `,` stands for the current line, as in `:3,`.
+                    if separated {
+                        addresses.push(current + 1);
+                    }
+                    break;
+                }
+            }
+        }
+
+        let (first, last) = match addresses[..] {
+            [] => return Ok((None, rest)),
+            [address] => (address, address),
+            [.., first, last] => (first, last),
+        };
+        if first.max(last) > self.buffer.len_lines() {
+            return Err(eyre!("Invalid range"));
+        }
+
+        // Line 0 only means something to commands placing text after a line, elsewhere it is the first line.
+        let (first, last) = (first.saturating_sub(1), last.saturating_sub(1));
+        Ok((Some(first.min(last)..=first.max(last)), rest))
+    }
+
+    /// Line number, counting from 1, of the address at the start of `text` and the text after it. Addresses are `.`
+    /// for `current`, `$` for the last line, a number, a mark as `'a`, or the next line matching `/pattern/` or the
+    /// previous one matching `?pattern?`, followed by any number of `+N` and `-N` offsets. Offsets alone are relative
+    /// to `current`.
+    pub(crate) fn parse_address<'a>(
+        &mut self,
+        text: &'a str,
+        current: usize,
+    ) -> Result<(Option<usize>, &'a str), Report> {
+        let text = text.trim_start();
+        let mut chars = text.chars();
+
+        let (mut line, mut rest) = match chars.next() {
+            Some('.') => (Some(current + 1), chars.as_str()),
+            Some('$') => (Some(self.buffer.len_lines()), chars.as_str()),
+            Some('\'') => {
+                let mark = chars.next().ok_or_else(|| eyre!("Mark not set"))?;
+                (Some(self.mark(mark)?.1 + 1), chars.as_str())
+            }
+            Some(c) if c.is_ascii_digit() => split_number(text)?,
+            Some(delimiter @ ('/' | '?')) => {
+                let (pattern, rest) = search::split_delimited(chars.as_str(), delimiter);
+                (Some(self.pattern_line(pattern, current, delimiter == '/')? + 1), rest.unwrap_or(""))
+            }
+            _ => (None, text),
+        };
+
+        while let Some(sign @ ('+' | '-')) = rest.chars().next() {
+            let (number, after) = split_number(&rest[1..])?;
+            let base = line.unwrap_or(current + 1);
+            let offset = number.unwrap_or(1);
+
+            let target = if sign == '+' { base.checked_add(offset) } else { base.checked_sub(offset) };
+            line = Some(target.ok_or_else(|| eyre!("Invalid range"))?);
+            rest = after;
+        }
+
+        Ok((line, rest))
+    }
+
+    /// Line of the first match of `pattern` after line `from`, or before it when searching backwards, wrapping around
+    /// the buffer. An empty pattern repeats the last search.
+    fn pattern_line(&mut self, pattern: String, from: usize, forward: bool) -> Result<usize, Report> {
+        let pattern = match pattern.is_empty() {
+            true => self.search.pattern.clone().ok_or_else(|| eyre!("No previous regular expression"))?,
+            false => pattern,
+        };
+        let regex = search::compile(&pattern)?;
+
+        let start = match forward {
+            true => self.buffer.line_to_char(from) + self.buffer.line_len(from),
+            false => self.buffer.line_to_char(from),
+        };
+        let (found, _) = search::find(&self.buffer, &regex, start, forward)
+            .ok_or_else(|| eyre!("Pattern not found: {}", pattern))?;
+
+        self.search.pattern = Some(pattern);
+        Ok(self.buffer.char_to_line(found.start))
+    }
+}
+
+/// Number at the start of `text`, if there is one, and the text after it. Numbers too large for a line are an
+/// invalid range.
+fn split_number(text: &str) -> Result<(Option<usize>, &str), Report> {
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let number = match digits {
+        0 => None,
+        _ => Some(text[..digits].parse().map_err(|_| eyre!("Invalid range"))?),
+    };
+    Ok((number, &text[digits..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Editor on a five-line buffer, with the cursor on line `line`, counting from 0.
+    fn editor(line: usize) -> Editor {
+        let mut editor = Editor::with_text("one\ntwo\nthree\nfour\nfive");
+        editor.set_cursor((0, line));
+        editor
+    }
+
+    /// Line range, counting from 0, and command of `command` with the cursor on line `line`.
+    fn range(line: usize, command: &str) -> (Option<(usize, usize)>, String) {
+        let (range, rest) = editor(line).parse_range(command).unwrap();
+        (range.map(|range| (*range.start(), *range.end())), rest.to_string())
+    }
+
+    #[test]
+    fn numbers_and_special_lines() {
+        assert_eq!(range(2, "d"), (None, "d".to_string()));
+        assert_eq!(range(2, "%d"), (Some((0, 4)), "d".to_string()));
+        assert_eq!(range(2, ".,$ d"), (Some((2, 4)), "d".to_string()));
+        assert_eq!(range(2, "3p"), (Some((2, 2)), "p".to_string()));
+        assert_eq!(range(2, "2,4"), (Some((1, 3)), String::new()));
+        assert_eq!(range(2, "4,2"), (Some((1, 3)), String::new()));
+        assert_eq!(range(2, "0"), (Some((0, 0)), String::new()));
+        assert_eq!(range(2, "1,2,4"), (Some((1, 3)), String::new()));
+    }
+
+    #[test]
+    fn offsets_and_separators() {
+        assert_eq!(range(2, "+1").0, Some((3, 3)));
+        assert_eq!(range(2, "-").0, Some((1, 1)));
+        assert_eq!(range(2, ".+2").0, Some((4, 4)));
+        assert_eq!(range(2, "$-1-1").0, Some((2, 2)));
+        assert_eq!(range(2, "2,+1").0, Some((1, 3)));
+        assert_eq!(range(2, "2;+1").0, Some((1, 2)));
+        assert_eq!(range(0, "3,").0, Some((0, 2)));
+        assert_eq!(range(2, ",").0, Some((2, 2)));
+    }
+
+    #[test]
+    fn patterns_and_marks() {
+        assert_eq!(range(2, "/fo/").0, Some((3, 3)));
+        assert_eq!(range(2, "?o?").0, Some((1, 1)));
+        assert_eq!(range(2, "/one/").0, Some((0, 0)));
+        // Both patterns are looked for from the cursor line, the first wrapping around to `two`.
+        assert_eq!(range(2, "/t/,/f/-1d"), (Some((1, 2)), "d".to_string()));
+
+        let mut editor = editor(3);
+        editor.set_mark('a').unwrap();
+        editor.set_cursor((0, 0));
+        let (range, _) = editor.parse_range("'a,.").unwrap();
+        assert_eq!(range, Some(0..=3));
+    }
+
+    #[test]
+    fn invalid_ranges() {
+        let mut editor = editor(2);
+        assert!(editor.parse_range("6").is_err());
+        assert!(editor.parse_range("1-2").is_err());
+        assert!(editor.parse_range("'z").is_err());
+        assert!(editor.parse_range("/missing/").is_err());
+        // Numbers and offsets too large for a line are errors rather than dropped or wrapped.
+        assert!(editor.parse_range("99999999999999999999").is_err());
+        assert!(editor.parse_range("+99999999999999999999").is_err());
+        assert!(editor.parse_range("+9223372036854775807").is_err());
+        assert!(editor.parse_range("+18446744073709551615").is_err());
+
+        let mut editor = Editor::with_text("a\nb\nc");
+        editor.set_cursor((0, 1));
+        editor.type_keys(":99999999999999999999d<CR>");
+        assert_eq!(editor.error.as_deref(), Some("Invalid range"));
+        assert_eq!(editor.text(), "a\nb\nc");
+    }
+}
@@ -0,0 +1,574 @@
+use color_eyre::{eyre::eyre, Report};
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    mem::replace,
+    path::Path,
+};
+
+use crate::editor::history::{self, History};
+use crate::editor::mark::Marks;
+use crate::editor::motion::Position;
+use crate::editor::visual::VisualArea;
+use crate::editor::Editor;
+use crate::util::Rope;
+
+/// A buffer put away while another one is edited. The buffer being edited lives in the editor's own fields instead,
+/// and is swapped with one of these to switch.
+pub(crate) struct Buffer {
+    number: usize,
+    pub(crate) text: Rope,
+    history: History,
+    pub(crate) filename: Option<String>,
+    pub(crate) readonly: bool,
+    pub(crate) line_ending: LineEnding,
+    cursor: Position,
+    offset: (usize, usize),
+    marks: Marks,
+    last_visual: Option<VisualArea>,
+}
+
+/// Line break of a file, which the buffer reads as `\n` and writes back the way it was.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) enum LineEnding {
+    #[default]
+    Unix,
+    Dos,
+}
+
+impl LineEnding {
+    /// Line break of `text`, told by its first line.
+    pub(crate) fn detect(text: &str) -> Self {
+        match text.find('\n') {
+            Some(end) if text[..end].ends_with('\r') => LineEnding::Dos,
+            _ => LineEnding::Unix,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            LineEnding::Unix => "unix",
+            LineEnding::Dos => "dos",
+        }
+    }
+
+    /// `text` of the buffer as it is written to the file.
+    pub(crate) fn apply(self, text: &str) -> String {
+        match self {
+            LineEnding::Unix => text.to_string(),
+            LineEnding::Dos => text.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// Buffers open besides the current one. Numbers count up from 1 and are not reused after a buffer is deleted.
+pub(crate) struct Buffers {
+    /// Sorted by number.
+    hidden: Vec<Buffer>,
+    pub(crate) current: usize,
+    /// Buffer edited before the current one, which `<C-^>` and `:e #` go back to.
+    pub(crate) alternate: Option<usize>,
+    last_number: usize,
+}
+
+impl Default for Buffers {
+    fn default() -> Self {
+        Self { hidden: Vec::new(), current: 1, alternate: None, last_number: 1 }
+    }
+}
+
+impl Buffer {
+    /// Whether the buffer has changes not written to its file.
+    pub(crate) fn modified(&self) -> bool {
+        self.history.modified()
+    }
+}
+
+impl Buffers {
+    /// Buffer `number` if it is not the current one.
+    pub(crate) fn get(&self, number: usize) -> Option<&Buffer> {
+        self.hidden.iter().find(|buffer| buffer.number == number)
+    }
+
+    fn position(&self, number: usize) -> Option<usize> {
+        self.hidden.iter().position(|buffer| buffer.number == number)
+    }
+
+    /// Numbers of every buffer, including the current one, in order.
+    fn numbers(&self) -> Vec<usize> {
+        let mut numbers = self.hidden.iter().map(|buffer| buffer.number).collect::<Vec<_>>();
+        numbers.push(self.current);
+        numbers.sort();
+        numbers
+    }
+
+    fn new_buffer(&mut self) -> Buffer {
+        self.last_number += 1;
+        Buffer {
+            number: self.last_number,
+            text: Rope::new(""),
+            history: History::new(),
+            filename: None,
+            readonly: false,
+            line_ending: LineEnding::default(),
+            cursor: (0, 0),
+            offset: (0, 0),
+            marks: Marks::default(),
+            last_visual: None,
+        }
+    }
+}
+
+/// Whether `a` and `b` name the same file, comparing the files themselves when they exist.
+fn same_file(a: &str, b: &str) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => Path::new(a) == Path::new(b),
+    }
+}
+
+/// Writes `text` to `filename` with `line_ending`, and `history` to the undo file for it, marking its current revision
+/// as the saved one. Failing to write the undo file does not fail the write, and gives the message returned instead.
+pub(crate) fn write_file(
+    filename: &str,
+    text: &Rope,
+    line_ending: LineEnding,
+    history: &mut History,
+) -> Result<Option<String>, Report> {
+    let mut file = BufWriter::new(File::create(filename)?);
+    for chunk in text.chunks() {
+        file.write_all(line_ending.apply(chunk).as_bytes())?;
+    }
+    file.flush()?;
+
+    history.mark_saved();
+    let Some(path) = history::undo_file_path(filename) else {
+        return Ok(None);
+    };
+    let saved = history.save(&path, &history::content_hash(text));
+    Ok(saved.err().map(|err| format!("Failed to write undo file {}: {}", path.display(), err)))
+}
+
+impl Editor {
+    /// `:e[dit][!] [file]`: edits `file` in the buffer that already has it, or in a new one. Without a file, or with
+    /// the current one, the file is read again. Either way a modified buffer is only left behind with `force`, and
+    /// keeps its changes when it is.
+    pub(crate) fn edit_file(&mut self, filename: Option<&str>, force: bool) -> Result<(), Report> {
+        let filename = match filename {
+            Some("#") => {
+                return self.switch_buffer(self.buffers.alternate.ok_or_else(|| eyre!("No alternate file"))?, force)
+            }
+            Some(filename) => filename.to_string(),
+            None => self.filename.clone().ok_or_else(|| eyre!("No file name"))?,
+        };
+
+        if self.filename.as_deref().is_some_and(|current| same_file(current, &filename)) {
+            self.check_modified(force)?;
+            let cursor = self.display.cursor.position;
+            self.load_file(&filename)?;
+            self.marks = Marks::default();
+            self.set_cursor(cursor);
+            return Ok(());
+        }
+
+        let existing = self
+            .buffers
+            .hidden
+            .iter()
+            .find(|buffer| buffer.filename.as_deref().is_some_and(|name| same_file(name, &filename)));
+        if let Some(number) = existing.map(|buffer| buffer.number) {
+            return self.switch_buffer(number, force);
+        }
+
+        self.check_modified(force)?;
+        let buffer = self.buffers.new_buffer();
+        self.replace_buffer(buffer);
+        self.load_file(&filename)
+    }
+
+    /// `:b[uffer][!] {N|name}`: switches to buffer `number`. Leaving a modified buffer needs `force`.
+    pub(crate) fn switch_buffer(&mut self, number: usize, force: bool) -> Result<(), Report> {
+        if number == self.buffers.current {
+            return Ok(());
+        }
+
+        let index = self.buffers.position(number).ok_or_else(|| eyre!("Buffer {} does not exist", number))?;
+        self.check_modified(force)?;
+
+        let buffer = self.buffers.hidden.remove(index);
+        self.replace_buffer(buffer);
+        Ok(())
+    }
+
+    /// Edits buffer `number` in a window moved to, which leaves the alternate buffer as it was.
+    pub(crate) fn show_buffer(&mut self, number: usize) {
+        let Some(index) = self.buffers.position(number) else {
+            return;
+        };
+
+        let alternate = self.buffers.alternate;
+        let buffer = self.buffers.hidden.remove(index);
+        self.replace_buffer(buffer);
+        self.buffers.alternate = alternate;
+    }
+
+    /// `:new` and `:vnew`: edits a new empty buffer.
+    pub(crate) fn edit_new_buffer(&mut self) {
+        let buffer = self.buffers.new_buffer();
+        self.replace_buffer(buffer);
+    }
+
+    /// `:bn[ext][!]` and `:bp[revious][!]`: switches to the buffer numbered after or before the current one, wrapping
+    /// around at the ends of the list.
+    pub(crate) fn cycle_buffer(&mut self, forward: bool, force: bool) -> Result<(), Report> {
+        let numbers = self.buffers.numbers();
+        let index = numbers.iter().position(|&number| number == self.buffers.current).unwrap_or(0);
+        let steps = self.repeats() % numbers.len();
+        let target = match forward {
+            true => (index + steps) % numbers.len(),
+            false => (index + numbers.len() - steps) % numbers.len(),
+        };
+        self.switch_buffer(numbers[target], force)
+    }
+
+    /// `<C-^>`: switches to the alternate buffer, or to buffer N given as a count.
+    pub(crate) fn switch_to_alternate(&mut self) -> Result<(), Report> {
+        let number = self.count.or(self.buffers.alternate).ok_or_else(|| eyre!("No alternate file"))?;
+        self.switch_buffer(number, false)
+    }
+
+    /// `:bd[elete][!] [N|name]`: removes buffer `number` from the list, the current one when `None`. A modified
+    /// buffer is only deleted with `force`. When the current buffer goes, the alternate or a neighbour takes its
+    /// place, or an empty buffer if it was the last.
+    pub(crate) fn delete_buffer(&mut self, number: Option<usize>, force: bool) -> Result<(), Report> {
+        let number = number.unwrap_or(self.buffers.current);
+        if number != self.buffers.current {
+            let index = self.buffers.position(number).ok_or_else(|| eyre!("Buffer {} does not exist", number))?;
+            if self.buffers.hidden[index].modified() && !force {
+                return Err(eyre!("No write since last change for buffer {} (add ! to override)", number));
+            }
+            self.buffers.hidden.remove(index);
+            if self.buffers.alternate == Some(number) {
+                self.buffers.alternate = None;
+            }
+            self.close_windows_showing(number);
+            return Ok(());
+        }
+
+        if self.history.modified() && !force {
+            return Err(eyre!("No write since last change for buffer {} (add ! to override)", number));
+        }
+
+        let next = self.buffers.alternate.and_then(|alternate| self.buffers.position(alternate));
+        let next = next.or_else(|| match self.buffers.hidden.iter().position(|buffer| buffer.number > number) {
+            Some(index) => Some(index),
+            None => self.buffers.hidden.len().checked_sub(1),
+        });
+        let buffer = match next {
+            Some(index) => self.buffers.hidden.remove(index),
+            None => self.buffers.new_buffer(),
+        };
+
+        self.replace_buffer(buffer);
+        self.buffers.hidden.retain(|buffer| buffer.number != number);
+        self.buffers.alternate = None;
+        self.close_windows_showing(number);
+        Ok(())
+    }
+
+    /// Buffer that `args` of `:b` and `:bd` refer to: a number, or a unique part of a file name.
+    pub(crate) fn find_buffer(&self, args: &str) -> Result<Option<usize>, Report> {
+        if args.is_empty() {
+            return Ok(None);
+        }
+        if let Ok(number) = args.parse::<usize>() {
+            return Ok(Some(number));
+        }
+
+        let names = self.buffers.hidden.iter().map(|buffer| (buffer.number, buffer.filename.as_deref()));
+        let matches = names
+            .chain([(self.buffers.current, self.filename.as_deref())])
+            .filter(|(_, name)| name.is_some_and(|name| name.contains(args)))
+            .collect::<Vec<_>>();
+        match matches.as_slice() {
+            [] => Err(eyre!("No matching buffer for {}", args)),
+            [(number, _)] => Ok(Some(*number)),
+            _ => Err(eyre!("More than one match for {}", args)),
+        }
+    }
+
+    /// `:ls`: lists the buffers in the same shape as Vim, with `%` for the current one, `#` for the alternate, `a`
+    /// and `h` for shown and hidden, and `+` for modified.
+    pub(crate) fn list_buffers(&self) -> String {
+        let current =
+            (self.buffers.current, self.filename.as_deref(), self.history.modified(), self.display.cursor.position);
+        let mut buffers = self
+            .buffers
+            .hidden
+            .iter()
+            .map(|buffer| (buffer.number, buffer.filename.as_deref(), buffer.modified(), buffer.cursor))
+            .chain([current])
+            .collect::<Vec<_>>();
+        buffers.sort_by_key(|(number, ..)| *number);
+
+        let lines = buffers.into_iter().map(|(number, filename, modified, (_, line))| {
+            let flag = match number {
+                _ if number == self.buffers.current => '%',
+                _ if Some(number) == self.buffers.alternate => '#',
+                _ => ' ',
+            };
+            let state = if number == self.buffers.current { 'a' } else { 'h' };
+            let name = format!("\"{}\"", filename.unwrap_or("[No Name]"));
+            format!(
+                "{:>3} {}{} {} {:<30} line {}",
+                number,
+                flag,
+                state,
+                if modified { '+' } else { ' ' },
+                name,
+                line + 1
+            )
+        });
+        lines.collect::<Vec<_>>().join("\n")
+    }
+
+    /// File name of buffer `number`, and whether it is modified.
+    pub(crate) fn buffer_state(&self, number: usize) -> (Option<&str>, bool) {
+        match self.buffers.get(number) {
+            Some(buffer) => (buffer.filename.as_deref(), buffer.modified()),
+            None => (self.filename.as_deref(), self.history.modified()),
+        }
+    }
+
+    /// Fails when leaving the current buffer would lose sight of changes to it, unless `force` is set. Another
+    /// window still showing it, in any tab page, is enough.
+    fn check_modified(&self, force: bool) -> Result<(), Report> {
+        let shown = self.windows.shows(self.buffers.current) || self.tabs.shows(self.buffers.current);
+        match self.history.modified() && !force && !shown {
+            true => Err(eyre!("No write since last change (add ! to override)")),
+            false => Ok(()),
+        }
+    }
+
+    /// Fails when quitting would lose changes to a buffer, naming it when it is not the current one.
+    pub(crate) fn check_all_saved(&self) -> Result<(), Report> {
+        if self.history.modified() {
+            return Err(eyre!("No write since last change (add ! to override)"));
+        }
+        match self.buffers.hidden.iter().find(|buffer| buffer.modified()) {
+            Some(buffer) => Err(eyre!("No write since last change for buffer {} (add ! to override)", buffer.number)),
+            None => Ok(()),
+        }
+    }
+
+    /// `:wa[ll]`: writes every modified buffer to its file. Buffers without a file name are left as they are, and
+    /// reported once the others are written.
+    pub(crate) fn write_all(&mut self) -> Result<(), Report> {
+        let mut unnamed = Vec::new();
+        for buffer in self.buffers.hidden.iter_mut().filter(|buffer| buffer.modified()) {
+            match &buffer.filename {
+                Some(filename) => {
+                    if let Some(error) = write_file(filename, &buffer.text, buffer.line_ending, &mut buffer.history)? {
+                        self.error = Some(error);
+                    }
+                    buffer.readonly = false;
+                }
+                None => unnamed.push(buffer.number),
+            }
+        }
+
+        if self.history.modified() {
+            match self.filename.clone() {
+                Some(filename) => self.save_file(&filename)?,
+                None => unnamed.push(self.buffers.current),
+            }
+        }
+
+        match unnamed.iter().min() {
+            Some(number) => Err(eyre!("No file name for buffer {}", number)),
+            None => Ok(()),
+        }
+    }
+
+    /// Puts the current buffer away among the hidden ones and edits `buffer` instead, making the one left the
+    /// alternate.
+    fn replace_buffer(&mut self, buffer: Buffer) {
+        let previous = Buffer {
+            number: replace(&mut self.buffers.current, buffer.number),
+            text: replace(&mut self.buffer, buffer.text),
+            history: replace(&mut self.history, buffer.history),
+            filename: replace(&mut self.filename, buffer.filename),
+            readonly: replace(&mut self.readonly, buffer.readonly),
+            line_ending: replace(&mut self.line_ending, buffer.line_ending),
+            cursor: self.display.cursor.position,
+            offset: self.display.offset,
+            marks: replace(&mut self.marks, buffer.marks),
+            last_visual: replace(&mut self.last_visual, buffer.last_visual),
+        };
+
+        self.display.offset = buffer.offset;
+        self.set_cursor(buffer.cursor);
+        self.display.cursor_clamp(&self.buffer);
+        self.buffers.alternate = Some(previous.number);
+
+        let index = self.buffers.hidden.partition_point(|hidden| hidden.number < previous.number);
+        self.buffers.hidden.insert(index, previous);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    /// Directory with the files `a` and `b`, unique to `test`.
+    fn files(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("crate-buffers-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a"), "alpha\n").unwrap();
+        std::fs::write(dir.join("b"), "beta\n").unwrap();
+        dir
+    }
+
+    /// Editor that has edited `a` and then `b`, from `dir`.
+    fn editing(dir: &Path) -> Editor {
+        let mut editor = Editor::with_text("");
+        editor.type_keys(&format!(":e {}<CR>:e {}<CR>", dir.join("a").display(), dir.join("b").display()));
+        editor
+    }
+
+    #[test]
+    fn lists_buffers() {
+        let dir = files("ls");
+        let mut editor = editing(&dir);
+        editor.type_keys("ix<Esc>:ls<CR>");
+
+        let line =
+            |number, flags, name: &str| format!("{:>3} {} {:<30} line 1", number, flags, format!("\"{}\"", name));
+        let (a, b) = (dir.join("a").display().to_string(), dir.join("b").display().to_string());
+        let expected = [line(1, " h  ", "[No Name]"), line(2, "#h  ", &a), line(3, "%a +", &b)].join("\n");
+        assert_eq!(editor.error.as_deref(), Some(expected.as_str()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn switches_buffers() {
+        let dir = files("switch");
+        let mut editor = editing(&dir);
+        assert_eq!((editor.buffers.current, editor.text().as_str()), (3, "beta"));
+
+        editor.type_keys("<C-^>");
+        assert_eq!((editor.buffers.current, editor.text().as_str()), (2, "alpha"));
+        editor.type_keys("<C-^>");
+        assert_eq!(editor.buffers.current, 3);
+        editor.type_keys("1<C-^>");
+        assert_eq!((editor.buffers.current, editor.text().as_str()), (1, ""));
+
+        editor.type_keys(":b 3<CR>");
+        assert_eq!(editor.buffers.current, 3);
+        editor.type_keys(":b /a<CR>");
+        assert_eq!(editor.buffers.current, 2);
+        editor.type_keys(":bn<CR>");
+        assert_eq!(editor.buffers.current, 3);
+        editor.type_keys(":bn<CR>");
+        assert_eq!(editor.buffers.current, 1);
+        editor.type_keys(":bp<CR>:bp<CR>");
+        assert_eq!(editor.buffers.current, 2);
+        // Editing a file already in a buffer goes back to that buffer.
+        editor.type_keys(&format!(":e {}<CR>", dir.join("b").display()));
+        assert_eq!(editor.buffers.current, 3);
+
+        editor.type_keys(":b 9<CR>");
+        assert_eq!(editor.error.as_deref(), Some("Buffer 9 does not exist"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_buffers() {
+        let dir = files("delete");
+        let mut editor = editing(&dir);
+
+        // Deleting the current buffer goes to the alternate one, and a hidden one just leaves the list.
+        editor.type_keys(":bd<CR>");
+        assert_eq!((editor.buffers.current, editor.text().as_str()), (2, "alpha"));
+        editor.type_keys(":bd 1<CR>:ls<CR>");
+        assert_eq!(editor.error.as_ref().map(|list| list.lines().count()), Some(1));
+        editor.type_keys(":bd<CR>");
+        assert_eq!((editor.buffers.current, editor.filename.as_deref()), (4, None));
+
+        editor.type_keys("ix<Esc>:bd<CR>");
+        assert!(editor.error.as_deref().is_some_and(|error| error.starts_with("No write since last change")));
+        editor.type_keys(":bd!<CR>");
+        assert_eq!((editor.buffers.current, editor.text().as_str()), (5, ""));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn edit_refuses_to_leave_modified_buffer() {
+        let dir = files("modified");
+        let mut editor = editing(&dir);
+        editor.type_keys("ix<Esc>");
+
+        editor.type_keys(&format!(":e {}<CR>", dir.join("a").display()));
+        assert_eq!(editor.error.as_deref(), Some("No write since last change (add ! to override)"));
+        editor.type_keys("<C-^>");
+        assert_eq!(editor.buffers.current, 3);
+        editor.type_keys(":e<CR>");
+        assert_eq!(editor.text(), "xbeta");
+
+        // With `!` the buffer is left with its changes, which are still there when it is edited again.
+        editor.type_keys(&format!(":e! {}<CR>", dir.join("a").display()));
+        assert_eq!(editor.buffers.current, 2);
+        editor.type_keys("<C-^>");
+        assert!(editor.history.modified());
+        assert_eq!(editor.text(), "xbeta");
+        editor.type_keys(":e!<CR>");
+        assert_eq!(editor.text(), "beta");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn quit_refuses_to_lose_changes() {
+        let dir = files("quit");
+        let mut editor = editing(&dir);
+        editor.type_keys("ix<Esc>:q<CR>");
+        assert_eq!(editor.error.as_deref(), Some("No write since last change (add ! to override)"));
+        // Undoing back to the text read from the file is no change.
+        editor.type_keys("u:q<CR>");
+        assert!(editor.stop);
+
+        let mut editor = editing(&dir);
+        editor.type_keys("<C-^>ix<Esc>:b! 3<CR>:q<CR>");
+        assert_eq!(editor.error.as_deref(), Some("No write since last change for buffer 2 (add ! to override)"));
+        editor.type_keys(":qa<CR>");
+        assert!(!editor.stop);
+        editor.type_keys(":q!<CR>");
+        assert!(editor.stop);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn writes_all_buffers() {
+        let dir = files("wall");
+        let mut editor = editing(&dir);
+        editor.type_keys("<C-^>ix<Esc>:b! 3<CR>iy<Esc>:wa<CR>");
+        let written = |name| std::fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!((written("a"), written("b")), ("xalpha".to_string(), "ybeta".to_string()));
+        editor.type_keys(":q<CR>");
+        assert!(editor.stop);
+
+        // Buffers without a name are reported after the others are written.
+        let mut editor = editing(&dir);
+        editor.type_keys(":b 1<CR>iz<Esc>:b! 3<CR>iw<Esc>:wa<CR>");
+        assert_eq!(editor.error.as_deref(), Some("No file name for buffer 1"));
+        assert_eq!(written("b"), "wybeta");
+        editor.type_keys(":xa<CR>");
+        assert!(!editor.stop);
+
+        for name in ["a", "b"] {
+            let _ = std::fs::remove_file(history::undo_file_path(&dir.join(name).display().to_string()).unwrap());
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+use color_eyre::{eyre::eyre, Report};
+use std::fs;
+
+use crate::editor::motion;
+use crate::editor::Editor;
+use crate::util::ex_commands::{Completion, DefaultRange, ExArgs};
+
+/// Splits the name off an Ex command: a run of letters, or a single other char as in `:>` or `:!`.
+fn split_name(command: &str) -> (&str, &str) {
+    let len = match command.find(|c: char| !c.is_ascii_alphabetic()) {
+        Some(0) => command.chars().next().map_or(0, char::len_utf8),
+        Some(len) => len,
+        None => command.len(),
+    };
+    command.split_at(len)
+}
+
+/// Longest text all of `candidates` start with.
+fn common_prefix(candidates: &[String]) -> String {
+    let Some(first) = candidates.first() else {
+        return String::new();
+    };
+
+    let mut prefix = first.as_str();
+    for candidate in &candidates[1..] {
+        while !candidate.starts_with(prefix) {
+            prefix = &prefix[..prefix.len() - prefix.chars().last().map_or(0, char::len_utf8)];
+        }
+    }
+    prefix.to_string()
+}
+
+/// Paths starting with `prefix`, with a `/` after directories. Hidden files are only offered once `.` is typed.
+fn complete_file(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(i) => (&prefix[..=i], &prefix[i + 1..]),
+        None => ("", prefix),
+    };
+
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return Vec::new();
+    };
+    let mut candidates = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let file_name = entry.file_name().into_string().ok()?;
+            if !file_name.starts_with(name) || (file_name.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{}{}{}", dir, file_name, slash))
+        })
+        .collect::<Vec<_>>();
+    candidates.sort();
+    candidates
+}
+
+impl Editor {
+    /// Runs an Ex command line as typed after `:`, looking its name up in the registered commands.
+    pub(crate) fn execute_command(&mut self, line: &str) -> Result<(), Report> {
+        let (range, command) = self.parse_range(line)?;
+        let command = command.trim_start();
+
+        // A range alone moves to its last line, as in `:42`.
+        if command.is_empty() {
+            if let Some(range) = range {
+                let last = *range.end();
+                self.set_cursor((motion::first_non_blank_column(&self.buffer, last), last));
+            }
+            return Ok(());
+        }
+
+        let (name, rest) = split_name(command);
+        let command = self.commands.find(name).cloned();
+        let command = command.ok_or_else(|| eyre!("Not an editor command: {}", line.trim()))?;
+
+        let (bang, args) = match rest.strip_prefix('!') {
+            Some(args) if command.bang => (true, args),
+            _ => (false, rest),
+        };
+        if range.is_some() && command.range == DefaultRange::None {
+            return Err(eyre!("No range allowed"));
+        }
+
+        let cursor_line = self.display.cursor.position.1;
+        let lines = match (&range, command.range) {
+            (Some(range), _) => range.clone(),
+            (None, DefaultRange::WholeBuffer) => 0..=self.buffer.len_lines() - 1,
+            (None, _) => cursor_line..=cursor_line,
+        };
+
+        let args = ExArgs { lines, range: range.is_some(), bang, args: args.trim().to_string() };
+        (command.handler)(self, &args)
+    }
+
+    /// Line below which `:m` and `:t` put text, counting from 1, with 0 for above the first line.
+    pub(crate) fn parse_target(&mut self, args: &str) -> Result<usize, Report> {
+        let (target, rest) = self.parse_address(args, self.display.cursor.position.1)?;
+        if !rest.trim().is_empty() {
+            return Err(eyre!("Trailing characters: {}", rest.trim()));
+        }
+
+        target.filter(|&target| target <= self.buffer.len_lines()).ok_or_else(|| eyre!("Invalid address"))
+    }
+
+    /// `<Tab>` on the command line: completes the command name being typed, or the last word of its arguments, as
+    /// far as every candidate agrees, listing the candidates when there are several.
+    pub(crate) fn complete_command(&mut self) {
+        if self.search.prompt.is_some() {
+            return;
+        }
+
+        let line = self.command.clone();
+        let Ok((_, command)) = self.parse_range(&line) else {
+            return;
+        };
+        let command = command.trim_start();
+        let (name, rest) = split_name(command);
+
+        let (start, candidates) = if rest.is_empty() {
+            let names = self.commands.complete(name).into_iter().map(str::to_string).collect();
+            (line.len() - command.len(), names)
+        } else {
+            let Some(completion) = self.commands.find(name).map(|command| command.completion) else {
+                return;
+            };
+            let word = rest.rsplit(char::is_whitespace).next().unwrap_or("");
+            let candidates = match completion {
+                Completion::File => complete_file(word),
+                Completion::Command => self.commands.complete(word).into_iter().map(str::to_string).collect(),
+                Completion::None => return,
+            };
+            (line.len() - word.len(), candidates)
+        };
+
+        if candidates.is_empty() {
+            return;
+        }
+        if candidates.len() > 1 {
+            self.error = Some(candidates.join("  "));
+        }
+
+        let completed = common_prefix(&candidates);
+        if completed.len() > line.len() - start {
+            self.command.replace_range(start.., &completed);
+        }
+    }
+
+    /// `:h[elp] [name]`: lists every Ex command and what it does, or only the one `name` refers to.
+    pub(crate) fn list_commands(&self, name: &str) -> Result<String, Report> {
+        let commands = match name.strip_prefix(':').unwrap_or(name) {
+            "" => self.commands.iter().collect::<Vec<_>>(),
+            name => vec![self.commands.find(name).ok_or_else(|| eyre!("Sorry, no help for {}", name))?],
+        };
+
+        let lines = commands.into_iter().map(|command| {
+            let bang = if command.bang { "[!]" } else { "" };
+            let range = if command.range == DefaultRange::None { "" } else { "[range]" };
+            format!(":{:<16} {:<8} {}", format!("{}{}", command.usage(), bang), range, command.help)
+        });
+        Ok(lines.collect::<Vec<_>>().join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    const TEXT: &str = "one\ntwo\nthree\nfour";
+
+    fn executed(line: &str) -> Result<String, String> {
+        let mut editor = Editor::with_text(TEXT);
+        editor.set_cursor((0, 1));
+        editor.execute_command(line).map_err(|err| err.to_string())?;
+        Ok(editor.text())
+    }
+
+    #[test]
+    fn executes_abbreviated_commands() {
+        assert_eq!(executed("d"), Ok("one\nthree\nfour".to_string()));
+        assert_eq!(executed("3del"), Ok("one\ntwo\nfour".to_string()));
+        assert_eq!(executed("  1,2  delete  "), Ok("three\nfour".to_string()));
+        assert_eq!(executed("sor"), Ok("four\none\nthree\ntwo".to_string()));
+        assert_eq!(executed("sort!"), Ok("two\nthree\none\nfour".to_string()));
+        assert_eq!(executed(">"), Ok("one\n    two\nthree\nfour".to_string()));
+        assert_eq!(executed("2"), Ok(TEXT.to_string()));
+
+        assert_eq!(executed("so"), Err("Not an editor command: so".to_string()));
+        assert_eq!(executed("1,2q"), Err("No range allowed".to_string()));
+    }
+
+    #[test]
+    fn parses_targets() {
+        let mut editor = Editor::with_text(TEXT);
+        editor.set_cursor((0, 1));
+        assert_eq!(editor.parse_target("0").unwrap(), 0);
+        assert_eq!(editor.parse_target("$").unwrap(), 4);
+        assert_eq!(editor.parse_target(".").unwrap(), 2);
+        assert_eq!(editor.parse_target("/four/").unwrap(), 4);
+        assert!(editor.parse_target("5").is_err());
+        assert!(editor.parse_target("2 x").is_err());
+        assert!(editor.parse_target("").is_err());
+    }
+
+    #[test]
+    fn completes_command_line() {
+        let completed = |line: &str| {
+            let mut editor = Editor::with_text(TEXT);
+            editor.type_keys(&format!(":{}<Tab>", line));
+            (editor.command.clone(), editor.error.clone())
+        };
+        assert_eq!(completed("und"), ("undo".to_string(), Some("undo  undolist".to_string())));
+        assert_eq!(completed("undol"), ("undolist".to_string(), None));
+        assert_eq!(completed("%so"), ("%sort".to_string(), None));
+        assert_eq!(completed("help su"), ("help substitute".to_string(), None));
+        assert_eq!(completed("zz"), ("zz".to_string(), None));
+    }
+
+    #[test]
+    fn lists_commands() {
+        let editor = Editor::with_text(TEXT);
+        assert_eq!(
+            editor.list_commands("d").unwrap(),
+            format!(":{:<16} {:<8} {}", "d[elete]", "[range]", "Delete lines into a register")
+        );
+        assert!(editor.list_commands(":sort").unwrap().starts_with(":sor[t][!]"));
+        assert_eq!(editor.list_commands("").unwrap().lines().count(), editor.commands.iter().count());
+        assert!(editor.list_commands("zz").is_err());
+    }
+}
@@ -1,60 +1,154 @@
-use color_eyre::Report;
+use color_eyre::{eyre::eyre, Report};
 use crossterm::event::{poll, read, Event, KeyCode, KeyEvent, KeyModifiers};
 use std::{
-    fs::File,
-    io::{BufRead, BufReader, Write},
+    collections::VecDeque,
+    fs,
+    ops::Range,
     path::Path,
-    sync::mpsc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread,
     time::{Duration, Instant},
 };
 use tokio::runtime::Runtime;
 
-use crate::util::{Display, Keymap};
-
-#[derive(Eq, PartialEq, Hash)]
+use crate::editor::buffers::{self, Buffers, LineEnding};
+use crate::editor::global::GlobalLines;
+use crate::editor::history::{self, History};
+use crate::editor::mark::Marks;
+use crate::editor::motion::{self, CharSearch};
+use crate::editor::operator::PendingOperator;
+use crate::editor::recording::Recording;
+use crate::editor::register::Registers;
+use crate::editor::repeat::DotRepeat;
+use crate::editor::search::Search;
+use crate::editor::substitute::Substitute;
+use crate::editor::tabs::Tabs;
+use crate::editor::visual::{BlockInsert, Visual, VisualArea, VisualKind};
+use crate::editor::window::Windows;
+use crate::util::keymap::key_notation;
+use crate::util::{Clipboard, Display, ExCommands, Keymap, Rope, StatusLine};
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Eq, PartialEq, Hash)]
 pub enum Mode {
     NORMAL,
     COMMAND,
     INSERT,
     VISUAL,
+    OPERATOR,
 }
 
+/// Action waiting for the char typed after its keys, as in `f{char}`.
+pub(crate) type CharArgumentFn = dyn FnOnce(&mut Editor, char) -> Result<(), Report>;
+
 pub struct Editor {
-    pub(crate) buffer: Vec<String>,
+    pub(crate) buffer: Rope,
+    pub(crate) history: History,
     pub(crate) error: Option<String>,
     pub(crate) command: String,
 
     pub(crate) filename: Option<String>,
+    /// Whether the file could not be written to when it was read.
+    pub(crate) readonly: bool,
+    pub(crate) line_ending: LineEnding,
+    pub(crate) buffers: Buffers,
+    pub(crate) windows: Windows,
+    pub(crate) tabs: Tabs,
 
     pub(crate) dirty: bool,
     pub(crate) stop: bool,
+    /// Cleared while a shell command has the terminal, so that what is typed goes to it instead of the editor.
+    pub(crate) listening: Arc<AtomicBool>,
 
     pub(crate) mode: Mode,
 
     pub(crate) display: Display,
+    pub(crate) statusline: StatusLine,
 
     pub(crate) keymap: Keymap,
+    pub(crate) commands: ExCommands,
     pub(crate) last_key_time: Instant,
+    pub(crate) count: Option<usize>,
+    /// Keys typed so far of the command being typed, shown in the status line.
+    pub(crate) pending_keys: String,
+    pub(crate) char_argument: Option<Box<CharArgumentFn>>,
+    /// Keys to process before reading the terminal again, queued by macros and mappings, each with whether mappings
+    /// apply to it.
+    pub(crate) typeahead: VecDeque<(KeyEvent, bool)>,
+    pub(crate) recording: Option<Recording>,
+    pub(crate) last_macro: Option<char>,
+    pub(crate) dot_repeat: DotRepeat,
+
+    pub(crate) operator: Option<PendingOperator>,
+    pub(crate) registers: Registers,
+    pub(crate) selected_register: Option<char>,
+    pub(crate) clipboard: Clipboard,
+    pub(crate) last_find: Option<CharSearch>,
+    pub(crate) marks: Marks,
+    pub(crate) global_lines: GlobalLines,
+    pub(crate) search: Search,
+    pub(crate) substitute: Substitute,
+
+    pub(crate) visual: Option<Visual>,
+    pub(crate) last_visual: Option<VisualArea>,
+    pub(crate) block_insert: Option<BlockInsert>,
 }
 
 impl Editor {
     pub fn new() -> Self {
+        Self::with_display(Display::new())
+    }
+
+    pub(crate) fn with_display(display: Display) -> Self {
         Self {
-            buffer: vec![String::new()],
+            buffer: Rope::new(""),
+            history: History::new(),
             error: None,
             command: String::new(),
 
             filename: None,
+            readonly: false,
+            line_ending: LineEnding::default(),
+            buffers: Buffers::default(),
+            windows: Windows::default(),
+            tabs: Tabs::default(),
 
             dirty: true,
             stop: false,
+            listening: Arc::new(AtomicBool::new(true)),
 
             mode: Mode::NORMAL,
 
-            display: Display::new(),
+            display,
+            statusline: StatusLine::default(),
 
             keymap: Keymap::new(),
+            commands: ExCommands::new(),
             last_key_time: Instant::now(),
+            count: None,
+            pending_keys: String::new(),
+            char_argument: None,
+            typeahead: VecDeque::new(),
+            recording: None,
+            last_macro: None,
+            dot_repeat: DotRepeat::default(),
+
+            operator: None,
+            registers: Registers::new(),
+            selected_register: None,
+            clipboard: Clipboard::detect(),
+            last_find: None,
+            marks: Marks::default(),
+            global_lines: GlobalLines::default(),
+            search: Search::default(),
+            substitute: Substitute::default(),
+
+            visual: None,
+            last_visual: None,
+            block_insert: None,
         }
     }
 
@@ -64,39 +158,196 @@ impl Editor {
             return Ok(());
         }
 
-        let file = File::open(filename)?;
-        let reader = BufReader::new(file);
-        self.buffer = reader.lines().collect::<Result<Vec<String>, _>>()?;
+        let text = fs::read_to_string(filename)?;
+        self.line_ending = LineEnding::detect(&text);
+        self.readonly = fs::metadata(filename)?.permissions().readonly();
+        let text = text.replace("\r\n", "\n");
+        self.buffer = Rope::new(text.strip_suffix('\n').unwrap_or(&text));
+        self.history = History::new();
+
+        if let Some(path) = history::undo_file_path(filename) {
+            match History::load(&path, &history::content_hash(&self.buffer)) {
+                Ok(Some(history)) => self.history = history,
+                Ok(None) => {}
+                Err(err) => self.error = Some(format!("Failed to read undo file {}: {}", path.display(), err)),
+            }
+        }
 
         Ok(())
     }
 
     pub fn save_file(&mut self, filename: &str) -> Result<(), Report> {
-        let mut file = File::create(filename)?;
-        file.write_all(self.buffer.join("\n").as_bytes())?;
+        if let Some(error) = buffers::write_file(filename, &self.buffer, self.line_ending, &mut self.history)? {
+            self.error = Some(error);
+        }
+        self.readonly = false;
 
         self.filename = Some(filename.to_string());
         Ok(())
     }
 
+    /// Inserts `text` at char index `index`, recording the edit in the undo history.
+    pub(crate) fn insert_text(&mut self, index: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let breaks = text.matches('\n').count();
+        if breaks > 0 {
+            // Text inserted at the start of a line pushes that line down too.
+            let (x, y) = motion::to_position(&self.buffer, index);
+            self.adjust_lines(0..0, if x == 0 { y } else { y + 1 }, breaks as isize);
+        }
+
+        self.dot_repeat.edited = true;
+        self.history.record(index, String::new(), text.to_string(), self.display.cursor.position);
+        self.buffer.insert(index, text);
+    }
+
+    /// Removes the chars in `range`, recording the edit in the undo history.
+    pub(crate) fn remove_text(&mut self, range: Range<usize>) {
+        if range.is_empty() {
+            return;
+        }
+
+        let (first, last) = (self.buffer.char_to_line(range.start), self.buffer.char_to_line(range.end));
+        if last > first {
+            // Removing whole lines moves the line after them up, otherwise what is left of the last line joins the
+            // first.
+            let whole = range.start == self.buffer.line_to_char(first) && range.end == self.buffer.line_to_char(last);
+            let (removed, shifted) = if whole { (first..last, last) } else { (first + 1..last + 1, last + 1) };
+            self.adjust_lines(removed, shifted, first as isize - last as isize);
+        }
+
+        self.dot_repeat.edited = true;
+        let removed = self.buffer.slice(range.clone());
+        self.history.record(range.start, removed, String::new(), self.display.cursor.position);
+        self.buffer.remove(range);
+    }
+
+    pub(crate) fn replace_text(&mut self, range: Range<usize>, text: &str) {
+        let start = range.start;
+        self.remove_text(range);
+        self.insert_text(start, text);
+    }
+
+    /// Makes the next key typed the argument of `action` instead of being looked up in the keymap.
+    pub(crate) fn await_char<F>(&mut self, action: F)
+    where
+        F: FnOnce(&mut Editor, char) -> Result<(), Report> + 'static,
+    {
+        self.char_argument = Some(Box::new(action));
+    }
+
+    /// Error for commands that fail without a message, such as motions that cannot move. Like any error, it ends a
+    /// running macro.
+    pub(crate) fn failed() -> Report {
+        eyre!("")
+    }
+
+    /// Queues `keys` to be handled as if typed, ahead of anything typed or queued already. Mappings only apply to them
+    /// if `remap` is set.
+    pub(crate) fn feed_keys(&mut self, keys: Vec<KeyEvent>, remap: bool) {
+        for key in keys.into_iter().rev() {
+            self.typeahead.push_front((key, remap));
+        }
+    }
+
+    /// Count typed before the current action, defaulting to 1.
+    pub(crate) fn repeats(&self) -> usize {
+        self.count.unwrap_or(1)
+    }
+
+    pub(crate) fn set_cursor(&mut self, (x, y): (usize, usize)) {
+        self.display.cursor_move_y(y, &self.buffer);
+        self.display.cursor_move_x(x, &self.buffer);
+    }
+
+    pub(crate) fn undo(&mut self) -> Result<(), Report> {
+        for _ in 0..self.repeats() {
+            let cursor = self.history.undo(&mut self.buffer).ok_or_else(|| eyre!("Already at oldest change"))?;
+            self.set_cursor(cursor);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn redo(&mut self) -> Result<(), Report> {
+        for _ in 0..self.repeats() {
+            let cursor = self.history.redo(&mut self.buffer).ok_or_else(|| eyre!("Already at newest change"))?;
+            self.set_cursor(cursor);
+        }
+        Ok(())
+    }
+
+    /// Moves `steps` revisions backwards or forwards in time, following the undo tree across branches.
+    pub(crate) fn undo_time(&mut self, steps: isize) -> Result<(), Report> {
+        self.history.commit();
+        let target = self.history.current.saturating_add_signed(steps).min(self.history.revisions.len() - 1);
+        if target == self.history.current {
+            return Err(eyre!(if steps < 0 { "Already at oldest change" } else { "Already at newest change" }));
+        }
+
+        if let Some(cursor) = self.history.goto(target, &mut self.buffer) {
+            self.set_cursor(cursor);
+        }
+        Ok(())
+    }
+
+    /// Lists the tip of every undo branch, in the same shape as Vim's `:undolist`.
+    pub(crate) fn undo_list(&self) -> String {
+        let leaves = self.history.leaves();
+        if leaves.is_empty() {
+            return "Nothing to undo".to_string();
+        }
+
+        let mut list = vec!["number changes  when".to_string()];
+        for seq in leaves {
+            let elapsed = self.history.revisions[seq].time.elapsed().map_or(0, |elapsed| elapsed.as_secs());
+            let when = match elapsed {
+                0..=99 => format!("{} seconds ago", elapsed),
+                100..=5999 => format!("{} minutes ago", elapsed / 60),
+                _ => format!("{} hours ago", elapsed / 3600),
+            };
+            list.push(format!("{:>6} {:>7}  {}", seq, self.history.depth(seq), when));
+        }
+
+        list.join("\n")
+    }
+
+    /// Command line as displayed, with the `:` or search prompt it was opened with.
+    pub(crate) fn command_line(&self) -> String {
+        let prompt = match &self.search.prompt {
+            Some(prompt) if prompt.forward => '/',
+            Some(_) => '?',
+            None => ':',
+        };
+        format!("{}{}", prompt, self.command)
+    }
+
     pub fn run(&mut self) -> Result<(), Report> {
         let (tx, mut rx) = mpsc::channel::<KeyEvent>();
+        let listening = self.listening.clone();
 
         let rt = Runtime::new()?;
         rt.block_on(async {
             tokio::spawn(async move {
-                Editor::key_event_listener(tx).await;
+                Editor::key_event_listener(tx, listening).await;
             });
         });
 
         while !self.stop {
             if let Err(err) = self.handle_key_event(&mut rx) {
-                self.error = Some(err.to_string());
+                // A failing command ends any macro still running.
+                self.typeahead.clear();
+                if !err.to_string().is_empty() {
+                    self.error = Some(err.to_string());
+                }
                 self.dirty = true;
             }
+            self.finish_change();
 
-            if self.dirty {
-                self.display.render(&self.buffer, &self.command, &self.error, &self.mode)?;
+            if self.dirty && self.typeahead.is_empty() {
+                self.render()?;
                 self.dirty = false;
             }
         }
@@ -110,15 +361,73 @@ impl Editor {
             self.dirty = true;
         }
 
-        let event = match rx.try_recv() {
-            Ok(event) => event,
-            Err(_) => return Ok(()),
+        // Only typed keys are recorded, not the ones a macro replays.
+        let (event, remap) = match self.typeahead.pop_front() {
+            Some(queued) => queued,
+            None => {
+                let Ok(event) = rx.try_recv() else {
+                    return Ok(());
+                };
+                if let Some(recording) = &mut self.recording {
+                    recording.keys.push(event);
+                }
+                self.keymap.key_typed();
+                self.record_pending_key(event);
+                (event, true)
+            }
         };
+        self.process_key(event, remap)
+    }
 
-        let mut unresolved = self.keymap.traverse(&self.mode, event)?;
+    /// Adds a key typed in a mode that takes commands to the ones shown in the status line, which start over with the
+    /// first key of each command.
+    fn record_pending_key(&mut self, event: KeyEvent) {
+        if !matches!(self.mode, Mode::NORMAL | Mode::VISUAL | Mode::OPERATOR) {
+            return;
+        }
+        if !self.command_pending() {
+            self.pending_keys.clear();
+        }
+        self.pending_keys.push_str(&key_notation(&event));
+    }
+
+    /// Whether the keys typed so far wait for more to make a command: a count, a partial sequence, an operator
+    /// waiting for its motion, a selected register or an action waiting for its char.
+    pub(crate) fn command_pending(&self) -> bool {
+        !self.keymap.is_empty()
+            || self.keymap.has_count()
+            || self.mode == Mode::OPERATOR
+            || self.selected_register.is_some()
+            || self.char_argument.is_some()
+    }
+
+    /// Name of the mode shown in the status line, telling the kinds of Visual mode apart.
+    pub(crate) fn mode_name(&self) -> &'static str {
+        match self.mode {
+            Mode::NORMAL => "NORMAL",
+            Mode::INSERT => "INSERT",
+            Mode::COMMAND => "COMMAND",
+            Mode::OPERATOR => "O-PENDING",
+            Mode::VISUAL => match self.visual.as_ref().map(|visual| visual.kind) {
+                Some(VisualKind::Line) => "V-LINE",
+                Some(VisualKind::Block) => "V-BLOCK",
+                _ => "VISUAL",
+            },
+        }
+    }
+
+    /// Handles `event` as typed, looking it up among the mappings first if `remap` is set.
+    pub(crate) fn process_key(&mut self, event: KeyEvent, remap: bool) -> Result<(), Report> {
+        self.record_change_key(event);
+
+        if let Some(action) = self.char_argument.take() {
+            return self.handle_char_argument(action, event);
+        }
+
+        let mut unresolved = self.keymap.traverse(&self.mode, event, remap)?;
         if unresolved.is_some() {
             self.execute_keymap_action()?;
-            unresolved = self.keymap.traverse(&self.mode, event)?;
+            unresolved = self.keymap.traverse(&self.mode, event, remap)?;
         }
 
         if self.keymap.is_leaf() {
@@ -131,11 +440,39 @@ impl Editor {
             }
         }
 
+        if self.mode != Mode::INSERT {
+            self.display.cursor_clamp(&self.buffer);
+        }
+
         self.last_key_time = Instant::now();
         self.dirty = true;
         Ok(())
     }
 
+    /// Passes `event` to an action waiting for a char. Any other key cancels it along with a pending operator, or
+    /// ends a substitution waiting for confirmation.
+    fn handle_char_argument(&mut self, action: Box<CharArgumentFn>, event: KeyEvent) -> Result<(), Report> {
+        let result = match event.code {
+            KeyCode::Char(c) if !event.modifiers.intersects(KeyModifiers::ALT | KeyModifiers::CONTROL) => {
+                action(self, c)
+            }
+            _ => {
+                self.cancel_operator();
+                self.finish_substitution()
+            }
+        };
+
+        // An action asking for another char is still running, so its changes are not a complete undo step yet.
+        if self.mode != Mode::INSERT && self.char_argument.is_none() {
+            self.history.end_step();
+            self.display.cursor_clamp(&self.buffer);
+        }
+
+        self.last_key_time = Instant::now();
+        self.dirty = true;
+        result
+    }
+
     fn handle_unresolved_key_event(&mut self, unresolved: KeyEvent) {
         match self.mode {
             Mode::COMMAND => {
@@ -144,60 +481,91 @@ impl Editor {
                 } else if unresolved.code == KeyCode::Backspace {
                     self.command.pop();
                 }
+                self.update_incremental_search();
             }
-            Mode::INSERT => match unresolved.code {
-                KeyCode::Char(c) => {
-                    let (x, y) = self.display.cursor.position;
-                    self.buffer[y as usize].insert(x as usize, c);
-                    self.display.cursor_move_by((1, 0), &self.buffer);
-                }
-                KeyCode::Enter => {
-                    let (x, y) = self.display.cursor.position;
-                    let remaining = self.buffer[y as usize].split_off(x as usize);
-                    self.buffer.insert((y + 1) as usize, remaining);
-                    self.display.cursor_move_by((-(x as i16), 1), &self.buffer)
-                }
-                KeyCode::Delete => {
-                    let (x, y) = self.display.cursor.position;
-                    if x < self.buffer[y as usize].len() as u16 {
-                        self.buffer[y as usize].remove(x as usize);
-                    } else if y + 1 < self.buffer.len() as u16 {
-                        let next_line = self.buffer.remove((y + 1) as usize);
-                        self.buffer[y as usize].push_str(&next_line);
+            Mode::OPERATOR => self.cancel_operator(),
+            Mode::INSERT => {
+                let (x, y) = self.display.cursor.position;
+                let index = self.buffer.line_to_char(y) + x;
+
+                match unresolved.code {
+                    KeyCode::Char(c) => {
+                        self.registers.last_insert.push(c);
+                        self.insert_text(index, c.encode_utf8(&mut [0; 4]));
+                        self.display.cursor_move_by((1, 0), &self.buffer);
                     }
-                }
-                KeyCode::Backspace => {
-                    let (x, y) = self.display.cursor.position;
-                    if x > 0 {
-                        self.buffer[y as usize].remove((x - 1) as usize);
-                        self.display.cursor_move_by((-1, 0), &self.buffer);
-                    } else if y > 0 {
-                        let prev_line_len = self.buffer[(y - 1) as usize].len() as u16;
-                        let current_line = self.buffer.remove(y as usize);
-                        self.buffer[(y - 1) as usize].push_str(&current_line);
-                        self.display.cursor_move_by((prev_line_len as i16, -1), &self.buffer);
+                    KeyCode::Enter => {
+                        self.registers.last_insert.push('\n');
+                        self.insert_text(index, "\n");
+                        self.display.cursor_move_by((-(x as isize), 1), &self.buffer)
+                    }
+                    KeyCode::Delete if index < self.buffer.len_chars() => {
+                        self.remove_text(index..index + 1);
+                    }
+                    KeyCode::Backspace => {
+                        self.registers.last_insert.pop();
+                        if x > 0 {
+                            self.remove_text(index - 1..index);
+                            self.display.cursor_move_by((-1, 0), &self.buffer);
+                        } else if y > 0 {
+                            let prev_line_len = self.buffer.line_len(y - 1);
+                            self.remove_text(index - 1..index);
+                            self.display.cursor_move_by((prev_line_len as isize, -1), &self.buffer);
+                        }
                     }
+                    _ => {}
                 }
-                _ => {}
-            },
+            }
             _ => {}
         }
     }
 
-    fn execute_keymap_action(&mut self) -> Result<(), Report> {
-        if let Some(action) = self.keymap.get_action() {
-            for _ in 0..self.keymap.repeats() {
-                action.borrow_mut()(self)?;
-            }
+    pub(crate) fn execute_keymap_action(&mut self) -> Result<(), Report> {
+        let action = self.keymap.get_action();
+        self.count = self.keymap.count();
+        self.keymap.clear();
+
+        let was_insert = self.mode == Mode::INSERT;
+        let result = match action {
+            Some(action) => action.borrow_mut()(self),
+            None => Ok(()),
         };
 
-        self.keymap.clear();
+        if !was_insert && self.mode == Mode::INSERT {
+            self.registers.last_insert.clear();
+        }
+        // A selected register only applies to the next command, or the operator waiting for its motion.
+        if self.mode != Mode::OPERATOR {
+            self.selected_register = None;
+        }
+
+        // Everything an action changes outside of an insert session becomes a single undo step.
+        if self.mode != Mode::INSERT {
+            self.history.end_step();
+        }
+
+        result
+    }
+
+    /// Hands the terminal over to a shell command: keys stop being read, and the alternate screen and raw mode are
+    /// left until `resume`.
+    pub(crate) fn suspend(&mut self) -> Result<(), Report> {
+        self.listening.store(false, Ordering::SeqCst);
+        // Gives the listener time to notice before the command starts reading, as it only checks between polls.
+        thread::sleep(Duration::from_millis(20));
+        self.display.suspend()
+    }
+
+    pub(crate) fn resume(&mut self) -> Result<(), Report> {
+        self.display.resume()?;
+        self.listening.store(true, Ordering::SeqCst);
+        self.dirty = true;
         Ok(())
     }
 
-    async fn key_event_listener(tx: mpsc::Sender<KeyEvent>) {
+    async fn key_event_listener(tx: mpsc::Sender<KeyEvent>, listening: Arc<AtomicBool>) {
         loop {
-            if !poll(Duration::from_millis(10)).unwrap() {
+            if !listening.load(Ordering::SeqCst) || !poll(Duration::from_millis(10)).unwrap() {
                 tokio::time::sleep(Duration::from_millis(10)).await;
                 continue;
             }
@@ -210,3 +578,38 @@ impl Editor {
         }
     }
 }
+
+#[cfg(test)]
+impl Editor {
+    /// Editor on `text` with the default keybinds and commands, drawing nowhere.
+    pub(crate) fn with_text(text: &str) -> Self {
+        let mut editor = Self::with_display(Display::with_output((80, 24), Box::new(std::io::sink())));
+        crate::macros::default_keybinds(&mut editor);
+        crate::macros::default_commands(&mut editor);
+        editor.buffer = Rope::new(text);
+        editor
+    }
+
+    /// Types `keys`, written as `add_keybind!` takes them, as the key listener would send them, running the keys of
+    /// macros and keeping errors as `run` does.
+    pub(crate) fn type_keys(&mut self, keys: &str) {
+        let (tx, mut rx) = mpsc::channel();
+        for event in crate::util::keymap::parse_keys(keys) {
+            tx.send(event).unwrap();
+            loop {
+                if let Err(err) = self.handle_key_event(&mut rx) {
+                    self.typeahead.clear();
+                    self.error = Some(err.to_string()).filter(|err| !err.is_empty());
+                }
+                self.finish_change();
+                if self.typeahead.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+
+    pub(crate) fn text(&self) -> String {
+        self.buffer.to_string()
+    }
+}
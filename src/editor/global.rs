@@ -0,0 +1,136 @@
+use color_eyre::{eyre::eyre, Report};
+use std::ops::{Range, RangeInclusive};
+
+use crate::editor::mark;
+use crate::editor::search;
+use crate::editor::Editor;
+
+/// Lines matched by a running `:g` that its command has not run on yet, last first. They move with the edits its
+/// command makes as marks do, and are skipped once deleted.
+#[derive(Default)]
+pub(crate) struct GlobalLines {
+    pending: Option<Vec<usize>>,
+}
+
+impl GlobalLines {
+    pub(crate) fn running(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Follows an edit that removed the lines in `removed` and moved every line from `shifted` on by `delta`.
+    pub(crate) fn adjust(&mut self, removed: Range<usize>, shifted: usize, delta: isize) {
+        if let Some(pending) = &mut self.pending {
+            pending.retain_mut(|line| mark::adjust_line(line, &removed, shifted, delta));
+        }
+    }
+}
+
+impl Editor {
+    /// `:[range]g/{pattern}/[cmd]` and `:[range]v/{pattern}/[cmd]`: marks the lines that match `pattern`, or that
+    /// don't when `invert` is set, and then runs the Ex command `cmd` on each of them, `:p` by default. Lines that
+    /// earlier commands delete are skipped, and the whole run is a single undo step.
+    pub(crate) fn global(&mut self, lines: RangeInclusive<usize>, args: &str, invert: bool) -> Result<(), Report> {
+        if self.global_lines.running() {
+            return Err(eyre!("Cannot do :global recursive"));
+        }
+
+        let delimiter = args
+            .chars()
+            .next()
+            .filter(|&c| !c.is_alphanumeric() && !matches!(c, '\\' | '"' | '|'))
+            .ok_or_else(|| eyre!("Regular expression missing from :global"))?;
+        let (pattern, command) = search::split_delimited(&args[delimiter.len_utf8()..], delimiter);
+
+        let pattern = match pattern.is_empty() {
+            true => self.search.pattern.clone().ok_or_else(|| eyre!("No previous regular expression"))?,
+            false => pattern,
+        };
+        let regex = search::compile(&pattern)?;
+        self.search.pattern = Some(pattern.clone());
+
+        let matched = lines.rev().filter(|&line| regex.is_match(&self.buffer.line(line)) != invert).collect::<Vec<_>>();
+        if matched.is_empty() {
+            return Err(match invert {
+                true => eyre!("Pattern found in every line: {}", pattern),
+                false => eyre!("Pattern not found: {}", pattern),
+            });
+        }
+
+        let command = match command.map(str::trim) {
+            Some(command) if !command.is_empty() => command.to_string(),
+            _ => "p".to_string(),
+        };
+
+        // Commands keep running after one fails, as a pattern missing from some of the lines is expected.
+        self.error = None;
+        self.global_lines.pending = Some(matched);
+        let mut result = Ok(());
+        while let Some(line) = self.global_lines.pending.as_mut().and_then(Vec::pop) {
+            self.set_cursor((0, line));
+            if let Err(err) = self.execute_command(&command) {
+                result = result.and(Err(err));
+            }
+        }
+        self.global_lines.pending = None;
+
+        result
+    }
+
+    /// `:[range]p[rint]`: shows `lines`. Under `:g` each line printed is added to the ones before it.
+    pub(crate) fn print_lines(&mut self, lines: RangeInclusive<usize>) {
+        let text = lines.map(|line| self.buffer.line(line)).collect::<Vec<_>>().join("\n");
+        self.error = match self.error.take() {
+            Some(printed) if self.global_lines.running() => Some(format!("{}\n{}", printed, text)),
+            _ => Some(text),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text left after running `:g` with `args` over the whole of `text`.
+    fn global(text: &str, args: &str, invert: bool) -> String {
+        let mut editor = Editor::with_text(text);
+        editor.global(0..=editor.buffer.len_lines() - 1, args, invert).unwrap();
+        editor.text()
+    }
+
+    #[test]
+    fn runs_on_lines_as_they_move() {
+        assert_eq!(global("a\nx1\nx2\nb\nx3", "/x/d", false), "a\nb");
+        assert_eq!(global("a\nx1\nx2\nb\nx3", "/x/d", true), "x1\nx2\nx3");
+        assert_eq!(global("one\ntwo\nthree", "/^/m0", false), "three\ntwo\none");
+
+        // Lines deleted by the command run on an earlier one are skipped.
+        assert_eq!(global("x1\nx2\ny\nx3\nz", "/x/.,+1d", false), "y");
+        assert_eq!(global("x1\ny\nx2", "/x/t.", false), "x1\nx1\ny\nx2\nx2");
+    }
+
+    #[test]
+    fn marks_follow_its_edits() {
+        let mut editor = Editor::with_text("x1\na\nx2\nb");
+        editor.type_keys("3jmb:g/x/d<CR>");
+        assert_eq!(editor.text(), "a\nb");
+        editor.type_keys(":'bd<CR>");
+        assert_eq!(editor.text(), "a");
+    }
+
+    #[test]
+    fn prints_and_undoes_in_one_step() {
+        let mut editor = Editor::with_text("a1\nb\na2");
+        editor.type_keys(":g/a<CR>");
+        assert_eq!(editor.error.as_deref(), Some("a1\na2"));
+
+        editor.type_keys(":g/a/s/a/x<CR>");
+        assert_eq!(editor.text(), "x1\nb\nx2");
+        editor.type_keys("u");
+        assert_eq!(editor.text(), "a1\nb\na2");
+
+        editor.type_keys(":2,3v//d<CR>");
+        assert_eq!(editor.text(), "a1\na2");
+        assert!(editor.global(0..=1, "/z/d", false).is_err());
+        assert!(editor.global(0..=1, "/a/g/a/d", false).is_err());
+    }
+}
@@ -0,0 +1,433 @@
+use color_eyre::Report;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::util::Rope;
+
+/// Bumped whenever the on-disk undo format changes, so stale files are ignored instead of misread.
+const UNDO_FILE_VERSION: u32 = 1;
+
+/// A single reversible edit: `removed` was replaced with `inserted` at char index `position`.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Change {
+    pub(crate) position: usize,
+    pub(crate) removed: String,
+    pub(crate) inserted: String,
+}
+
+impl Change {
+    fn apply(&self, buffer: &mut Rope) {
+        buffer.remove(self.position..self.position + self.removed.chars().count());
+        buffer.insert(self.position, &self.inserted);
+    }
+
+    fn revert(&self, buffer: &mut Rope) {
+        buffer.remove(self.position..self.position + self.inserted.chars().count());
+        buffer.insert(self.position, &self.removed);
+    }
+
+    /// Folds `next` into this change when it continues typing or deleting at the same spot.
+    fn merge(&mut self, next: &Change) -> bool {
+        let end = self.position + self.inserted.chars().count();
+        if next.removed.is_empty() && next.position == end {
+            self.inserted.push_str(&next.inserted);
+            return true;
+        }
+
+        if next.inserted.is_empty() && self.removed.is_empty() && next.position + 1 == end {
+            if let Some(last) = self.inserted.pop() {
+                if next.removed == last.to_string() {
+                    return true;
+                }
+                self.inserted.push(last);
+            }
+        }
+
+        false
+    }
+}
+
+/// A node of the undo tree, holding the change group that leads to it from its parent.
+#[derive(Clone, Serialize, Deserialize)]
+pub(crate) struct Revision {
+    pub(crate) parent: usize,
+    pub(crate) last_child: Option<usize>,
+    pub(crate) changes: Vec<Change>,
+    pub(crate) cursor: (usize, usize),
+    pub(crate) time: SystemTime,
+}
+
+/// Undo tree as stored in the undo file, tagged with the hash of the text it applies to. Revisions are borrowed from
+/// the history when it is written.
+#[derive(Serialize, Deserialize)]
+struct UndoFile<'a> {
+    version: u32,
+    hash: String,
+    current: usize,
+    revisions: Cow<'a, [Revision]>,
+}
+
+/// Undo tree for a buffer. Revision 0 is the original text, and every other revision is numbered by the order in
+/// which it was created, so moving through sequence numbers walks the history chronologically across branches.
+pub(crate) struct History {
+    pub(crate) revisions: Vec<Revision>,
+    pub(crate) current: usize,
+    /// Revision whose text was last read from or written to the file.
+    saved: usize,
+    pending: Vec<Change>,
+    pending_cursor: Option<(usize, usize)>,
+    /// Number of groups open, during which the commands run do not end undo steps of their own.
+    groups: usize,
+}
+
+impl History {
+    pub fn new() -> Self {
+        let root =
+            Revision { parent: 0, last_child: None, changes: Vec::new(), cursor: (0, 0), time: SystemTime::now() };
+        Self { revisions: vec![root], current: 0, saved: 0, pending: Vec::new(), pending_cursor: None, groups: 0 }
+    }
+
+    /// Whether the text differs from the revision that was last read or written, as far as the history tells: undoing
+    /// back to it makes the buffer unmodified again.
+    pub fn modified(&self) -> bool {
+        !self.pending.is_empty() || self.saved != self.current
+    }
+
+    /// Marks the current revision as the one matching the file, after writing it.
+    pub fn mark_saved(&mut self) {
+        self.commit();
+        self.saved = self.current;
+    }
+
+    /// Records `removed` being replaced with `inserted` at `position`, grouping it with the edits made since the last
+    /// commit. `cursor` is the cursor position before the edit and is restored when the group is undone.
+    pub fn record(&mut self, position: usize, removed: String, inserted: String, cursor: (usize, usize)) {
+        let change = Change { position, removed, inserted };
+        self.pending_cursor.get_or_insert(cursor);
+
+        if let Some(last) = self.pending.last_mut() {
+            if last.merge(&change) {
+                return;
+            }
+        }
+
+        self.pending.push(change);
+    }
+
+    /// Closes the current change group, making it a single undo step.
+    pub fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let revision = Revision {
+            parent: self.current,
+            last_child: None,
+            changes: std::mem::take(&mut self.pending),
+            cursor: self.pending_cursor.take().unwrap_or_default(),
+            time: SystemTime::now(),
+        };
+
+        self.revisions.push(revision);
+        let seq = self.revisions.len() - 1;
+        self.revisions[self.current].last_child = Some(seq);
+        self.current = seq;
+    }
+
+    /// Ends the undo step of a command, unless a group is open.
+    pub fn end_step(&mut self) {
+        if self.groups == 0 {
+            self.commit();
+        }
+    }
+
+    /// Makes the commands run until the matching `end_group` part of the undo step of the one running them, as with
+    /// the keys `:normal` executes.
+    pub fn begin_group(&mut self) {
+        self.groups += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        self.groups = self.groups.saturating_sub(1);
+    }
+
+    /// Reverts the current revision, returning the cursor position from before it was made.
+    pub fn undo(&mut self, buffer: &mut Rope) -> Option<(usize, usize)> {
+        self.commit();
+        if self.current == 0 {
+            return None;
+        }
+
+        let revision = &self.revisions[self.current];
+        revision.changes.iter().rev().for_each(|change| change.revert(buffer));
+
+        let (parent, cursor) = (revision.parent, revision.cursor);
+        self.revisions[parent].last_child = Some(self.current);
+        self.current = parent;
+        Some(cursor)
+    }
+
+    /// Reapplies the most recently undone child of the current revision.
+    pub fn redo(&mut self, buffer: &mut Rope) -> Option<(usize, usize)> {
+        self.commit();
+        let child = self.revisions[self.current].last_child?;
+
+        let revision = &self.revisions[child];
+        revision.changes.iter().for_each(|change| change.apply(buffer));
+
+        self.current = child;
+        Some(revision.cursor)
+    }
+
+    /// Moves to revision `seq` along the tree, undoing up to the common ancestor and redoing down to the target.
+    pub fn goto(&mut self, seq: usize, buffer: &mut Rope) -> Option<(usize, usize)> {
+        self.commit();
+        if seq >= self.revisions.len() || seq == self.current {
+            return None;
+        }
+
+        let mut path = vec![seq];
+        while *path.last().unwrap() != 0 {
+            path.push(self.revisions[*path.last().unwrap()].parent);
+        }
+
+        let mut cursor = None;
+        while !path.contains(&self.current) {
+            cursor = self.undo(buffer);
+        }
+
+        let ancestor = path.iter().position(|&rev| rev == self.current).unwrap();
+        for &rev in path[..ancestor].iter().rev() {
+            self.revisions[self.current].last_child = Some(rev);
+            cursor = self.redo(buffer);
+        }
+
+        cursor
+    }
+
+    /// Number of change groups between the root and `seq`.
+    pub fn depth(&self, mut seq: usize) -> usize {
+        let mut depth = 0;
+        while seq != 0 {
+            seq = self.revisions[seq].parent;
+            depth += 1;
+        }
+        depth
+    }
+
+    /// Revisions that have no children, i.e. the tips of every undo branch.
+    pub fn leaves(&self) -> Vec<usize> {
+        let mut has_children = vec![false; self.revisions.len()];
+        self.revisions.iter().skip(1).for_each(|revision| has_children[revision.parent] = true);
+        (1..self.revisions.len()).filter(|&seq| !has_children[seq]).collect()
+    }
+
+    /// Writes the committed history to `path`, tagged with `hash` so it is only restored onto the same text.
+    pub fn save(&mut self, path: &Path, hash: &str) -> Result<(), Report> {
+        self.commit();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let undo_file = UndoFile {
+            version: UNDO_FILE_VERSION,
+            hash: hash.to_string(),
+            current: self.current,
+            revisions: Cow::Borrowed(&self.revisions),
+        };
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &undo_file)?;
+        Ok(())
+    }
+
+    /// Reads the history stored at `path`, returning `None` when there is none, it was written for other text, or
+    /// its revisions do not make a tree that can be walked.
+    pub fn load(path: &Path, hash: &str) -> Result<Option<Self>, Report> {
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let undo_file: UndoFile = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        let len = undo_file.revisions.len();
+        // Every revision but the root comes after its parent, and the children redone into exist.
+        let valid_tree = undo_file.revisions.iter().enumerate().all(|(seq, revision)| {
+            (seq == 0 || revision.parent < seq) && revision.last_child.is_none_or(|child| child < len)
+        });
+        if undo_file.version != UNDO_FILE_VERSION || undo_file.hash != hash || undo_file.current >= len || !valid_tree {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            revisions: undo_file.revisions.into_owned(),
+            current: undo_file.current,
+            saved: undo_file.current,
+            pending: Vec::new(),
+            pending_cursor: None,
+            groups: 0,
+        }))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// SHA-256 of the buffer contents, identifying the text an undo history belongs to.
+pub(crate) fn content_hash(buffer: &Rope) -> String {
+    let mut hasher = Sha256::new();
+    buffer.chunks().for_each(|chunk| hasher.update(chunk.as_bytes()));
+    hex(&hasher.finalize())
+}
+
+/// Location of the undo file for `filename`, keyed by the hash of its absolute path under the user cache directory.
+pub(crate) fn undo_file_path(filename: &str) -> Option<PathBuf> {
+    let cache = std::env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+
+    let path = std::path::absolute(filename).ok()?;
+    let key = hex(&Sha256::digest(path.to_string_lossy().as_bytes()));
+    Some(cache.join("text-editor").join("undo").join(format!("{}.json", key)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Inserts `text` at `position` in `buffer` and records it in `history` as an undo step of its own.
+    fn insert(history: &mut History, buffer: &mut Rope, position: usize, text: &str) {
+        history.record(position, String::new(), text.to_string(), (0, 0));
+        buffer.insert(position, text);
+        history.commit();
+    }
+
+    /// Path in the temporary directory for an undo file of test `name`, removed if left over from an earlier run.
+    fn undo_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("text-editor-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn undo_redo_and_branches() {
+        let (mut history, mut buffer) = (History::new(), Rope::new("abc"));
+        insert(&mut history, &mut buffer, 3, "x");
+        insert(&mut history, &mut buffer, 4, "y");
+        assert_eq!(buffer.to_string(), "abcxy");
+
+        history.undo(&mut buffer);
+        history.undo(&mut buffer);
+        assert_eq!(buffer.to_string(), "abc");
+        assert!(history.undo(&mut buffer).is_none());
+
+        // A change after undoing starts a new branch, and redo follows the branch undone last.
+        insert(&mut history, &mut buffer, 0, "z");
+        assert_eq!(buffer.to_string(), "zabc");
+        history.undo(&mut buffer);
+        history.redo(&mut buffer);
+        assert_eq!(buffer.to_string(), "zabc");
+        assert!(history.redo(&mut buffer).is_none());
+
+        // `g-` and `g+` move by sequence number, across branches.
+        history.goto(2, &mut buffer);
+        assert_eq!(buffer.to_string(), "abcxy");
+        history.goto(1, &mut buffer);
+        assert_eq!(buffer.to_string(), "abcx");
+        history.goto(3, &mut buffer);
+        assert_eq!(buffer.to_string(), "zabc");
+        history.goto(0, &mut buffer);
+        assert_eq!(buffer.to_string(), "abc");
+
+        assert_eq!(history.leaves(), vec![2, 3]);
+        assert_eq!((history.depth(2), history.depth(3)), (2, 1));
+    }
+
+    #[test]
+    fn typing_merges_into_one_step() {
+        let (mut history, mut buffer) = (History::new(), Rope::new(""));
+        for (i, c) in "abc".chars().enumerate() {
+            history.record(i, String::new(), c.to_string(), (i, 0));
+            buffer.insert(i, &c.to_string());
+        }
+        history.commit();
+
+        assert_eq!(history.undo(&mut buffer), Some((0, 0)));
+        assert_eq!(buffer.to_string(), "");
+        assert_eq!(history.revisions.len(), 2);
+    }
+
+    #[test]
+    fn modified_against_saved_revision() {
+        let (mut history, mut buffer) = (History::new(), Rope::new("abc"));
+        assert!(!history.modified());
+
+        history.record(0, String::new(), "x".to_string(), (0, 0));
+        buffer.insert(0, "x");
+        assert!(history.modified());
+        history.mark_saved();
+        assert!(!history.modified());
+
+        insert(&mut history, &mut buffer, 0, "y");
+        assert!(history.modified());
+        history.undo(&mut buffer);
+        assert!(!history.modified());
+        history.undo(&mut buffer);
+        assert!(history.modified());
+        history.redo(&mut buffer);
+        assert!(!history.modified());
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let path = undo_file("round-trip");
+        let (mut history, mut buffer) = (History::new(), Rope::new("abc"));
+        insert(&mut history, &mut buffer, 3, "x");
+        insert(&mut history, &mut buffer, 0, "y");
+        history.undo(&mut buffer);
+
+        let hash = content_hash(&buffer);
+        history.save(&path, &hash).unwrap();
+        let mut loaded = History::load(&path, &hash).unwrap().unwrap();
+        assert_eq!((loaded.current, loaded.revisions.len()), (1, 3));
+        assert!(!loaded.modified());
+
+        loaded.redo(&mut buffer);
+        assert_eq!(buffer.to_string(), "yabcx");
+        loaded.goto(0, &mut buffer);
+        assert_eq!(buffer.to_string(), "abc");
+
+        // Other text, a missing file and a tree that cannot be walked are all ignored.
+        assert!(History::load(&path, &content_hash(&Rope::new("other"))).unwrap().is_none());
+        assert!(History::load(&undo_file("missing"), &hash).unwrap().is_none());
+
+        let mut json: serde_json::Value = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        json["revisions"][1]["parent"] = 5.into();
+        fs::write(&path, json.to_string()).unwrap();
+        assert!(History::load(&path, &hash).unwrap().is_none());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn failed_save_keeps_revisions() {
+        let blocker = undo_file("blocker");
+        fs::write(&blocker, "").unwrap();
+
+        let (mut history, mut buffer) = (History::new(), Rope::new("abc"));
+        insert(&mut history, &mut buffer, 3, "x");
+        assert!(history.save(&blocker.join("undo.json"), "hash").is_err());
+
+        insert(&mut history, &mut buffer, 4, "y");
+        history.undo(&mut buffer);
+        history.undo(&mut buffer);
+        assert_eq!(buffer.to_string(), "abc");
+
+        fs::remove_file(&blocker).unwrap();
+    }
+}
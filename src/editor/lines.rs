@@ -0,0 +1,197 @@
+use color_eyre::{eyre::eyre, Report};
+use std::cmp::Ordering;
+use std::fs;
+use std::ops::{Range, RangeInclusive};
+
+use crate::editor::motion;
+use crate::editor::operator::{Operator, RangeKind};
+use crate::editor::Editor;
+
+impl Editor {
+    /// Chars of `lines`, from the start of the first to the end of the last, without its line break.
+    pub(crate) fn line_range(&self, lines: &RangeInclusive<usize>) -> Range<usize> {
+        self.buffer.line_to_char(*lines.start())
+            ..self.buffer.line_to_char(*lines.end()) + self.buffer.line_len(*lines.end())
+    }
+
+    /// `:[range]d [x]` and `:[range]y [x]`: deletes or yanks `lines` into the register named by `args`, or the
+    /// unnamed one.
+    pub(crate) fn delete_lines(
+        &mut self,
+        lines: RangeInclusive<usize>,
+        args: &str,
+        delete: bool,
+    ) -> Result<(), Report> {
+        if let Some(name) = args.chars().next() {
+            self.select_register(name)?;
+        }
+
+        let operator = if delete { Operator::Delete } else { Operator::Yank };
+        self.apply_operator(operator, self.line_range(&lines), RangeKind::Linewise)
+    }
+
+    /// `:[range]m {address}`: moves `lines` below line `target`, which counts from 1 and is 0 for the top of the
+    /// buffer.
+    pub(crate) fn move_lines(&mut self, lines: RangeInclusive<usize>, target: usize) -> Result<(), Report> {
+        let (first, last) = (*lines.start(), *lines.end());
+        if target > first && target <= last {
+            return Err(eyre!("Cannot move a range of lines into itself"));
+        }
+
+        if target != first && target != last + 1 {
+            let text = self.buffer.slice(self.line_range(&lines));
+            self.remove_lines(first, last);
+            let target = if target > last { target - (last - first + 1) } else { target };
+            self.put_lines(target, &text);
+        }
+
+        let line = if target > last { target - 1 } else { target + last - first };
+        self.set_cursor((motion::first_non_blank_column(&self.buffer, line), line));
+        Ok(())
+    }
+
+    /// `:[range]t {address}` and `:[range]co {address}`: copies `lines` below line `target`, counted as for `:m`.
+    pub(crate) fn copy_lines(&mut self, lines: RangeInclusive<usize>, target: usize) -> Result<(), Report> {
+        let text = self.buffer.slice(self.line_range(&lines));
+        self.put_lines(target, &text);
+
+        let line = target + lines.end() - lines.start();
+        self.set_cursor((motion::first_non_blank_column(&self.buffer, line), line));
+        Ok(())
+    }
+
+    /// Inserts `text` as whole lines below line `target`, counting from 1, or above the first line when it is 0.
+    pub(crate) fn put_lines(&mut self, target: usize, text: &str) {
+        match target {
+            0 => self.insert_text(0, &format!("{}\n", text)),
+            _ => {
+                let line = target - 1;
+                let end = self.buffer.line_to_char(line) + self.buffer.line_len(line);
+                self.insert_text(end, &format!("\n{}", text));
+            }
+        }
+    }
+
+    /// `:[range]w[!] [file]`: writes the buffer, or just `lines` when they are not all of it. Writing part of the
+    /// buffer over its own file needs `!`.
+    pub(crate) fn write_lines(
+        &mut self,
+        lines: RangeInclusive<usize>,
+        filename: Option<&str>,
+        force: bool,
+    ) -> Result<(), Report> {
+        let target = filename.map(str::to_string).or(self.filename.clone());
+        let target = target.ok_or_else(|| eyre!("No filename specified"))?;
+
+        if lines == (0..=self.buffer.len_lines() - 1) {
+            return self.save_file(&target);
+        }
+        if filename.is_none() && !force {
+            return Err(eyre!("Use ! to write partial buffer"));
+        }
+
+        fs::write(&target, self.line_ending.apply(&self.buffer.slice(self.line_range(&lines))))?;
+        Ok(())
+    }
+
+    /// `:[range]sor[t][!] [i][n][u]`: sorts `lines`, in reverse with `!`. With `i` case is ignored, with `n` lines
+    /// are sorted by the first number in them, after the lines without one, and with `u` only the first of equal
+    /// lines is kept.
+    pub(crate) fn sort_lines(&mut self, lines: RangeInclusive<usize>, reverse: bool, args: &str) -> Result<(), Report> {
+        if let Some(flag) = args.chars().find(|c| !"inu ".contains(*c)) {
+            return Err(eyre!("Invalid argument: {}", flag));
+        }
+        let (ignore_case, numeric, unique) = (args.contains('i'), args.contains('n'), args.contains('u'));
+
+        let key = |line: &String| -> (Option<i64>, String) {
+            let number = numeric.then(|| first_number(line)).flatten();
+            let text = if ignore_case { line.to_lowercase() } else { line.clone() };
+            (number, if numeric { String::new() } else { text })
+        };
+        let compare = |a: &String, b: &String| -> Ordering { key(a).cmp(&key(b)) };
+
+        let mut sorted = lines.clone().map(|line| self.buffer.line(line)).collect::<Vec<_>>();
+        sorted.sort_by(compare);
+        if unique {
+            sorted.dedup_by(|a, b| compare(a, b) == Ordering::Equal);
+        }
+        if reverse {
+            sorted.reverse();
+        }
+
+        let range = self.line_range(&lines);
+        let text = sorted.join("\n");
+        if text != self.buffer.slice(range.clone()) {
+            self.replace_text(range, &text);
+        }
+
+        let first = *lines.start();
+        self.set_cursor((motion::first_non_blank_column(&self.buffer, first), first));
+        Ok(())
+    }
+}
+
+/// First decimal number in `line`, with a `-` right before it making it negative.
+fn first_number(line: &str) -> Option<i64> {
+    let start = line.find(|c: char| c.is_ascii_digit())?;
+    let digits = line[start..].chars().take_while(char::is_ascii_digit).count();
+    let number = line[start..start + digits].parse::<i64>().ok()?;
+    Some(if line[..start].ends_with('-') { -number } else { number })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    const TEXT: &str = "one\ntwo\nthree\nfour";
+
+    #[test]
+    fn deletes_and_yanks() {
+        assert_eq!(typed(TEXT, ":2,3d<CR>").text(), "one\nfour");
+        assert_eq!(typed(TEXT, ":3<CR>:d<CR>").text(), "one\ntwo\nfour");
+        assert_eq!(typed(TEXT, ":%d<CR>").text(), "");
+        assert_eq!(typed(TEXT, ":2y<CR>Gp").text(), "one\ntwo\nthree\nfour\ntwo");
+        assert_eq!(typed(TEXT, ":1,2d a<CR>G\"ap").text(), "three\nfour\none\ntwo");
+    }
+
+    #[test]
+    fn moves_and_copies() {
+        assert_eq!(typed(TEXT, ":1m$<CR>").text(), "two\nthree\nfour\none");
+        assert_eq!(typed(TEXT, ":3,4m0<CR>").text(), "three\nfour\none\ntwo");
+        assert_eq!(typed(TEXT, ":1,2m3<CR>").text(), "three\none\ntwo\nfour");
+        assert_eq!(typed(TEXT, ":4t0<CR>").text(), "four\none\ntwo\nthree\nfour");
+        assert_eq!(typed(TEXT, ":1,2co$<CR>").text(), "one\ntwo\nthree\nfour\none\ntwo");
+        assert!(typed(TEXT, ":1m9<CR>").error.is_some());
+    }
+
+    #[test]
+    fn shifts_and_joins() {
+        assert_eq!(typed(TEXT, ":2,3><CR>").text(), "one\n    two\n    three\nfour");
+        assert_eq!(typed(TEXT, ":2>><CR>:2<lt><CR>").text(), "one\n    two\nthree\nfour");
+        assert_eq!(typed(TEXT, ":%j<CR>").text(), "one two three four");
+        assert_eq!(typed(TEXT, ":3j<CR>").text(), "one\ntwo\nthree four");
+    }
+
+    #[test]
+    fn sorts() {
+        assert_eq!(typed(TEXT, ":sort<CR>").text(), "four\none\nthree\ntwo");
+        assert_eq!(typed(TEXT, ":2,$sort!<CR>").text(), "one\ntwo\nthree\nfour");
+        assert_eq!(typed("b\nA\na\nB", ":sort i<CR>").text(), "A\na\nb\nB");
+        assert_eq!(typed("b\nA\na\nB", ":sort iu<CR>").text(), "A\nb");
+        assert_eq!(typed("x10\ny-2\nz\nw3", ":sort n<CR>").text(), "z\ny-2\nw3\nx10");
+        assert!(typed(TEXT, ":sort x<CR>").error.is_some());
+    }
+
+    #[test]
+    fn marks_follow_edits() {
+        // The mark stays on its line when lines above it are deleted, and goes with it.
+        assert_eq!(typed(TEXT, "jjjmagg:1d<CR>:'ad<CR>").text(), "two\nthree");
+        assert!(typed(TEXT, "jjjmagg:4d<CR>:'ad<CR>").error.is_some());
+    }
+}
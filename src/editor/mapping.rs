@@ -0,0 +1,72 @@
+use color_eyre::Report;
+
+use crate::editor::Editor;
+
+impl Editor {
+    /// `:map {lhs} {rhs}` and its variants: maps `lhs` to `rhs` in the modes listed in `modes`, or lists the
+    /// mappings of those modes, starting with `args` when given alone.
+    pub(crate) fn map_keys(&mut self, modes: &str, args: &str, noremap: bool) -> Result<(), Report> {
+        let (lhs, rhs) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+        let rhs = rhs.trim_start();
+        if !rhs.is_empty() {
+            self.keymap.add_mapping(modes, lhs, rhs, noremap);
+            return Ok(());
+        }
+
+        let mappings = self
+            .keymap
+            .mappings()
+            .filter(|mapping| mapping.lhs.starts_with(lhs) && mapping.modes.chars().any(|c| modes.contains(c)))
+            .map(|mapping| {
+                let noremap = if mapping.noremap { "*" } else { " " };
+                format!("{:<4} {:<12} {}{}", mapping.modes, mapping.lhs, noremap, mapping.rhs)
+            })
+            .collect::<Vec<_>>();
+
+        self.error = Some(match mappings.is_empty() {
+            true => "No mapping found".to_string(),
+            false => mappings.join("\n"),
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn maps_keys_in_their_modes() {
+        assert_eq!(typed("a b c", ":nmap Q dw<CR>Q").text(), "b c");
+        assert_eq!(typed("a b c", ":nmap Q dw<CR>2Q").text(), "c");
+        assert_eq!(typed("a b c", ":nmap Q dw<CR>vQ").text(), "a b c");
+        assert_eq!(typed("a b c", ":omap Q w<CR>dQ").text(), "b c");
+        assert_eq!(typed("a b c", ":imap jk <lt>Esc><CR>ixjkx").text(), "xa b c");
+        assert_eq!(typed("a b c", ":map! jk <lt>Esc><CR>ixjk0dw").text(), "b c");
+        // A later mapping of the same keys replaces the earlier one.
+        assert_eq!(typed("a b c", ":nmap Q dw<CR>:nmap Q dl<CR>Q").text(), " b c");
+    }
+
+    #[test]
+    fn noremap_ignores_other_mappings() {
+        let text = "a b\nc\nd\ne";
+        assert_eq!(typed(text, ":nmap J dw<CR>:nmap Q J<CR>Q").text(), "b\nc\nd\ne");
+        assert_eq!(typed(text, ":nmap J dw<CR>:nnoremap Q J<CR>Q").text(), "a b c\nd\ne");
+        // A mapping can start with the keys it maps when it does not remap them.
+        assert_eq!(typed(text, ":nnoremap J JJ<CR>J").text(), "a b c d\ne");
+        assert_eq!(typed(text, ":nmap J JJ<CR>J").error.as_deref(), Some("Recursive mapping"));
+    }
+
+    #[test]
+    fn lists_mappings() {
+        let editor = typed("", ":nmap Q dw<CR>:vnoremap Qa y<CR>:nmap W w<CR>:map Q<CR>");
+        assert_eq!(editor.error.as_deref(), Some("n    Q             dw\nv    Qa           *y"));
+        assert_eq!(typed("", ":imap Q dw<CR>:nmap<CR>").error.as_deref(), Some("No mapping found"));
+    }
+}
@@ -0,0 +1,75 @@
+use color_eyre::{eyre::eyre, Report};
+use std::collections::HashMap;
+use std::ops::Range;
+
+use crate::editor::motion::{Motion, Position};
+use crate::editor::Editor;
+
+/// Positions set with `m{a-zA-Z}`. Marks move with their line as lines are added or removed above it, and go away
+/// with it when it is deleted.
+#[derive(Default)]
+pub(crate) struct Marks {
+    marks: HashMap<char, Position>,
+}
+
+/// Moves `line` to follow an edit that removed the lines in `removed` and moved every line from `shifted` on by
+/// `delta`. False when `line` was removed.
+pub(crate) fn adjust_line(line: &mut usize, removed: &Range<usize>, shifted: usize, delta: isize) -> bool {
+    if removed.contains(line) {
+        return false;
+    }
+    if *line >= shifted {
+        *line = line.saturating_add_signed(delta);
+    }
+    true
+}
+
+impl Marks {
+    /// Follows an edit that removed the lines in `removed` and moved every line from `shifted` on by `delta`.
+    pub(crate) fn adjust(&mut self, removed: Range<usize>, shifted: usize, delta: isize) {
+        self.marks.retain(|_, (_, line)| adjust_line(line, &removed, shifted, delta));
+    }
+}
+
+impl Editor {
+    /// Moves the marks and the lines `:g` is yet to visit to follow an edit that removed the lines in `removed` and
+    /// moved every line from `shifted` on by `delta`.
+    pub(crate) fn adjust_lines(&mut self, removed: Range<usize>, shifted: usize, delta: isize) {
+        self.marks.adjust(removed.clone(), shifted, delta);
+        self.global_lines.adjust(removed, shifted, delta);
+    }
+
+    /// `m{mark}`: remembers the cursor position under `mark`.
+    pub(crate) fn set_mark(&mut self, mark: char) -> Result<(), Report> {
+        if !mark.is_ascii_alphabetic() {
+            return Err(eyre!("Invalid mark name: {}", mark));
+        }
+
+        self.marks.marks.insert(mark, self.display.cursor.position);
+        Ok(())
+    }
+
+    /// Position of `mark`, including `<` and `>` for the start and end of the last Visual selection.
+    pub(crate) fn mark(&self, mark: char) -> Result<Position, Report> {
+        let position = match mark {
+            '<' => self.last_visual.map(|area| area.ordered().0),
+            '>' => self.last_visual.map(|area| area.ordered().1),
+            _ => self.marks.marks.get(&mark).copied(),
+        };
+
+        let (x, y) = position.ok_or_else(|| eyre!("Mark not set"))?;
+        if y >= self.buffer.len_lines() {
+            return Err(eyre!("Mark has invalid line number"));
+        }
+        Ok((x.min(self.buffer.line_len(y)), y))
+    }
+
+    /// `'{mark}` and `` `{mark} ``: moves to the line of `mark` linewise, or to its exact position exclusively.
+    pub(crate) fn jump_to_mark(&mut self, mark: char, exact: bool) -> Result<(), Report> {
+        let (x, y) = self.mark(mark)?;
+        match exact {
+            true => self.motion(|_, _, _| Motion::exclusive((x, y))),
+            false => self.motion(|_, _, _| Motion::linewise(y)),
+        }
+    }
+}
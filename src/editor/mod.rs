@@ -1,4 +1,26 @@
+mod address;
+mod buffers;
+mod command;
+#[allow(clippy::module_inception)]
 mod editor;
+mod global;
+mod history;
+pub(crate) mod lines;
+mod mapping;
+pub(crate) mod mark;
+pub(crate) mod motion;
+mod normal;
+pub(crate) mod operator;
+pub(crate) mod recording;
+pub(crate) mod register;
+pub(crate) mod repeat;
+pub(crate) mod search;
+mod shell;
+pub(crate) mod substitute;
+pub(crate) mod tabs;
+pub(crate) mod text_object;
+pub(crate) mod visual;
+pub(crate) mod window;
 
 pub(crate) use self::editor::Mode;
 
@@ -0,0 +1,566 @@
+use crate::util::Rope;
+
+/// Cursor position as `(column, line)`, both in chars.
+pub(crate) type Position = (usize, usize);
+
+/// How an operator treats the text between the cursor and the target of a motion.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MotionKind {
+    /// Charwise, excluding the char under the target.
+    Exclusive,
+    /// Charwise, including the char under the target.
+    Inclusive,
+    /// Every line between the cursor and the target. The cursor keeps its column when moved.
+    Linewise,
+}
+
+pub(crate) struct Motion {
+    pub(crate) target: Position,
+    pub(crate) kind: MotionKind,
+}
+
+impl Motion {
+    pub(crate) fn exclusive(target: Position) -> Option<Self> {
+        Some(Self { target, kind: MotionKind::Exclusive })
+    }
+
+    pub(crate) fn inclusive(target: Position) -> Option<Self> {
+        Some(Self { target, kind: MotionKind::Inclusive })
+    }
+
+    pub(crate) fn linewise(line: usize) -> Option<Self> {
+        Some(Self { target: (0, line), kind: MotionKind::Linewise })
+    }
+}
+
+pub(crate) fn to_char(buffer: &Rope, (x, y): Position) -> usize {
+    buffer.line_to_char(y) + x
+}
+
+pub(crate) fn to_position(buffer: &Rope, char_idx: usize) -> Position {
+    let y = buffer.char_to_line(char_idx);
+    (char_idx - buffer.line_to_char(y), y)
+}
+
+/// Column of the first non-whitespace char of `line`, or its length when it is blank.
+pub(crate) fn first_non_blank_column(buffer: &Rope, line: usize) -> usize {
+    let text = buffer.line(line);
+    text.chars().position(|c| !c.is_whitespace()).unwrap_or(text.chars().count())
+}
+
+fn last_line_index(buffer: &Rope) -> usize {
+    buffer.len_lines() - 1
+}
+
+/// `h`
+pub(crate) fn left(_buffer: &Rope, (x, y): Position, count: Option<usize>) -> Option<Motion> {
+    Motion::exclusive((x.checked_sub(1)?.saturating_sub(count.unwrap_or(1) - 1), y))
+}
+
+/// `l`
+pub(crate) fn right(buffer: &Rope, (x, y): Position, count: Option<usize>) -> Option<Motion> {
+    Motion::exclusive((x.saturating_add(count.unwrap_or(1)).min(buffer.line_len(y)), y))
+}
+
+/// `k`
+pub(crate) fn up(_buffer: &Rope, (_x, y): Position, count: Option<usize>) -> Option<Motion> {
+    Motion::linewise(y.checked_sub(1)?.saturating_sub(count.unwrap_or(1) - 1))
+}
+
+/// `j`
+pub(crate) fn down(buffer: &Rope, (_x, y): Position, count: Option<usize>) -> Option<Motion> {
+    match y < last_line_index(buffer) {
+        true => Motion::linewise(y.saturating_add(count.unwrap_or(1)).min(last_line_index(buffer))),
+        false => None,
+    }
+}
+
+/// `$`, moving `count - 1` lines down first.
+pub(crate) fn line_end(buffer: &Rope, (_x, y): Position, count: Option<usize>) -> Option<Motion> {
+    let y = y.saturating_add(count.unwrap_or(1) - 1).min(last_line_index(buffer));
+    Motion::inclusive((buffer.line_len(y).saturating_sub(1), y))
+}
+
+/// `_`
+pub(crate) fn first_non_blank(buffer: &Rope, (_x, y): Position, _count: Option<usize>) -> Option<Motion> {
+    Motion::exclusive((first_non_blank_column(buffer, y), y))
+}
+
+/// `gg`, going to line `count` when one is given.
+pub(crate) fn first_line(buffer: &Rope, _position: Position, count: Option<usize>) -> Option<Motion> {
+    Motion::linewise(count.map_or(0, |count| count.saturating_sub(1).min(last_line_index(buffer))))
+}
+
+/// `G`, going to line `count` when one is given.
+pub(crate) fn last_line(buffer: &Rope, _position: Position, count: Option<usize>) -> Option<Motion> {
+    Motion::linewise(
+        count.map_or(last_line_index(buffer), |count| count.saturating_sub(1).min(last_line_index(buffer))),
+    )
+}
+
+/// Target of the doubled operator forms (`dd`, `yy`, `>>`): `count` lines starting at the cursor.
+pub(crate) fn current_line(buffer: &Rope, (_x, y): Position, count: Option<usize>) -> Option<Motion> {
+    Motion::linewise(y.saturating_add(count.unwrap_or(1) - 1).min(last_line_index(buffer)))
+}
+
+/// `0`
+pub(crate) fn line_start(_buffer: &Rope, (_x, y): Position, _count: Option<usize>) -> Option<Motion> {
+    Motion::exclusive((0, y))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CharClass {
+    Blank,
+    Punctuation,
+    Word,
+}
+
+/// Class of `c` for word motions. WORD motions only distinguish blank from non-blank.
+pub(crate) fn char_class(c: char, big: bool) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Blank
+    } else if big || c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+fn class_at(buffer: &Rope, char_idx: usize, big: bool) -> CharClass {
+    buffer.char(char_idx).map_or(CharClass::Blank, |c| char_class(c, big))
+}
+
+/// Whether `char_idx` is the newline of an empty line, which word motions treat as a word of its own.
+fn is_empty_line(buffer: &Rope, char_idx: usize) -> bool {
+    buffer.char(char_idx) == Some('\n') && (char_idx == 0 || buffer.char(char_idx - 1) == Some('\n'))
+}
+
+fn next_word_start(buffer: &Rope, mut i: usize, big: bool) -> usize {
+    let len = buffer.len_chars();
+    let class = class_at(buffer, i, big);
+    if class != CharClass::Blank {
+        while i < len && class_at(buffer, i, big) == class {
+            i += 1;
+        }
+    }
+
+    while i < len && class_at(buffer, i, big) == CharClass::Blank {
+        if buffer.char(i) == Some('\n') && is_empty_line(buffer, i + 1) {
+            return i + 1;
+        }
+        i += 1;
+    }
+
+    i
+}
+
+fn prev_word_start(buffer: &Rope, i: usize, big: bool) -> usize {
+    if i == 0 {
+        return 0;
+    }
+
+    let mut i = i - 1;
+    while i > 0 && class_at(buffer, i, big) == CharClass::Blank {
+        if is_empty_line(buffer, i) {
+            return i;
+        }
+        i -= 1;
+    }
+
+    let class = class_at(buffer, i, big);
+    while i > 0 && class_at(buffer, i - 1, big) == class {
+        i -= 1;
+    }
+
+    i
+}
+
+fn next_word_end(buffer: &Rope, i: usize, big: bool) -> usize {
+    let last = buffer.len_chars().saturating_sub(1);
+    let mut i = (i + 1).min(last);
+    while i < last && class_at(buffer, i, big) == CharClass::Blank {
+        i += 1;
+    }
+
+    let class = class_at(buffer, i, big);
+    while i < last && class_at(buffer, i + 1, big) == class {
+        i += 1;
+    }
+
+    i
+}
+
+fn prev_word_end(buffer: &Rope, mut i: usize, big: bool) -> usize {
+    let class = class_at(buffer, i, big);
+    if class != CharClass::Blank {
+        while i > 0 && class_at(buffer, i, big) == class {
+            i -= 1;
+        }
+    }
+
+    while i > 0 && class_at(buffer, i, big) == CharClass::Blank {
+        if is_empty_line(buffer, i) {
+            return i;
+        }
+        i -= 1;
+    }
+
+    i
+}
+
+/// `to`, unless it is where the cursor already is once kept on the last char of the buffer. Motions that cannot move
+/// fail, which ends a running macro.
+fn moved(buffer: &Rope, from: Position, to: Position) -> Option<Position> {
+    let last = buffer.len_chars().saturating_sub(1);
+    match to_char(buffer, to).min(last) == to_char(buffer, from).min(last) {
+        true => None,
+        false => Some(to),
+    }
+}
+
+/// Applies `step` to the cursor `count` times, working on char indices, and stops early once it no longer moves.
+fn repeat_step(
+    buffer: &Rope,
+    position: Position,
+    count: Option<usize>,
+    step: impl Fn(usize) -> usize,
+) -> Option<Position> {
+    let count = count.unwrap_or(1);
+    let mut i = to_char(buffer, position);
+    for _ in 0..count {
+        match step(i) {
+            next if next == i => break,
+            next => i = next,
+        }
+    }
+
+    let target = to_position(buffer, i);
+    match count {
+        0 => Some(target),
+        _ => moved(buffer, position, target),
+    }
+}
+
+/// `w`, or `W` when `big` is set.
+pub(crate) fn word_forward(buffer: &Rope, position: Position, count: Option<usize>, big: bool) -> Option<Motion> {
+    Motion::exclusive(repeat_step(buffer, position, count, |i| next_word_start(buffer, i, big))?)
+}
+
+/// `w` after an operator: the last word moved over ends at the end of its line instead of taking the line break
+/// and the indent of the next line with it.
+pub(crate) fn word_forward_operator(
+    buffer: &Rope,
+    position: Position,
+    count: Option<usize>,
+    big: bool,
+) -> Option<Motion> {
+    let count = count.unwrap_or(1);
+    let (x, y) = repeat_step(buffer, position, Some(count - 1), |i| next_word_start(buffer, i, big))?;
+    let (target_x, target_y) = to_position(buffer, next_word_start(buffer, to_char(buffer, (x, y)), big));
+
+    if target_y > y && buffer.line_len(y) > 0 {
+        Motion::exclusive((buffer.line_len(y), y))
+    } else {
+        Motion::exclusive((target_x, target_y))
+    }
+}
+
+/// `cw` on a non-blank: like `e`, except that the word under the cursor counts even when the cursor is on its last
+/// char.
+pub(crate) fn change_word(buffer: &Rope, position: Position, count: Option<usize>, big: bool) -> Option<Motion> {
+    let start = to_char(buffer, position);
+    let mut end = start;
+    for n in 0..count.unwrap_or(1) {
+        if n > 0 || class_at(buffer, start + 1, big) == class_at(buffer, start, big) {
+            end = next_word_end(buffer, end, big);
+        }
+    }
+
+    Motion::inclusive(to_position(buffer, end))
+}
+
+/// `b`, or `B` when `big` is set.
+pub(crate) fn word_backward(buffer: &Rope, position: Position, count: Option<usize>, big: bool) -> Option<Motion> {
+    Motion::exclusive(repeat_step(buffer, position, count, |i| prev_word_start(buffer, i, big))?)
+}
+
+/// `e`, or `E` when `big` is set.
+pub(crate) fn word_end(buffer: &Rope, position: Position, count: Option<usize>, big: bool) -> Option<Motion> {
+    Motion::inclusive(repeat_step(buffer, position, count, |i| next_word_end(buffer, i, big))?)
+}
+
+/// `ge`, or `gE` when `big` is set.
+pub(crate) fn word_end_backward(buffer: &Rope, position: Position, count: Option<usize>, big: bool) -> Option<Motion> {
+    Motion::inclusive(repeat_step(buffer, position, count, |i| prev_word_end(buffer, i, big))?)
+}
+
+/// `}`: the next empty line after a paragraph, or the end of the buffer.
+pub(crate) fn paragraph_forward(buffer: &Rope, (x, y): Position, count: Option<usize>) -> Option<Motion> {
+    let last = last_line_index(buffer);
+    let mut line = y;
+    for _ in 0..count.unwrap_or(1) {
+        while line < last && buffer.line_len(line) == 0 {
+            line += 1;
+        }
+        while line < last && buffer.line_len(line) > 0 {
+            line += 1;
+        }
+    }
+
+    Motion::exclusive(moved(buffer, (x, y), (buffer.line_len(line), line))?)
+}
+
+/// `{`: the previous empty line before a paragraph, or the start of the buffer.
+pub(crate) fn paragraph_backward(buffer: &Rope, (x, y): Position, count: Option<usize>) -> Option<Motion> {
+    let mut line = y;
+    for _ in 0..count.unwrap_or(1) {
+        while line > 0 && buffer.line_len(line) == 0 {
+            line -= 1;
+        }
+        while line > 0 && buffer.line_len(line) > 0 {
+            line -= 1;
+        }
+    }
+
+    Motion::exclusive(moved(buffer, (x, y), (0, line))?)
+}
+
+/// Whether a sentence starts at `char_idx`: the first non-blank after a `.`, `!` or `?` (optionally followed by
+/// closing brackets or quotes) and whitespace, or after an empty line. Empty lines are sentences of their own.
+pub(crate) fn is_sentence_start(buffer: &Rope, char_idx: usize) -> bool {
+    match buffer.char(char_idx) {
+        None => return false,
+        Some('\n') => return is_empty_line(buffer, char_idx),
+        Some(c) if c.is_whitespace() => return false,
+        _ => {}
+    }
+
+    let (mut i, mut newlines) = (char_idx, 0);
+    while i > 0 && buffer.char(i - 1).is_some_and(char::is_whitespace) {
+        newlines += (buffer.char(i - 1) == Some('\n')) as usize;
+        i -= 1;
+    }
+
+    if i == 0 || newlines >= 2 {
+        return true;
+    }
+    if i == char_idx {
+        return false;
+    }
+
+    while i > 0 && buffer.char(i - 1).is_some_and(|c| ")]\"'".contains(c)) {
+        i -= 1;
+    }
+    i > 0 && buffer.char(i - 1).is_some_and(|c| ".!?".contains(c))
+}
+
+/// `)`
+pub(crate) fn sentence_forward(buffer: &Rope, position: Position, count: Option<usize>) -> Option<Motion> {
+    let len = buffer.len_chars();
+    Motion::exclusive(repeat_step(buffer, position, count, |i| {
+        (i + 1..len).find(|&j| is_sentence_start(buffer, j)).unwrap_or(len)
+    })?)
+}
+
+/// `(`
+pub(crate) fn sentence_backward(buffer: &Rope, position: Position, count: Option<usize>) -> Option<Motion> {
+    Motion::exclusive(repeat_step(buffer, position, count, |i| {
+        (0..i).rev().find(|&j| is_sentence_start(buffer, j)).unwrap_or(0)
+    })?)
+}
+
+/// Target of `f`, `F`, `t` and `T`, remembered so `;` and `,` can repeat it.
+#[derive(Clone, Copy)]
+pub(crate) struct CharSearch {
+    pub(crate) target: char,
+    pub(crate) forward: bool,
+    pub(crate) till: bool,
+}
+
+impl CharSearch {
+    pub(crate) fn reversed(self) -> Self {
+        Self { forward: !self.forward, ..self }
+    }
+}
+
+/// `f`, `F`, `t` and `T` within the current line. `repeat` is set for `;` and `,`, so a `t` that already stopped
+/// right before its target moves on to the next occurrence instead of staying put.
+pub(crate) fn find_char(
+    buffer: &Rope,
+    (x, y): Position,
+    count: Option<usize>,
+    search: CharSearch,
+    repeat: bool,
+) -> Option<Motion> {
+    let line = buffer.line(y).chars().collect::<Vec<_>>();
+    let skip = (search.till && repeat) as usize;
+    let mut column = x;
+
+    for _ in 0..count.unwrap_or(1) {
+        column = if search.forward {
+            (column + 1 + skip..line.len()).find(|&i| line[i] == search.target)?
+        } else {
+            (0..column.checked_sub(skip)?).rev().find(|&i| line[i] == search.target)?
+        };
+    }
+
+    match (search.forward, search.till) {
+        (true, false) => Motion::inclusive((column, y)),
+        (true, true) => Motion::inclusive((column - 1, y)),
+        (false, false) => Motion::exclusive((column, y)),
+        (false, true) => Motion::exclusive((column + 1, y)),
+    }
+}
+
+const BRACKETS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// `%`: the bracket matching the first one at or after the cursor on its line. With a count, goes to that
+/// percentage of the buffer instead.
+pub(crate) fn matching_bracket(buffer: &Rope, (x, y): Position, count: Option<usize>) -> Option<Motion> {
+    if let Some(percent) = count {
+        let line = (percent.min(100) * buffer.len_lines()).div_ceil(100).saturating_sub(1);
+        return Motion::linewise(line);
+    }
+
+    let line_start = buffer.line_to_char(y);
+    let (start, bracket) = buffer
+        .line(y)
+        .chars()
+        .enumerate()
+        .skip(x)
+        .find(|&(_, c)| BRACKETS.iter().any(|&(open, close)| c == open || c == close))?;
+
+    let target = find_matching_bracket(buffer, line_start + start, bracket)?;
+    Motion::inclusive(to_position(buffer, target))
+}
+
+/// Char index of the bracket matching the one at `char_idx`, skipping over nested pairs.
+fn find_matching_bracket(buffer: &Rope, char_idx: usize, bracket: char) -> Option<usize> {
+    let &pair = BRACKETS.iter().find(|&&(open, close)| bracket == open || bracket == close)?;
+    match bracket == pair.0 {
+        true => find_unmatched(buffer, char_idx + 1, pair, true),
+        false => find_unmatched(buffer, char_idx.checked_sub(1)?, pair, false),
+    }
+}
+
+/// Searches from `char_idx` for the first closing bracket of `(open, close)` without an opening one before it, or
+/// the first unmatched opening bracket when searching backwards.
+pub(crate) fn find_unmatched(
+    buffer: &Rope,
+    char_idx: usize,
+    (open, close): (char, char),
+    forward: bool,
+) -> Option<usize> {
+    let (nested, target) = if forward { (open, close) } else { (close, open) };
+
+    let mut depth = 0usize;
+    let mut i = char_idx;
+    loop {
+        match buffer.char(i)? {
+            c if c == nested => depth += 1,
+            c if c == target => match depth {
+                0 => return Some(i),
+                _ => depth -= 1,
+            },
+            _ => {}
+        }
+
+        i = if forward { i + 1 } else { i.checked_sub(1)? };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::editor::Editor;
+
+    type MotionFn = fn(&Rope, Position, Option<usize>) -> Option<Motion>;
+
+    fn target(motion: MotionFn, text: &str, position: Position, count: Option<usize>) -> Option<Position> {
+        motion(&Rope::new(text), position, count).map(|motion| motion.target)
+    }
+
+    #[test]
+    fn words() {
+        let text = "foo bar.baz  qux\n\nnext";
+        let w: MotionFn = |b, p, c| word_forward(b, p, c, false);
+        let big_w: MotionFn = |b, p, c| word_forward(b, p, c, true);
+        assert_eq!(target(w, text, (0, 0), None), Some((4, 0)));
+        assert_eq!(target(w, text, (0, 0), Some(2)), Some((7, 0)));
+        assert_eq!(target(w, text, (0, 0), Some(4)), Some((13, 0)));
+        assert_eq!(target(big_w, text, (0, 0), Some(2)), Some((13, 0)));
+        // An empty line is a word of its own.
+        assert_eq!(target(w, text, (13, 0), None), Some((0, 1)));
+        assert_eq!(target(w, text, (0, 1), None), Some((0, 2)));
+
+        let b: MotionFn = |b, p, c| word_backward(b, p, c, false);
+        let big_b: MotionFn = |b, p, c| word_backward(b, p, c, true);
+        assert_eq!(target(b, text, (13, 0), None), Some((8, 0)));
+        assert_eq!(target(b, text, (13, 0), Some(3)), Some((4, 0)));
+        assert_eq!(target(big_b, text, (13, 0), None), Some((4, 0)));
+        assert_eq!(target(b, text, (0, 2), None), Some((0, 1)));
+
+        let e: MotionFn = |b, p, c| word_end(b, p, c, false);
+        let big_e: MotionFn = |b, p, c| word_end(b, p, c, true);
+        assert_eq!(target(e, text, (0, 0), None), Some((2, 0)));
+        assert_eq!(target(e, text, (2, 0), None), Some((6, 0)));
+        assert_eq!(target(e, text, (0, 0), Some(3)), Some((7, 0)));
+        assert_eq!(target(big_e, text, (4, 0), None), Some((10, 0)));
+    }
+
+    #[test]
+    fn paragraphs() {
+        let text = "a\nb\n\nc\nd\n\ne";
+        assert_eq!(target(paragraph_forward, text, (0, 0), None), Some((0, 2)));
+        assert_eq!(target(paragraph_forward, text, (0, 0), Some(2)), Some((0, 5)));
+        assert_eq!(target(paragraph_forward, text, (0, 3), Some(2)), Some((1, 6)));
+        assert_eq!(target(paragraph_forward, text, (0, 6), None), None);
+        assert_eq!(target(paragraph_backward, text, (0, 6), None), Some((0, 5)));
+        assert_eq!(target(paragraph_backward, text, (0, 4), Some(2)), Some((0, 0)));
+    }
+
+    #[test]
+    fn sentences() {
+        let text = "One. Two!  Three?\nFour. (Five.) Six";
+        assert_eq!(target(sentence_forward, text, (0, 0), None), Some((5, 0)));
+        assert_eq!(target(sentence_forward, text, (0, 0), Some(2)), Some((11, 0)));
+        assert_eq!(target(sentence_forward, text, (11, 0), None), Some((0, 1)));
+        assert_eq!(target(sentence_forward, text, (0, 1), Some(2)), Some((14, 1)));
+        assert_eq!(target(sentence_backward, text, (0, 1), None), Some((11, 0)));
+        assert_eq!(target(sentence_backward, text, (13, 0), None), Some((11, 0)));
+        assert_eq!(target(sentence_backward, text, (6, 0), Some(5)), Some((0, 0)));
+    }
+
+    #[test]
+    fn matching_brackets() {
+        let text = "if (a[1] + (b)) {\n  x\n}";
+        assert_eq!(target(matching_bracket, text, (0, 0), None), Some((14, 0)));
+        assert_eq!(target(matching_bracket, text, (5, 0), None), Some((7, 0)));
+        assert_eq!(target(matching_bracket, text, (14, 0), None), Some((3, 0)));
+        assert_eq!(target(matching_bracket, text, (15, 0), None), Some((0, 2)));
+        assert_eq!(target(matching_bracket, text, (0, 2), None), Some((16, 0)));
+        assert_eq!(target(matching_bracket, "(a", (0, 0), None), None);
+        assert_eq!(target(matching_bracket, "no brackets", (0, 0), None), None);
+        // With a count, `%` goes to that percentage of the lines.
+        assert_eq!(target(matching_bracket, text, (0, 0), Some(50)), Some((0, 1)));
+    }
+
+    #[test]
+    fn finds_and_repeats_chars() {
+        let moved = |keys: &str| {
+            let mut editor = Editor::with_text("a,b,c,d;e");
+            editor.type_keys(keys);
+            editor.display.cursor.position.0
+        };
+        assert_eq!(moved("f,"), 1);
+        assert_eq!(moved("2f,"), 3);
+        assert_eq!(moved("f,;"), 3);
+        assert_eq!(moved("f,;;,"), 3);
+        assert_eq!(moved("$F,"), 5);
+        assert_eq!(moved("tc"), 3);
+        // A repeated `t` moves on instead of staying before the char it stopped at.
+        assert_eq!(moved("t,;"), 2);
+        assert_eq!(moved("$T,;"), 4);
+        assert_eq!(moved("fz"), 0);
+    }
+}
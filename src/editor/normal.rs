@@ -0,0 +1,135 @@
+use color_eyre::{eyre::eyre, Report};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::{mem::take, ops::RangeInclusive};
+
+use crate::editor::{Editor, Mode};
+use crate::util::keymap;
+
+impl Editor {
+    /// `:[range]norm[al][!] {keys}`: executes `keys` as if typed in Normal mode, at the start of each line of `lines`
+    /// when a range is given and at the cursor otherwise. With `bang` mappings do not apply to them. A command left
+    /// incomplete is abandoned as if `<Esc>` was typed, and all the changes are a single undo step.
+    pub(crate) fn execute_normal(
+        &mut self,
+        lines: Option<RangeInclusive<usize>>,
+        keys: &str,
+        bang: bool,
+    ) -> Result<(), Report> {
+        if keys.is_empty() {
+            return Err(eyre!("Argument required"));
+        }
+        let keys = keymap::parse_keys(keys);
+
+        // Keys queued before these, as by a macro running `:normal`, wait until they are done.
+        let typeahead = take(&mut self.typeahead);
+        let dot_repeat_keys = take(&mut self.dot_repeat.keys);
+        self.history.begin_group();
+
+        let mut result = Ok(());
+        match lines {
+            Some(lines) => {
+                for line in lines {
+                    // Lines removed by the keys run so far are not there to run them on.
+                    if line >= self.buffer.len_lines() {
+                        break;
+                    }
+                    self.set_cursor((0, line));
+                    result = result.and(self.execute_keys(keys.clone(), !bang));
+                }
+            }
+            None => result = self.execute_keys(keys, !bang),
+        }
+
+        self.history.end_group();
+        self.dot_repeat.keys = dot_repeat_keys;
+        self.typeahead = typeahead;
+        result
+    }
+
+    /// Processes `keys` and whatever they queue in turn, up to the first error, then ends any command they left
+    /// waiting for more keys.
+    fn execute_keys(&mut self, keys: Vec<KeyEvent>, remap: bool) -> Result<(), Report> {
+        self.feed_keys(keys, remap);
+
+        let mut result = Ok(());
+        while let Some((event, remap)) = self.typeahead.pop_front() {
+            result = self.process_key(event, remap);
+            if result.is_err() {
+                self.typeahead.clear();
+                break;
+            }
+            // Each complete change the keys make can be repeated with `.` afterwards.
+            self.finish_change();
+        }
+
+        // A keybind waiting to see whether more keys follow runs as it would after the timeout.
+        if !self.keymap.is_empty() {
+            result = result.and(self.execute_keymap_action());
+        }
+
+        let escape = KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE);
+        if self.char_argument.is_some() {
+            let _ = self.process_key(escape, false);
+        }
+        // Leaving a search prompt can return to Visual or Operator-pending mode, which takes another `<Esc>`.
+        for _ in 0..3 {
+            if self.mode == Mode::NORMAL {
+                break;
+            }
+            let _ = self.process_key(escape, false);
+        }
+        self.keymap.clear();
+        self.keymap.set_count(None);
+        self.selected_register = None;
+        self.finish_change();
+        self.dot_repeat.keys.clear();
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::{Editor, Mode};
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn runs_keys_on_each_line() {
+        let text = "one\ntwo\nthree";
+        assert_eq!(typed(text, ":normal A;<CR>").text(), "one;\ntwo\nthree");
+        assert_eq!(typed(text, ":%norm A;<CR>").text(), "one;\ntwo;\nthree;");
+        assert_eq!(typed(text, ":2,3norm i- <CR>").text(), "one\n-two\n-three");
+        // Lines deleted by the keys run on earlier ones are gone before their turn comes.
+        assert_eq!(typed(text, ":%norm dd<CR>").text(), "two");
+        assert!(typed(text, ":norm<CR>").error.is_some());
+    }
+
+    #[test]
+    fn abandons_incomplete_commands() {
+        // The Insert mode `i` started is left as if `<Esc>` was typed, and `d` waiting for a motion is dropped.
+        let editor = typed("one two", ":norm iX<CR>");
+        assert_eq!(editor.text(), "Xone two");
+        assert!(editor.mode == Mode::NORMAL);
+        assert_eq!(typed("one two", ":norm d<CR>x").text(), "one two");
+    }
+
+    #[test]
+    fn bang_ignores_mappings() {
+        let text = "a b\nc\nd";
+        assert_eq!(typed(text, ":nmap J dw<CR>:normal J<CR>").text(), "b\nc\nd");
+        assert_eq!(typed(text, ":nmap J dw<CR>:normal! J<CR>").text(), "a b c\nd");
+        assert_eq!(typed(text, ":nnoremap Q J<CR>:normal! Q<CR>").text(), text);
+    }
+
+    #[test]
+    fn undoes_and_repeats() {
+        // All the lines change in one undo step, and `.` repeats the last change the keys made.
+        assert_eq!(typed("a\nb\nc", ":%norm A!<CR>u").text(), "a\nb\nc");
+        assert_eq!(typed("a\nb\nc", ":1,2norm A!<CR>G.").text(), "a!\nb!\nc!");
+    }
+}
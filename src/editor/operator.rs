@@ -0,0 +1,404 @@
+use color_eyre::Report;
+use std::ops::Range;
+
+use crate::editor::motion::{self, Motion, MotionKind, Position};
+use crate::editor::register::Register;
+use crate::editor::text_object::Selection;
+use crate::editor::{Editor, Mode};
+use crate::util::Rope;
+
+/// Number of spaces added or removed per level by `>` and `<`.
+const SHIFT_WIDTH: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Operator {
+    Delete,
+    Change,
+    Yank,
+    ShiftRight,
+    ShiftLeft,
+    Lowercase,
+    Uppercase,
+    ToggleCase,
+    /// `!`, which starts a `:!` command line filtering the lines moved over.
+    Filter,
+}
+
+/// Operator waiting for the motion that tells it which text to act on, with the count typed before it.
+pub(crate) struct PendingOperator {
+    pub(crate) operator: Operator,
+    pub(crate) count: Option<usize>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RangeKind {
+    Charwise,
+    Linewise,
+    Blockwise,
+}
+
+impl Editor {
+    pub(crate) fn begin_operator(&mut self, operator: Operator) {
+        self.operator = Some(PendingOperator { operator, count: self.count });
+        self.mode = Mode::OPERATOR;
+    }
+
+    pub(crate) fn cancel_operator(&mut self) {
+        self.operator = None;
+        if self.mode == Mode::OPERATOR {
+            self.mode = Mode::NORMAL;
+        }
+    }
+
+    /// Runs `motion` with the typed count. Without a pending operator this moves the cursor, otherwise the operator
+    /// is applied to the text between the cursor and the target.
+    pub(crate) fn motion<F>(&mut self, motion: F) -> Result<(), Report>
+    where
+        F: FnOnce(&Rope, Position, Option<usize>) -> Option<Motion>,
+    {
+        let (pending, count) = self.take_operator();
+        let cursor = self.display.cursor.position;
+        let Some(motion) = motion(&self.buffer, cursor, count) else {
+            return Err(Editor::failed());
+        };
+
+        match pending {
+            Some(pending) => {
+                let (range, kind) = self.motion_range(cursor, &motion);
+                self.apply_operator(pending.operator, range, kind)
+            }
+            None => {
+                match motion.kind {
+                    MotionKind::Linewise => self.display.cursor_move_y(motion.target.1, &self.buffer),
+                    _ => self.set_cursor(motion.target),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Applies the pending operator to the text selected by `object` around the cursor, as in `diw` or `ya(`, or
+    /// selects that text in Visual mode.
+    pub(crate) fn text_object<F>(&mut self, object: F) -> Result<(), Report>
+    where
+        F: FnOnce(&Rope, Position, Option<usize>) -> Option<Selection>,
+    {
+        let (pending, count) = self.take_operator();
+        let Some((range, kind)) = object(&self.buffer, self.display.cursor.position, count) else {
+            return Err(Editor::failed());
+        };
+
+        match pending {
+            Some(pending) => self.apply_operator(pending.operator, range, kind),
+            None => {
+                self.select_range(range, kind);
+                Ok(())
+            }
+        }
+    }
+
+    /// Leaves operator-pending mode, returning the pending operator and the count for its target, with both counts
+    /// multiplied as in `2d3w`.
+    fn take_operator(&mut self) -> (Option<PendingOperator>, Option<usize>) {
+        let pending = self.operator.take();
+        if self.mode == Mode::OPERATOR {
+            self.mode = Mode::NORMAL;
+        }
+
+        let count = match (pending.as_ref().and_then(|pending| pending.count), self.count) {
+            (Some(operator_count), Some(motion_count)) => Some(operator_count.saturating_mul(motion_count)),
+            (operator_count, motion_count) => operator_count.or(motion_count),
+        };
+
+        (pending, count)
+    }
+
+    /// Doubled operator forms such as `dd` and `gUU`, acting on `count` whole lines. Typing a different operator
+    /// than the pending one cancels it.
+    pub(crate) fn operator_lines(&mut self, operator: Operator) -> Result<(), Report> {
+        if self.operator.as_ref().map(|pending| pending.operator) != Some(operator) {
+            self.cancel_operator();
+            return Ok(());
+        }
+
+        self.motion(motion::current_line)
+    }
+
+    /// Char range covered by moving from `from` with `motion`. Linewise ranges span from the start of the first line
+    /// to the end of the last one, without its newline.
+    fn motion_range(&self, from: Position, motion: &Motion) -> Selection {
+        let to = motion.target;
+        if motion.kind == MotionKind::Linewise {
+            let (first, last) = (from.1.min(to.1), from.1.max(to.1));
+            let range = self.buffer.line_to_char(first)..self.buffer.line_to_char(last) + self.buffer.line_len(last);
+            return (range, RangeKind::Linewise);
+        }
+
+        let (a, b) = (motion::to_char(&self.buffer, from), motion::to_char(&self.buffer, to));
+        let (start, mut end) = (a.min(b), a.max(b));
+        match motion.kind {
+            MotionKind::Inclusive => end = (end + 1).min(self.buffer.len_chars()),
+            // An exclusive motion ending at the start of a later line stops at the end of the previous one instead,
+            // and becomes linewise when it also starts at or before the first non-blank of its line.
+            _ => {
+                let (x, y) = motion::to_position(&self.buffer, end);
+                let (start_x, start_y) = motion::to_position(&self.buffer, start);
+                if x == 0 && y > start_y {
+                    if start_x <= motion::first_non_blank_column(&self.buffer, start_y) {
+                        let range = self.buffer.line_to_char(start_y)..end - 1;
+                        return (range, RangeKind::Linewise);
+                    }
+                    end -= 1;
+                }
+            }
+        }
+
+        (start..end, RangeKind::Charwise)
+    }
+
+    pub(crate) fn apply_operator(
+        &mut self,
+        operator: Operator,
+        range: Range<usize>,
+        kind: RangeKind,
+    ) -> Result<(), Report> {
+        let first_line = self.buffer.char_to_line(range.start);
+        let last_line = self.buffer.char_to_line(range.end);
+
+        match operator {
+            Operator::Yank => {
+                self.yank(range.clone(), kind, false)?;
+                self.move_to_range_start(range.start, kind);
+            }
+            Operator::Delete => {
+                self.yank(range.clone(), kind, true)?;
+                match kind {
+                    RangeKind::Linewise => {
+                        self.remove_lines(first_line, last_line);
+                        let line = first_line.min(self.buffer.len_lines() - 1);
+                        self.set_cursor((motion::first_non_blank_column(&self.buffer, line), line));
+                    }
+                    _ => {
+                        self.remove_text(range.clone());
+                        self.move_to_range_start(range.start, kind);
+                    }
+                }
+            }
+            Operator::Change => {
+                self.yank(range.clone(), kind, true)?;
+                self.remove_text(range.clone());
+                self.set_cursor(motion::to_position(&self.buffer, range.start));
+                self.mode = Mode::INSERT;
+            }
+            Operator::ShiftRight | Operator::ShiftLeft => {
+                for line in first_line..=last_line {
+                    self.shift_line(line, operator == Operator::ShiftRight);
+                }
+                self.set_cursor((motion::first_non_blank_column(&self.buffer, first_line), first_line));
+            }
+            Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase => {
+                let text = self.buffer.slice(range.clone());
+                let converted = match operator {
+                    Operator::Lowercase => text.to_lowercase(),
+                    Operator::Uppercase => text.to_uppercase(),
+                    _ => text.chars().map(toggle_case).collect(),
+                };
+
+                if converted != text {
+                    self.replace_text(range.clone(), &converted);
+                }
+                self.move_to_range_start(range.start, kind);
+            }
+            Operator::Filter => {
+                // A motion that ends at the start of a line stops before it.
+                let last_line = match kind {
+                    RangeKind::Linewise => last_line,
+                    _ => self.buffer.char_to_line(range.end.saturating_sub(1).max(range.start)),
+                };
+                self.set_cursor((motion::first_non_blank_column(&self.buffer, first_line), first_line));
+                self.command = match last_line - first_line {
+                    0 => ".!".to_string(),
+                    lines => format!(".,.+{}!", lines),
+                };
+                self.mode = Mode::COMMAND;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn yank(&mut self, range: Range<usize>, kind: RangeKind, delete: bool) -> Result<(), Report> {
+        let mut text = self.buffer.slice(range);
+        if kind == RangeKind::Linewise {
+            text.push('\n');
+        }
+
+        self.store_register(Register { text, kind }, delete)
+    }
+
+    /// Removes lines `first..=last` together with the newline separating them from the rest of the buffer.
+    pub(crate) fn remove_lines(&mut self, first: usize, last: usize) {
+        let range = if last + 1 < self.buffer.len_lines() {
+            self.buffer.line_to_char(first)..self.buffer.line_to_char(last + 1)
+        } else if first > 0 {
+            self.buffer.line_to_char(first) - 1..self.buffer.len_chars()
+        } else {
+            0..self.buffer.len_chars()
+        };
+
+        self.remove_text(range);
+    }
+
+    fn shift_line(&mut self, line: usize, right: bool) {
+        let start = self.buffer.line_to_char(line);
+        let text = self.buffer.line(line);
+
+        if right && !text.is_empty() {
+            self.insert_text(start, &" ".repeat(SHIFT_WIDTH));
+        } else if !right {
+            let indent = match text.starts_with('\t') {
+                true => 1,
+                false => text.chars().take(SHIFT_WIDTH).take_while(|&c| c == ' ').count(),
+            };
+            self.remove_text(start..start + indent);
+        }
+    }
+
+    /// Joins lines `first..=last`, replacing each line break and the indent after it with a single space, as `J`
+    /// does. No space is added before an empty line or a closing parenthesis, or after trailing whitespace.
+    pub(crate) fn join_lines(&mut self, first: usize, last: usize) {
+        for _ in first..last.min(self.buffer.len_lines() - 1) {
+            let line_len = self.buffer.line_len(first);
+            let line_end = self.buffer.line_to_char(first) + line_len;
+            let next = self.buffer.line(first + 1);
+            let indent = next.chars().take_while(|c| c.is_whitespace()).count();
+
+            let rest = next.trim_start();
+            let current_ends_blank = line_len == 0 || self.buffer.char(line_end - 1).is_some_and(char::is_whitespace);
+            let separator = if rest.is_empty() || rest.starts_with(')') || current_ends_blank { "" } else { " " };
+
+            self.replace_text(line_end..line_end + 1 + indent, separator);
+            self.set_cursor((line_len, first));
+        }
+    }
+
+    fn move_to_range_start(&mut self, start: usize, kind: RangeKind) {
+        match kind {
+            RangeKind::Linewise => self.display.cursor_move_y(self.buffer.char_to_line(start), &self.buffer),
+            _ => self.set_cursor(motion::to_position(&self.buffer, start)),
+        }
+    }
+}
+
+fn toggle_case(c: char) -> char {
+    if c.is_uppercase() {
+        c.to_lowercase().next().unwrap_or(c)
+    } else {
+        c.to_uppercase().next().unwrap_or(c)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn counts_multiply() {
+        assert_eq!(typed("abcdefgh", "d3l").text(), "defgh");
+        assert_eq!(typed("abcdefgh", "2d3l").text(), "gh");
+        assert_eq!(typed("abcdefgh", "3dl").text(), "defgh");
+        assert_eq!(typed("1\n2\n3\n4\n5", "2dd").text(), "3\n4\n5");
+        assert_eq!(typed("1\n2\n3\n4\n5", "2d2d").text(), "5");
+        // Counts too large to multiply stop at the end of the buffer.
+        assert_eq!(typed("one two\nthree", "9999999999d9999999999w").text(), "");
+        assert_eq!(typed("abc\ndef", "j9999999999d9999999999l").text(), "abc\n");
+        assert_eq!(typed("1\n2\n3", "j9999999999d9999999999d").text(), "1");
+
+        // Counts reach motions and commands without an operator too, each running once with it.
+        assert_eq!(typed("1\n2\n3\n4\n5", "3j").display.cursor.position, (0, 3));
+        assert_eq!(typed("1\n2\n3\n4\n5", "99999999999999999999j").display.cursor.position, (0, 4));
+        assert_eq!(typed("1\n2\n3\n4\n5", "dddddd2u").text(), "2\n3\n4\n5");
+    }
+
+    #[test]
+    fn word_motions() {
+        assert_eq!(typed("one two three four", "d2w").text(), "three four");
+        assert_eq!(typed("one two three four", "2dw").text(), "three four");
+        assert_eq!(typed("one two three four", "2d2w").text(), "");
+        // `dw` on the last word of a line stops at its end, and `cw` leaves the blanks after the word.
+        assert_eq!(typed("one two\nthree", "wdw").text(), "one \nthree");
+        assert_eq!(typed("one two", "cwx<Esc>").text(), "x two");
+        assert_eq!(typed("one two three", "wd2e").text(), "one ");
+        assert_eq!(typed("a.b c", "dW").text(), "c");
+        assert_eq!(typed("one two three", "$db").text(), "one two e");
+    }
+
+    #[test]
+    fn linewise_motions_delete_whole_lines() {
+        let editor = typed("one\ntwo\nthree\nfour", "jdj");
+        assert_eq!(editor.text(), "one\nfour");
+        assert_eq!(editor.display.cursor.position, (0, 1));
+        assert_eq!(typed("one\ntwo\nthree", "Gdk").text(), "one");
+        assert_eq!(typed("one\ntwo\nthree", "jdG").text(), "one");
+        assert_eq!(typed("one\ntwo", "jdgg").text(), "");
+
+        let editor = typed("one\ntwo", "jyk");
+        assert_eq!(editor.read_register('"').unwrap().text, "one\ntwo\n");
+        assert_eq!(editor.display.cursor.position.1, 0);
+    }
+
+    #[test]
+    fn change_enters_insert_mode() {
+        let mut editor = typed("hello world", "c2lj");
+        editor.type_keys("<Esc>");
+        assert_eq!(editor.text(), "jllo world");
+
+        let mut editor = typed("one\ntwo", "cc");
+        assert!(editor.mode == Mode::INSERT);
+        editor.type_keys("x<Esc>");
+        assert_eq!(editor.text(), "x\ntwo");
+    }
+
+    #[test]
+    fn shifts_lines() {
+        assert_eq!(typed("a\nb\nc", ">>").text(), "    a\nb\nc");
+        assert_eq!(typed("a\nb\nc", "2>>").text(), "    a\n    b\nc");
+        assert_eq!(typed("a\n\nc", ">G").text(), "    a\n\n    c");
+        assert_eq!(typed("      a\n\tb\nc", "<j").text(), "  a\nb\nc");
+        assert_eq!(typed("  a", "<lt><lt>").text(), "a");
+    }
+
+    #[test]
+    fn changes_case() {
+        assert_eq!(typed("hello World", "gU$").text(), "HELLO WORLD");
+        assert_eq!(typed("Hello\nWorld", "2guu").text(), "hello\nworld");
+        assert_eq!(typed("Hello World", "g~~").text(), "hELLO wORLD");
+        assert_eq!(typed("hello", "3gUl").text(), "HELlo");
+        assert_eq!(typed("one two three", "wgUiw").text(), "one TWO three");
+        assert_eq!(typed("one two three", "wgU3iw").text(), "one TWO THREE");
+    }
+
+    #[test]
+    fn joins_lines() {
+        assert_eq!(typed("one\ntwo\nthree\nfour", "J").text(), "one two\nthree\nfour");
+        assert_eq!(typed("one\ntwo\nthree\nfour", "2J").text(), "one two\nthree\nfour");
+        assert_eq!(typed("one\ntwo\nthree\nfour", "3J").text(), "one two three\nfour");
+        assert_eq!(typed("one\n    two\n)", "3J").text(), "one two)");
+        assert_eq!(typed("one\ntwo", "jJ").text(), "one\ntwo");
+        assert_eq!(typed("one\ntwo\nthree", "j18446744073709551615J").text(), "one\ntwo three");
+    }
+
+    #[test]
+    fn another_operator_cancels() {
+        let editor = typed("one\ntwo", "dyj");
+        assert_eq!(editor.text(), "one\ntwo");
+        assert!(editor.mode == Mode::NORMAL && editor.operator.is_none());
+        assert_eq!(editor.display.cursor.position, (0, 1));
+    }
+}
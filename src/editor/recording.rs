@@ -0,0 +1,107 @@
+use color_eyre::{eyre::eyre, Report};
+
+use crate::editor::operator::RangeKind;
+use crate::editor::register::Register;
+use crate::editor::Editor;
+use crate::util::keymap;
+
+/// Most keys `@` queues at once, counting every repetition of the macro.
+const MAX_MACRO_KEYS: usize = 1 << 22;
+
+/// Macro being recorded with `q`, and the keys typed so far.
+pub(crate) struct Recording {
+    pub(crate) register: char,
+    pub(crate) keys: Vec<crossterm::event::KeyEvent>,
+}
+
+impl Editor {
+    /// `q{register}`: starts recording typed keys into `register`. An uppercase name appends to the register.
+    pub(crate) fn start_recording(&mut self, register: char) -> Result<(), Report> {
+        if !register.is_ascii_alphanumeric() && register != '"' {
+            return Err(eyre!("Invalid register name: {}", register));
+        }
+
+        self.recording = Some(Recording { register, keys: Vec::new() });
+        Ok(())
+    }
+
+    /// `q` while recording: stores the recorded keys, without the `q` itself, as text in the register.
+    pub(crate) fn stop_recording(&mut self) -> Result<(), Report> {
+        let Some(Recording { register, mut keys }) = self.recording.take() else {
+            return Ok(());
+        };
+        keys.pop();
+
+        let text = keys.iter().map(keymap::key_notation).collect::<String>();
+        self.registers.store(Some(register), Register { text, kind: RangeKind::Charwise }, false)
+    }
+
+    /// `@{register}`: replays the keys in `register` `count` times, and `@@` the last register executed. Keys queued
+    /// by a macro that calls itself go in front of the rest of it, so recursive macros run until one of their
+    /// commands fails, which drops everything still queued. A count that would queue too many keys is refused.
+    pub(crate) fn execute_macro(&mut self, register: char) -> Result<(), Report> {
+        let register = match register {
+            '@' => self.last_macro.ok_or_else(|| eyre!("No previously used register"))?,
+            register => register,
+        };
+
+        let text = match register {
+            ':' => format!(":{}<CR>", self.registers.last_command),
+            _ => self.read_register(register)?.text,
+        };
+        self.last_macro = Some(register);
+
+        let keys = keymap::parse_keys(&text);
+        if keys.len().checked_mul(self.repeats()).is_none_or(|len| len > MAX_MACRO_KEYS) {
+            return Err(eyre!("Count too large"));
+        }
+        self.feed_keys(keys.repeat(self.repeats()), true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let editor = typed("a\nb\nc\nd\ne", "qaA!<Esc>jq");
+        assert_eq!(editor.read_register('a').unwrap().text, "A!<Esc>j");
+        assert_eq!(editor.text(), "a!\nb\nc\nd\ne");
+
+        assert_eq!(typed("a\nb\nc\nd\ne", "qaA!<Esc>jq@a").text(), "a!\nb!\nc\nd\ne");
+        assert_eq!(typed("a\nb\nc\nd\ne", "qaA!<Esc>jq2@a").text(), "a!\nb!\nc!\nd\ne");
+        assert_eq!(typed("a\nb\nc\nd\ne", "qaA!<Esc>jq@a2@@").text(), "a!\nb!\nc!\nd!\ne");
+        // An uppercase name appends to the macro.
+        let editor = typed("a\nb\nc", "qaA!<Esc>qqAjq@a");
+        assert_eq!(editor.read_register('a').unwrap().text, "A!<Esc>j");
+        assert_eq!(editor.text(), "a!\nb!\nc");
+    }
+
+    #[test]
+    fn recursive_macro_stops_at_failure() {
+        // `qaq` empties the register first, so `@a` does nothing while recording.
+        assert_eq!(typed("1\n2\n3\n4\n5\n6", "qaqqaddj@aq@a").text(), "2\n4\n6");
+    }
+
+    #[test]
+    fn invalid_registers() {
+        assert!(typed("a", "q!").error.is_some());
+        assert!(typed("a", "@@").error.is_some());
+    }
+
+    #[test]
+    fn count_too_large() {
+        let editor = typed("a", "qaA!<Esc>q99999999999@a");
+        assert_eq!(editor.error.as_deref(), Some("Count too large"));
+        assert_eq!(editor.text(), "a!");
+        assert_eq!(typed("a", "qaA!<Esc>q18446744073709551615@a").text(), "a!");
+    }
+}
@@ -0,0 +1,344 @@
+use color_eyre::{eyre::eyre, Report};
+use std::collections::HashMap;
+
+use crate::editor::motion;
+use crate::editor::operator::RangeKind;
+use crate::editor::Editor;
+use crate::util::clipboard::Selection;
+
+/// Text stored by a yank or delete. Linewise text ends with a newline, and blockwise text has one line per row of the
+/// block.
+#[derive(Clone)]
+pub(crate) struct Register {
+    pub(crate) text: String,
+    pub(crate) kind: RangeKind,
+}
+
+/// Most bytes a single put can insert, counting every repetition.
+const MAX_PUT_LEN: usize = 1 << 28;
+
+/// Register names listed by `:registers`, in order.
+const LISTED: &str = "\"0123456789abcdefghijklmnopqrstuvwxyz-*+.:%";
+
+/// Contents of every writable register, plus the ones only the editor writes to.
+pub(crate) struct Registers {
+    /// Register that the unnamed register `""` refers to, i.e. the last one written.
+    unnamed: char,
+    stored: HashMap<char, Register>,
+    /// Text typed in the last Insert mode session, for `".`.
+    pub(crate) last_insert: String,
+    /// Last command line that was executed, for `":`.
+    pub(crate) last_command: String,
+    /// Last command run by the shell, which `!` stands for in the next one.
+    pub(crate) last_shell_command: Option<String>,
+}
+
+impl Register {
+    /// Adds `other` at the end of this register, as writing to an uppercase register name does. Text appended to or
+    /// from a linewise register becomes linewise.
+    fn append(&mut self, other: Register) {
+        if other.kind == RangeKind::Linewise && self.kind != RangeKind::Linewise {
+            self.text.push('\n');
+        }
+
+        self.text.push_str(&other.text);
+        if self.kind == RangeKind::Linewise && other.kind != RangeKind::Linewise {
+            self.text.push('\n');
+        }
+
+        if other.kind == RangeKind::Linewise {
+            self.kind = RangeKind::Linewise;
+        }
+    }
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self {
+            unnamed: '0',
+            stored: HashMap::new(),
+            last_insert: String::new(),
+            last_command: String::new(),
+            last_shell_command: None,
+        }
+    }
+
+    /// Stores `register` under `name`, or where the unnamed register puts it when no name is given: yanks go to `"0`,
+    /// deletes of a line or more shift `"1`–`"9` along and go to `"1`, and smaller deletes go to `"-`.
+    pub fn store(&mut self, name: Option<char>, register: Register, delete: bool) -> Result<(), Report> {
+        let name = match name {
+            Some('_') => return Ok(()),
+            Some('"') | None if !delete => '0',
+            Some('"') | None if register.kind == RangeKind::Linewise || register.text.contains('\n') => {
+                for n in (1..9).rev() {
+                    if let Some(older) = self.stored.remove(&char::from_digit(n, 10).unwrap()) {
+                        self.stored.insert(char::from_digit(n + 1, 10).unwrap(), older);
+                    }
+                }
+                '1'
+            }
+            Some('"') | None => '-',
+            Some(name) if name.is_ascii_uppercase() => {
+                let name = name.to_ascii_lowercase();
+                match self.stored.get_mut(&name) {
+                    Some(existing) => existing.append(register),
+                    None => {
+                        self.stored.insert(name, register);
+                    }
+                }
+                self.unnamed = name;
+                return Ok(());
+            }
+            Some(name) if name.is_ascii_lowercase() || name.is_ascii_digit() || "-+*".contains(name) => name,
+            Some(name) => return Err(eyre!("Register {} is read-only", name)),
+        };
+
+        self.stored.insert(name, register);
+        self.unnamed = name;
+        Ok(())
+    }
+}
+
+impl Editor {
+    /// `"{name}`: selects the register used by the next yank, delete or put.
+    pub(crate) fn select_register(&mut self, name: char) -> Result<(), Report> {
+        if !name.is_ascii_alphanumeric() && !"\"-_.:%+*".contains(name) {
+            return Err(eyre!("Invalid register name: {}", name));
+        }
+
+        self.selected_register = Some(name);
+        Ok(())
+    }
+
+    /// Stores `register` in the selected register, or the unnamed one. `delete` tells deletes from yanks, which go to
+    /// different registers when none is selected. The clipboard registers also send the text to the system clipboard,
+    /// and a failure to reach it is shown without losing the text or failing the yank or delete.
+    pub(crate) fn store_register(&mut self, register: Register, delete: bool) -> Result<(), Report> {
+        let name = self.selected_register.take();
+        let text = name.and_then(Selection::from_register).map(|selection| (selection, register.text.clone()));
+        self.registers.store(name, register, delete)?;
+
+        if let Some((selection, text)) = text {
+            if let Err(err) = self.clipboard.copy(&mut self.display.out, selection, &text) {
+                self.error = Some(err.to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Contents of register `name`, including the read-only ones the editor maintains.
+    pub(crate) fn read_register(&self, name: char) -> Result<Register, Report> {
+        let charwise = |text: &str| Ok(Register { text: text.to_string(), kind: RangeKind::Charwise });
+        let name = name.to_ascii_lowercase();
+        match name {
+            '.' => charwise(&self.registers.last_insert),
+            ':' => charwise(&self.registers.last_command),
+            '%' => charwise(self.filename.as_deref().ok_or_else(|| eyre!("No file name"))?),
+            '"' => self.read_register(self.registers.unnamed),
+            '+' | '*' => self.read_clipboard(name),
+            _ => self.registers.stored.get(&name).cloned().ok_or_else(|| eyre!("Nothing in register {}", name)),
+        }
+    }
+
+    /// Text of the system clipboard behind register `name`. Text the editor put there itself keeps its original kind,
+    /// anything else is linewise when it ends with a newline. Without a provider to paste from, the text last copied
+    /// from the editor is used.
+    fn read_clipboard(&self, name: char) -> Result<Register, Report> {
+        let stored = self.registers.stored.get(&name);
+        let text = match (self.clipboard.paste(Selection::from_register(name).unwrap()), stored) {
+            (Ok(text), _) => text,
+            (Err(_), Some(stored)) => return Ok(stored.clone()),
+            (Err(err), None) => return Err(err),
+        };
+
+        match stored {
+            Some(stored) if stored.text == text => Ok(stored.clone()),
+            _ => {
+                let kind = if text.ends_with('\n') { RangeKind::Linewise } else { RangeKind::Charwise };
+                Ok(Register { text, kind })
+            }
+        }
+    }
+
+    /// `p` and `P`: puts the selected register `count` times after or before the cursor. Linewise text goes on new
+    /// lines below or above, and blockwise text into the following lines at the cursor column.
+    pub(crate) fn put(&mut self, before: bool) -> Result<(), Report> {
+        let name = self.selected_register.take().unwrap_or('"');
+        let Register { text, kind } = self.read_register(name)?;
+        let count = self.repeats();
+        let (x, y) = self.display.cursor.position;
+        if text.len().checked_mul(count).is_none_or(|len| len > MAX_PUT_LEN) {
+            return Err(eyre!("Resulting text too long"));
+        }
+
+        match kind {
+            RangeKind::Charwise => {
+                let column = if before || self.buffer.line_len(y) == 0 { x } else { x + 1 };
+                let index = self.buffer.line_to_char(y) + column;
+                let text = text.repeat(count);
+                self.insert_text(index, &text);
+                self.set_cursor(motion::to_position(&self.buffer, index + text.chars().count().saturating_sub(1)));
+            }
+            RangeKind::Linewise => {
+                let text = text.repeat(count);
+                let line = if before {
+                    self.insert_text(self.buffer.line_to_char(y), &text);
+                    y
+                } else {
+                    let line_end = self.buffer.line_to_char(y) + self.buffer.line_len(y);
+                    self.insert_text(line_end, &format!("\n{}", text.strip_suffix('\n').unwrap_or(&text)));
+                    y + 1
+                };
+                self.set_cursor((motion::first_non_blank_column(&self.buffer, line), line));
+            }
+            RangeKind::Blockwise => {
+                let column = if before || self.buffer.line_len(y) == 0 { x } else { x + 1 };
+                for (i, row) in text.split('\n').enumerate() {
+                    let line = y + i;
+                    if line == self.buffer.len_lines() {
+                        self.insert_text(self.buffer.len_chars(), "\n");
+                    }
+
+                    let line_len = self.buffer.line_len(line);
+                    let padding = " ".repeat(column.saturating_sub(line_len));
+                    let index = self.buffer.line_to_char(line) + column.min(line_len);
+                    self.insert_text(index, &format!("{}{}", padding, row.repeat(count)));
+                }
+                self.set_cursor((column, y));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `:registers`, listing the registers in `names`, or all of them, in the same shape as Vim.
+    pub(crate) fn list_registers(&self, names: &str) -> String {
+        let mut list = vec!["Type Name Content".to_string()];
+        for name in LISTED.chars().filter(|&name| names.is_empty() || names.contains(name)) {
+            let Ok(register) = self.read_register(name) else {
+                continue;
+            };
+            if register.text.is_empty() {
+                continue;
+            }
+
+            let kind = match register.kind {
+                RangeKind::Charwise => 'c',
+                RangeKind::Linewise => 'l',
+                RangeKind::Blockwise => 'b',
+            };
+            let content = register.text.replace('\n', "^J").chars().take(70).collect::<String>();
+            list.push(format!("  {}  \"{}   {}", kind, name, content));
+        }
+
+        list.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::clipboard::{Clipboard, ClipboardProvider};
+
+    fn register(text: &str, kind: RangeKind) -> Register {
+        Register { text: text.to_string(), kind }
+    }
+
+    fn text(registers: &Registers, name: char) -> Option<&str> {
+        registers.stored.get(&name).map(|register| register.text.as_str())
+    }
+
+    #[test]
+    fn deletes_rotate_numbered_registers() {
+        let mut registers = Registers::new();
+        for n in 0..10 {
+            registers.store(None, register(&format!("line {}\n", n), RangeKind::Linewise), true).unwrap();
+        }
+        assert_eq!(text(&registers, '1'), Some("line 9\n"));
+        assert_eq!(text(&registers, '9'), Some("line 1\n"));
+        assert_eq!(registers.unnamed, '1');
+
+        // Deletes within a line go to `"-` and leave the numbered registers alone, unlike ones across lines.
+        registers.store(None, register("word", RangeKind::Charwise), true).unwrap();
+        assert_eq!((text(&registers, '-'), text(&registers, '1')), (Some("word"), Some("line 9\n")));
+        assert_eq!(registers.unnamed, '-');
+        registers.store(Some('"'), register("a\nb", RangeKind::Charwise), true).unwrap();
+        assert_eq!((text(&registers, '1'), text(&registers, '2')), (Some("a\nb"), Some("line 9\n")));
+
+        // Yanks and deletes into a named register leave them alone too.
+        registers.store(None, register("yank", RangeKind::Charwise), false).unwrap();
+        registers.store(Some('a'), register("named\n", RangeKind::Linewise), true).unwrap();
+        assert_eq!((text(&registers, '0'), text(&registers, '1')), (Some("yank"), Some("a\nb")));
+        assert_eq!(registers.unnamed, 'a');
+    }
+
+    #[test]
+    fn uppercase_appends() {
+        let mut registers = Registers::new();
+        registers.store(Some('A'), register("one", RangeKind::Charwise), false).unwrap();
+        registers.store(Some('A'), register("two", RangeKind::Charwise), false).unwrap();
+        assert_eq!(text(&registers, 'a'), Some("onetwo"));
+
+        registers.store(Some('A'), register("three\n", RangeKind::Linewise), false).unwrap();
+        assert_eq!(text(&registers, 'a'), Some("onetwo\nthree\n"));
+        assert!(registers.stored[&'a'].kind == RangeKind::Linewise);
+    }
+
+    #[test]
+    fn black_hole_and_read_only_registers() {
+        let mut registers = Registers::new();
+        registers.store(Some('_'), register("gone", RangeKind::Charwise), true).unwrap();
+        assert!(registers.stored.is_empty());
+        assert!(registers.store(Some('.'), register("text", RangeKind::Charwise), false).is_err());
+        assert!(registers.store(Some('%'), register("text", RangeKind::Charwise), false).is_err());
+    }
+
+    #[test]
+    fn put() {
+        let typed = |text: &str, keys: &str| {
+            let mut editor = Editor::with_text(text);
+            editor.type_keys(keys);
+            editor.text()
+        };
+        assert_eq!(typed("one\ntwo", "yyjp"), "one\ntwo\none");
+        assert_eq!(typed("one\ntwo", "yyjP"), "one\none\ntwo");
+        assert_eq!(typed("one\ntwo", "\"ayyj\"_dd\"ap"), "one\none");
+        assert_eq!(typed("one\ntwo", "\"ayyjdd\"a3p"), "one\none\none\none");
+        assert_eq!(typed("abc", "dlp"), "bac");
+        assert_eq!(typed("abc", "yl2P"), "aaabc");
+        assert_eq!(typed("one two", "dwwdd\"1p"), "\ntwo");
+    }
+
+    #[test]
+    fn put_too_long_fails() {
+        for keys in ["yy99999999999p", "yl18446744073709551615P", "<C-v>jy99999999999p"] {
+            let mut editor = Editor::with_text("one\ntwo");
+            editor.type_keys(keys);
+            assert_eq!(editor.error.as_deref(), Some("Resulting text too long"));
+            assert_eq!(editor.text(), "one\ntwo");
+        }
+    }
+
+    #[test]
+    fn failed_copy_keeps_the_register() {
+        struct Unreachable;
+
+        impl ClipboardProvider for Unreachable {
+            fn copy(&mut self, _: Selection, _: &str) -> Result<(), Report> {
+                Err(eyre!("No clipboard"))
+            }
+
+            fn paste(&self, _: Selection) -> Result<String, Report> {
+                Err(eyre!("No clipboard"))
+            }
+        }
+
+        let mut editor = Editor::with_text("one\ntwo");
+        editor.clipboard = Clipboard::new(false, Some(Box::new(Unreachable)));
+        editor.type_keys("\"+yy");
+        assert_eq!(editor.error.as_deref(), Some("No clipboard"));
+        editor.type_keys("jdd");
+        assert_eq!(editor.read_register('+').unwrap().text, "one\n");
+        assert_eq!(editor.text(), "one");
+    }
+}
@@ -0,0 +1,111 @@
+use color_eyre::{eyre::eyre, Report};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::editor::{Editor, Mode};
+
+/// Keys of the change in progress and of the last complete change, which `.` replays.
+#[derive(Default)]
+pub(crate) struct DotRepeat {
+    pub(crate) keys: Vec<KeyEvent>,
+    /// Whether the keys so far edited the buffer.
+    pub(crate) edited: bool,
+    last: Vec<KeyEvent>,
+    last_count: Option<usize>,
+}
+
+impl Editor {
+    pub(crate) fn record_change_key(&mut self, key: KeyEvent) {
+        self.dot_repeat.keys.push(key);
+    }
+
+    /// Ends the change in progress once the editor is back in Normal mode with nothing pending. The keys typed since
+    /// it last was become the last change if they edited the buffer, with a leading count kept apart so `.` can
+    /// replace it. Command-line edits are not repeated.
+    pub(crate) fn finish_change(&mut self) {
+        let at_rest = self.mode == Mode::NORMAL
+            && self.keymap.is_empty()
+            && !self.keymap.has_count()
+            && self.char_argument.is_none()
+            && self.operator.is_none()
+            && self.selected_register.is_none();
+        if !at_rest || self.dot_repeat.keys.is_empty() {
+            return;
+        }
+
+        let keys = std::mem::take(&mut self.dot_repeat.keys);
+        if !std::mem::take(&mut self.dot_repeat.edited) || keys[0].code == KeyCode::Char(':') {
+            return;
+        }
+
+        let digits = keys.iter().map_while(|key| match key.code {
+            KeyCode::Char(c) => c.to_digit(10).map(|digit| digit as usize),
+            _ => None,
+        });
+        let count_len = match keys[0].code {
+            KeyCode::Char('0') => 0,
+            _ => digits.clone().count(),
+        };
+
+        self.dot_repeat.last_count =
+            digits.take(count_len).reduce(|count, digit| count.saturating_mul(10).saturating_add(digit));
+        self.dot_repeat.last = keys[count_len..].to_vec();
+    }
+
+    /// `.`: replays the last change at the cursor, with the count given to `.` instead of the original one.
+    pub(crate) fn repeat_change(&mut self) -> Result<(), Report> {
+        if self.dot_repeat.last.is_empty() {
+            return Err(eyre!("No change to repeat"));
+        }
+
+        let count = self.count.or(self.dot_repeat.last_count);
+        let mut keys = count
+            .map_or(String::new(), |count| count.to_string())
+            .chars()
+            .map(|c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE))
+            .collect::<Vec<_>>();
+        keys.extend_from_slice(&self.dot_repeat.last);
+
+        self.feed_keys(keys, true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn repeats_operators_with_their_count() {
+        let text = "a b c d e f g h";
+        assert_eq!(typed(text, "3dw.").text(), "g h");
+        // A count given to `.` replaces the original one.
+        assert_eq!(typed(text, "3dw2.").text(), "f g h");
+        assert_eq!(typed("one\ntwo\nthree\nfour", "dd.").text(), "three\nfour");
+        assert_eq!(typed("one two", "d$.").text(), "");
+        // Counts past `u32::MAX` are kept whole.
+        assert_eq!(typed("one\ntwo", "5000000000ddu").dot_repeat.last_count, Some(5_000_000_000));
+        assert_eq!(typed("one\ntwo", "18446744073709551615ddu.").text(), "");
+    }
+
+    #[test]
+    fn repeats_changes_with_the_inserted_text() {
+        assert_eq!(typed("one two three", "ciwX<Esc>w.").text(), "X X three");
+        assert_eq!(typed("one two three", "ciwX<Esc>ww.").text(), "X two X");
+        assert_eq!(typed("a\nb", "A!<Esc>j.").text(), "a!\nb!");
+        assert_eq!(typed("ab", "ix<Esc>.").text(), "xxab");
+    }
+
+    #[test]
+    fn skips_what_does_not_edit() {
+        // Motions, yanks and undo do not replace the last change.
+        assert_eq!(typed("a b c d", "dwwyw.").text(), "b d");
+        assert_eq!(typed("a b c d", "dwuw.").text(), "a c d");
+        assert!(typed("a", ".").error.is_some());
+    }
+}
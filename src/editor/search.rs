@@ -0,0 +1,416 @@
+use color_eyre::{eyre::eyre, Report};
+use regex::{Regex, RegexBuilder};
+use std::mem::take;
+use std::ops::Range;
+
+use crate::editor::motion::{self, CharClass, Motion, Position};
+use crate::editor::{Editor, Mode};
+use crate::util::Rope;
+
+/// Last search, repeated by `n` and `N`, and the prompt of the one being typed.
+#[derive(Default)]
+pub(crate) struct Search {
+    pub(crate) pattern: Option<String>,
+    pub(crate) forward: bool,
+    pub(crate) prompt: Option<SearchPrompt>,
+    /// Match of the pattern typed so far, highlighted until the prompt is closed.
+    pub(crate) incremental: Option<Range<usize>>,
+}
+
+/// `/` or `?` prompt being typed on the command line, with what to restore when it is closed.
+pub(crate) struct SearchPrompt {
+    pub(crate) forward: bool,
+    mode: Mode,
+    origin: Position,
+    count: Option<usize>,
+    /// Register selected for an operator waiting for the search, which typing the pattern would otherwise clear.
+    register: Option<char>,
+}
+
+/// Compiles a search pattern. Matching ignores case unless the pattern has an uppercase letter outside of an escape
+/// such as `\S`; `\c` anywhere in it forces ignoring case and `\C` matching it.
+pub(crate) fn compile(pattern: &str) -> Result<Regex, Report> {
+    let ignore_case = if pattern.contains("\\c") {
+        true
+    } else if pattern.contains("\\C") {
+        false
+    } else {
+        let mut escaped = false;
+        !pattern.chars().any(|c| {
+            let uppercase = !escaped && c.is_uppercase();
+            escaped = !escaped && c == '\\';
+            uppercase
+        })
+    };
+
+    RegexBuilder::new(&pattern.replace("\\c", "").replace("\\C", ""))
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .build()
+        .map_err(|_| eyre!("Invalid pattern: {}", pattern))
+}
+
+/// Splits `text` at the first `delimiter` not escaped with a backslash, dropping the backslash of escaped ones.
+pub(crate) fn split_delimited(text: &str, delimiter: char) -> (String, Option<&str>) {
+    let mut part = String::new();
+    let mut chars = text.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            _ if c == delimiter => return (part, Some(&text[i + c.len_utf8()..])),
+            '\\' => match chars.next() {
+                Some((_, next)) if next == delimiter => part.push(next),
+                Some((_, next)) => {
+                    part.push('\\');
+                    part.push(next);
+                }
+                None => part.push('\\'),
+            },
+            _ => part.push(c),
+        }
+    }
+
+    (part, None)
+}
+
+/// Lines searched at a time. A match starting in one block can still run this many lines into the next.
+const SEARCH_BLOCK: usize = 256;
+
+/// Text of lines `first..end`, with the byte index it starts at.
+fn block_text(buffer: &Rope, first: usize, end: usize) -> (usize, String) {
+    let start = buffer.line_to_char(first);
+    (buffer.char_to_byte(start), buffer.slice(start..buffer.line_to_char(end)))
+}
+
+/// Char range in the buffer of a match in text starting at byte `start`.
+fn char_range(buffer: &Rope, start: usize, found: regex::Match) -> Range<usize> {
+    buffer.byte_to_char(start + found.start())..buffer.byte_to_char(start + found.end())
+}
+
+/// First match starting at char `from` or after it on lines before `stop`, looking a block of lines at a time rather
+/// than through the whole buffer.
+fn find_forward(buffer: &Rope, regex: &Regex, from: usize, stop: usize) -> Option<Range<usize>> {
+    let mut line = buffer.char_to_line(from);
+    while line < stop.min(buffer.len_lines()) {
+        let (start, text) = block_text(buffer, line, line + 2 * SEARCH_BLOCK);
+        let at = buffer.char_to_byte(from).saturating_sub(start);
+
+        // A match starting past this block is found again with the next one, after any that starts before it.
+        if let Some(found) = regex.find_at(&text, at).map(|found| char_range(buffer, start, found)) {
+            if found.start < buffer.line_to_char(line + SEARCH_BLOCK) || line + SEARCH_BLOCK >= buffer.len_lines() {
+                return Some(found);
+            }
+        }
+        line += SEARCH_BLOCK;
+    }
+    None
+}
+
+/// Last match starting before char `limit` on line `stop` or after it, looking a block of lines at a time backwards.
+fn find_backward(buffer: &Rope, regex: &Regex, limit: usize, stop: usize) -> Option<Range<usize>> {
+    let (mut limit, mut last) = (limit, buffer.char_to_line(limit.min(buffer.len_chars())));
+    loop {
+        let first = last.saturating_sub(SEARCH_BLOCK - 1).max(stop);
+        let (start, text) = block_text(buffer, first, last + 1 + SEARCH_BLOCK);
+        let found = regex
+            .find_iter(&text)
+            .map(|found| char_range(buffer, start, found))
+            .take_while(|found| found.start < limit)
+            .last();
+        if found.is_some() || first == stop {
+            return found;
+        }
+
+        (limit, last) = (buffer.line_to_char(first), first - 1);
+    }
+}
+
+/// Char range of the first match after char `from`, or the last one before it, and whether the search wrapped
+/// around the end of the buffer to find it.
+pub(crate) fn find(buffer: &Rope, regex: &Regex, from: usize, forward: bool) -> Option<(Range<usize>, bool)> {
+    let line = buffer.char_to_line(from.min(buffer.len_chars()));
+    if forward {
+        let next = (from + 1).min(buffer.len_chars());
+        match find_forward(buffer, regex, next, usize::MAX) {
+            Some(found) => Some((found, false)),
+            None => Some((find_forward(buffer, regex, 0, line + 1)?, true)),
+        }
+    } else {
+        match find_backward(buffer, regex, from, 0) {
+            Some(found) => Some((found, false)),
+            None => Some((find_backward(buffer, regex, usize::MAX, line)?, true)),
+        }
+    }
+}
+
+/// Columns of `line` covered by the char range `found`, including its line break when the match continues past it.
+pub(crate) fn match_columns(buffer: &Rope, found: &Range<usize>, line: usize) -> Option<Range<usize>> {
+    let start = buffer.line_to_char(line);
+    let end = start + buffer.line_len(line);
+    if found.is_empty() || found.start > end || found.end <= start {
+        return None;
+    }
+
+    Some(found.start.max(start) - start..found.end.min(end + 1) - start)
+}
+
+impl Editor {
+    /// `/` and `?`: opens a prompt for a pattern to search for forwards or backwards. The search is a motion, so it
+    /// can also extend a selection or give a pending operator its target.
+    pub(crate) fn open_search(&mut self, forward: bool) {
+        self.search.prompt = Some(SearchPrompt {
+            forward,
+            mode: self.mode,
+            origin: self.display.cursor.position,
+            count: self.count,
+            register: self.selected_register,
+        });
+        self.command.clear();
+        self.mode = Mode::COMMAND;
+    }
+
+    /// Moves the cursor to the first match of the pattern typed so far and highlights it, as `incsearch` does.
+    pub(crate) fn update_incremental_search(&mut self) {
+        let Some(prompt) = &self.search.prompt else {
+            return;
+        };
+        let (forward, origin) = (prompt.forward, prompt.origin);
+
+        self.search.incremental = None;
+        self.set_cursor(origin);
+        if self.command.is_empty() {
+            return;
+        }
+        let Ok(regex) = compile(&self.command) else {
+            return;
+        };
+
+        if let Some((found, _)) = find(&self.buffer, &regex, motion::to_char(&self.buffer, origin), forward) {
+            self.set_cursor(motion::to_position(&self.buffer, found.start));
+            self.search.incremental = Some(found);
+        }
+    }
+
+    /// Closes the prompt, returning to the mode and cursor position it was opened from.
+    fn close_search(&mut self) -> Option<SearchPrompt> {
+        let prompt = self.search.prompt.take()?;
+        self.search.incremental = None;
+        self.command.clear();
+        self.mode = prompt.mode;
+        self.set_cursor(prompt.origin);
+        Some(prompt)
+    }
+
+    /// `<Esc>` in the prompt: abandons the search along with any operator waiting for it.
+    pub(crate) fn cancel_search(&mut self) {
+        if self.close_search().is_some() {
+            self.cancel_operator();
+        }
+    }
+
+    /// `<CR>` in the prompt: searches for the typed pattern, or for the last one when nothing was typed.
+    pub(crate) fn confirm_search(&mut self) -> Result<(), Report> {
+        let pattern = take(&mut self.command);
+        let Some(prompt) = self.close_search() else {
+            return Ok(());
+        };
+
+        if !pattern.is_empty() {
+            self.search.pattern = Some(pattern);
+        }
+        self.search.forward = prompt.forward;
+        self.count = prompt.count;
+        self.selected_register = prompt.register;
+        self.search_next(true)
+    }
+
+    /// `n` and `N`: repeats the last search in the same or the opposite direction.
+    pub(crate) fn search_next(&mut self, same_direction: bool) -> Result<(), Report> {
+        self.search_from(self.search.forward == same_direction, None)
+    }
+
+    /// `*` and `#`: searches forwards or backwards for the keyword under or after the cursor as a whole word.
+    pub(crate) fn search_word(&mut self, forward: bool) -> Result<(), Report> {
+        let (x, y) = self.display.cursor.position;
+        let line = self.buffer.line(y).chars().collect::<Vec<_>>();
+        let is_keyword = |i: &usize| motion::char_class(line[*i], false) == CharClass::Word;
+
+        let found = (x..line.len()).find(is_keyword).ok_or_else(|| eyre!("No identifier under cursor"))?;
+        let start = (0..=found).rev().take_while(is_keyword).last().unwrap_or(found);
+        let end = (start..line.len()).take_while(is_keyword).last().map_or(start, |i| i + 1);
+
+        let word = line[start..end].iter().collect::<String>();
+        self.search.pattern = Some(format!("\\b{}\\b", regex::escape(&word)));
+        self.search.forward = forward;
+
+        // Searching backwards starts at the beginning of the word, so the word under the cursor is not found first.
+        self.search_from(forward, Some((start, y)))
+    }
+
+    /// Moves to the next match of the last pattern, searching from `start` instead of the cursor when given.
+    fn search_from(&mut self, forward: bool, start: Option<Position>) -> Result<(), Report> {
+        let pattern = self.search.pattern.clone().ok_or_else(|| eyre!("No previous regular expression"))?;
+        let regex = compile(&pattern)?;
+
+        let (mut found, mut wrapped) = (true, false);
+        let result = self.motion(|buffer, position, count| {
+            let mut from = motion::to_char(buffer, start.unwrap_or(position));
+            for _ in 0..count.unwrap_or(1) {
+                let Some((next, wrap)) = find(buffer, &regex, from, forward) else {
+                    found = false;
+                    return None;
+                };
+                (from, wrapped) = (next.start, wrapped || wrap);
+            }
+            Motion::exclusive(motion::to_position(buffer, from))
+        });
+
+        if !found {
+            return Err(eyre!("Pattern not found: {}", pattern));
+        }
+        if wrapped {
+            self.error = Some(
+                match forward {
+                    true => "search hit BOTTOM, continuing at TOP",
+                    false => "search hit TOP, continuing at BOTTOM",
+                }
+                .to_string(),
+            );
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn searches_forwards_and_backwards() {
+        let text = "one two\nthree two\nfour";
+        assert_eq!(typed(text, "/two<CR>").display.cursor.position, (4, 0));
+        assert_eq!(typed(text, "/two<CR>n").display.cursor.position, (6, 1));
+        assert_eq!(typed(text, "/two<CR>nN").display.cursor.position, (4, 0));
+        assert_eq!(typed(text, "2/two<CR>").display.cursor.position, (6, 1));
+        // `n` keeps the direction of `?`, and an empty pattern repeats the last one.
+        assert_eq!(typed(text, "G?two<CR>").display.cursor.position, (6, 1));
+        assert_eq!(typed(text, "G?two<CR>n").display.cursor.position, (4, 0));
+        assert_eq!(typed(text, "/two<CR>/<CR>").display.cursor.position, (6, 1));
+
+        let editor = typed(text, "/zzz<CR>");
+        assert_eq!(editor.error.as_deref(), Some("Pattern not found: zzz"));
+        assert_eq!(editor.display.cursor.position, (0, 0));
+        assert_eq!(typed(text, "n").error.as_deref(), Some("No previous regular expression"));
+    }
+
+    #[test]
+    fn wraps_around_the_buffer() {
+        let text = "one two\nthree two\nfour";
+        let editor = typed(text, "/two<CR>nn");
+        assert_eq!(editor.display.cursor.position, (4, 0));
+        assert_eq!(editor.error.as_deref(), Some("search hit BOTTOM, continuing at TOP"));
+
+        let editor = typed(text, "?four<CR>");
+        assert_eq!(editor.display.cursor.position, (0, 2));
+        assert_eq!(editor.error.as_deref(), Some("search hit TOP, continuing at BOTTOM"));
+        assert_eq!(typed(text, "/two<CR>").error, None);
+    }
+
+    #[test]
+    fn ignores_case_unless_the_pattern_has_uppercase() {
+        assert_eq!(typed("x Foo foo", "/foo<CR>").display.cursor.position, (2, 0));
+        assert_eq!(typed("x Foo foo", "/\\Cfoo<CR>").display.cursor.position, (6, 0));
+        assert_eq!(typed("x foo Foo", "/Foo<CR>").display.cursor.position, (6, 0));
+        assert_eq!(typed("x foo Foo", "/FOO\\c<CR>").display.cursor.position, (2, 0));
+        // Uppercase escapes such as `\S` do not count.
+        assert_eq!(typed("x foo Foo", "/\\Soo<CR>").display.cursor.position, (2, 0));
+    }
+
+    #[test]
+    fn searches_for_the_word_under_the_cursor() {
+        let text = "foo bar foo\nfoobar foo";
+        assert_eq!(typed(text, "*").display.cursor.position, (8, 0));
+        assert_eq!(typed(text, "**").display.cursor.position, (7, 1));
+        assert_eq!(typed(text, "*n").display.cursor.position, (7, 1));
+        assert_eq!(typed(text, "#").display.cursor.position, (7, 1));
+        assert_eq!(typed(text, "#N").display.cursor.position, (0, 0));
+        // On blanks, the next keyword on the line is taken.
+        assert_eq!(typed("  foo x foo", "*").display.cursor.position, (8, 0));
+        assert_eq!(typed("  ", "*").error.as_deref(), Some("No identifier under cursor"));
+    }
+
+    #[test]
+    fn highlights_while_typing_and_restores_on_escape() {
+        let mut editor = typed("one two\nthree", "/thr");
+        assert_eq!(editor.display.cursor.position, (0, 1));
+        assert_eq!(editor.search.incremental, Some(8..11));
+
+        editor.type_keys("<Esc>");
+        assert_eq!(editor.display.cursor.position, (0, 0));
+        assert_eq!(editor.search.incremental, None);
+        assert!(editor.mode == Mode::NORMAL);
+        assert_eq!(editor.search.pattern, None);
+    }
+
+    #[test]
+    fn is_a_motion_for_operators() {
+        assert_eq!(typed("one two three", "d/thr<CR>").text(), "three");
+        assert_eq!(typed("one two three", "wd?one<CR>").text(), "two three");
+        // Ending at the start of a line, the deletion stops at the end of the previous one, or takes whole lines
+        // when it started at the first non-blank.
+        assert_eq!(typed("one\ntwo\nthree", "ld/thr<CR>").text(), "o\nthree");
+        assert_eq!(typed("one\ntwo\nthree", "d/thr<CR>").text(), "three");
+        assert_eq!(typed("one two", "d/zzz<CR>").text(), "one two");
+
+        let editor = typed("foo bar baz", "\"ad/baz<CR>");
+        assert_eq!(editor.text(), "baz");
+        assert_eq!(editor.read_register('a').unwrap().text, "foo bar ");
+    }
+
+    /// Same search over the whole text at once, as `find` did before looking a block at a time.
+    fn find_whole(text: &str, regex: &Regex, from: usize, forward: bool) -> Option<(Range<usize>, bool)> {
+        let to_char = |byte: usize| text[..byte].chars().count();
+        let from = text.char_indices().nth(from).map_or(text.len(), |(i, _)| i);
+        let (found, wrapped) = if forward {
+            let next = text[from..].chars().next().map_or(text.len(), |c| from + c.len_utf8());
+            match regex.find_at(text, next) {
+                Some(found) => (found, false),
+                None => (regex.find(text)?, true),
+            }
+        } else {
+            match regex.find_iter(text).take_while(|found| found.start() < from).last() {
+                Some(found) => (found, false),
+                None => (regex.find_iter(text).last()?, true),
+            }
+        };
+        Some((to_char(found.start())..to_char(found.end()), wrapped))
+    }
+
+    #[test]
+    fn matches_whole_text_search_across_blocks() {
+        let text = (0..1000).map(|i| format!("line é{}", i)).collect::<Vec<_>>().join("\n");
+        let buffer = Rope::new(&text);
+        let len = buffer.len_chars();
+
+        for pattern in ["line é7\\d\\d$", "é25[56]\\nline", "^line é9", "é0$", "nothing", "é999$"] {
+            let regex = compile(pattern).unwrap();
+            for from in [0, 1, 2000, 2571, 2572, 6000, len - 1, len] {
+                for forward in [true, false] {
+                    assert_eq!(
+                        find(&buffer, &regex, from, forward),
+                        find_whole(&text, &regex, from, forward),
+                        "{} from {} forward {}",
+                        pattern,
+                        from,
+                        forward
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,225 @@
+use color_eyre::{eyre::eyre, Report};
+use crossterm::event::{self, Event};
+use std::{
+    fs,
+    io::{self, Write},
+    ops::RangeInclusive,
+    process::{Command, Stdio},
+    thread,
+};
+
+use crate::editor::motion;
+use crate::editor::substitute::plural;
+use crate::editor::Editor;
+
+fn shell_name() -> String {
+    std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string())
+}
+
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new(shell_name());
+    shell.arg("-c").arg(command);
+    shell
+}
+
+/// Runs `command` with `input` as its standard input, returning its standard output without the final line break,
+/// along with its standard error. When it fails, its standard error or exit status is the error instead.
+fn capture(command: &str, input: Option<String>) -> Result<(String, String), Report> {
+    let mut child = shell(command)
+        .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| eyre!("Cannot execute shell {}: {}", shell_name(), err))?;
+
+    // Written from another thread, so a command that writes output before reading all of its input cannot fill the
+    // pipe and wait on us forever.
+    let writer = input.zip(child.stdin.take()).map(|(input, mut stdin)| {
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        })
+    });
+    let output = child.wait_with_output()?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+
+    let stdout = String::from_utf8_lossy(&output.stdout).replace("\r\n", "\n");
+    let stderr = String::from_utf8_lossy(&output.stderr).trim_end().to_string();
+    if !output.status.success() {
+        return Err(match stderr.is_empty() {
+            true => eyre!("Shell returned {}", output.status.code().unwrap_or(-1)),
+            false => eyre!("{}", stderr),
+        });
+    }
+
+    Ok((stdout.strip_suffix('\n').unwrap_or(&stdout).to_string(), stderr))
+}
+
+impl Editor {
+    /// `:!{cmd}`: runs `cmd` on the terminal, with the editor's screen put away until a key is pressed after it.
+    /// `:{range}!{cmd}`: filters `lines` through `cmd`, replacing them with its output.
+    pub(crate) fn shell_command(&mut self, lines: Option<RangeInclusive<usize>>, command: &str) -> Result<(), Report> {
+        let command = self.expand_shell_command(command)?;
+        match lines {
+            Some(lines) => self.filter_lines(lines, &command),
+            None => self.run_interactive(&command),
+        }
+    }
+
+    /// `:[line]r[ead] [file]` and `:[line]r !{cmd}`: puts the lines of `file`, the current file by default, or the
+    /// output of `cmd`, below `line`.
+    pub(crate) fn read_lines(&mut self, line: usize, args: &str) -> Result<(), Report> {
+        let text = match args.strip_prefix('!') {
+            Some(command) => {
+                let command = self.expand_shell_command(command.trim_start())?;
+                let (output, errors) = capture(&command, None)?;
+                self.error = (!errors.is_empty()).then_some(errors);
+                output
+            }
+            None => {
+                let filename = match args {
+                    "" => self.filename.clone().ok_or_else(|| eyre!("No file name"))?,
+                    _ => args.to_string(),
+                };
+                let text = fs::read_to_string(&filename).map_err(|_| eyre!("Can't open file {}", filename))?;
+                let text = text.replace("\r\n", "\n");
+                text.strip_suffix('\n').unwrap_or(&text).to_string()
+            }
+        };
+
+        if !text.is_empty() {
+            self.put_lines(line + 1, &text);
+            self.set_cursor((motion::first_non_blank_column(&self.buffer, line + 1), line + 1));
+        }
+        Ok(())
+    }
+
+    /// Replaces `%` in `command` with the file name and `!` with the previous shell command, and remembers the
+    /// result as the previous one. A backslash before either keeps it as it is.
+    fn expand_shell_command(&mut self, command: &str) -> Result<String, Report> {
+        if command.is_empty() {
+            return Err(eyre!("Argument required"));
+        }
+
+        let mut expanded = String::new();
+        let mut chars = command.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if matches!(chars.peek(), Some('%' | '!')) => expanded.extend(chars.next()),
+                '%' => expanded
+                    .push_str(self.filename.as_deref().ok_or_else(|| eyre!("No file name to substitute for '%'"))?),
+                '!' => expanded.push_str(
+                    self.registers.last_shell_command.as_deref().ok_or_else(|| eyre!("No previous command"))?,
+                ),
+                _ => expanded.push(c),
+            }
+        }
+
+        self.registers.last_shell_command = Some(expanded.clone());
+        Ok(expanded)
+    }
+
+    /// Replaces `lines` with the output of `command` given them as input. A failing command leaves them as they
+    /// are, showing what it wrote to its standard error.
+    fn filter_lines(&mut self, lines: RangeInclusive<usize>, command: &str) -> Result<(), Report> {
+        let range = self.line_range(&lines);
+        let (output, errors) = capture(command, Some(format!("{}\n", self.buffer.slice(range.clone()))))?;
+
+        let (first, last) = (*lines.start(), *lines.end());
+        if output.is_empty() {
+            self.remove_lines(first, last);
+        } else if output != self.buffer.slice(range.clone()) {
+            self.replace_text(range, &output);
+        }
+
+        let line = first.min(self.buffer.len_lines() - 1);
+        self.set_cursor((motion::first_non_blank_column(&self.buffer, line), line));
+        self.error = Some(match errors.is_empty() {
+            true => format!("{} filtered", plural(last - first + 1, "line")),
+            false => errors,
+        });
+        Ok(())
+    }
+
+    /// Runs `command` on the terminal left as it was before the editor started, then waits for a key so its output
+    /// can be read before the editor's screen comes back.
+    fn run_interactive(&mut self, command: &str) -> Result<(), Report> {
+        self.suspend()?;
+
+        let mut out = io::stdout();
+        let _ = writeln!(out);
+        let status = shell(command).status();
+        if let Ok(status) = &status {
+            if !status.success() {
+                let _ = writeln!(out, "\nShell returned {}", status.code().unwrap_or(-1));
+            }
+        }
+        let _ = write!(out, "\nPress ENTER to continue");
+        let _ = out.flush();
+
+        // Raw mode makes any single key continue, without waiting for a line.
+        crossterm::terminal::enable_raw_mode()?;
+        while !matches!(event::read()?, Event::Key(_)) {}
+        self.resume()?;
+
+        status.map_err(|err| eyre!("Cannot execute shell {}: {}", shell_name(), err))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn filters_lines() {
+        let text = "c\nb\na\nd";
+        assert_eq!(typed(text, ":1,3!sort<CR>").text(), "a\nb\nc\nd");
+        assert_eq!(typed(text, ":%!sort -r<CR>").text(), "d\nc\nb\na");
+        assert_eq!(typed(text, ":2,3!sort<CR>").error.as_deref(), Some("2 lines filtered"));
+        // Output with fewer lines replaces all of them, and no output at all removes them.
+        assert_eq!(typed(text, ":%!head -n 1<CR>").text(), "c");
+        assert_eq!(typed(text, ":2,3!true<CR>").text(), "c\nd");
+        // A failing command leaves the lines alone.
+        let editor = typed(text, ":%!echo oops >&2; exit 3<CR>");
+        assert_eq!((editor.text().as_str(), editor.error.as_deref()), (text, Some("oops")));
+    }
+
+    #[test]
+    fn filter_operator() {
+        let text = "c\nb\na\nd";
+        assert_eq!(typed(text, "!jsort<CR>").text(), "b\nc\na\nd");
+        assert_eq!(typed(text, "2!!sort<CR>").text(), "b\nc\na\nd");
+        assert_eq!(typed(text, "!Gsort<CR>").text(), "a\nb\nc\nd");
+        // `!` after a filter is the previous command.
+        assert_eq!(typed(text, "!jsort -r<CR>jj!j!<CR>").text(), "c\nb\nd\na");
+    }
+
+    #[test]
+    fn reads_command_output() {
+        assert_eq!(typed("one\ntwo", ":r !echo x; echo y<CR>").text(), "one\nx\ny\ntwo");
+        assert_eq!(typed("one\ntwo", ":$r !echo x<CR>").text(), "one\ntwo\nx");
+        assert_eq!(typed("one\ntwo", ":r !true<CR>").text(), "one\ntwo");
+        assert!(typed("one", ":r !exit 1<CR>").error.is_some());
+        assert!(typed("one", ":r !<CR>").error.is_some());
+    }
+
+    #[test]
+    fn reads_files() {
+        let path = std::env::temp_dir().join(format!("crate-read-{}", std::process::id()));
+        std::fs::write(&path, "x\r\ny\n").unwrap();
+        let editor = typed("one\ntwo", &format!(":r {}<CR>", path.display()));
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(editor.text(), "one\nx\ny\ntwo");
+        assert!(typed("one", ":r /nonexistent/file<CR>").error.is_some());
+        assert!(typed("one", ":r<CR>").error.is_some());
+    }
+}
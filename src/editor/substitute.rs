@@ -0,0 +1,382 @@
+use color_eyre::{eyre::eyre, Report};
+use regex::{Captures, Regex};
+use std::ops::{Range, RangeInclusive};
+
+use crate::editor::motion;
+use crate::editor::search;
+use crate::editor::Editor;
+
+/// Replacement of the last `:s`, and the substitution waiting for an answer in confirm mode.
+#[derive(Default)]
+pub(crate) struct Substitute {
+    pub(crate) replacement: Option<String>,
+    pending: Option<Substitution>,
+}
+
+/// Progress of a `:s` command through its range.
+struct Substitution {
+    pattern: String,
+    regex: Regex,
+    replacement: String,
+    global: bool,
+    confirm: bool,
+
+    /// Where to look for the next match. Lines added by replacements containing line breaks move `last_line`.
+    line: usize,
+    column: usize,
+    last_line: usize,
+    /// Column right after an empty match, where another empty match is not allowed.
+    after_empty: Option<usize>,
+    /// Columns of the match being handled and the text replacing it.
+    current: Option<(Range<usize>, String)>,
+
+    matches: usize,
+    substitutions: usize,
+    changed_lines: usize,
+    last_changed: Option<usize>,
+}
+
+#[derive(Clone, Copy)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+/// Text replacing one match. `&` and `\0` insert the whole match, `\1` to `\9` its groups and `\r` or `\n` a line
+/// break, while `\u` and `\l` change the case of the next char and `\U` and `\L` that of everything up to `\E`.
+fn expand(replacement: &str, captures: &Captures) -> String {
+    fn push(result: &mut String, text: &str, once: &mut Option<Case>, until_end: Option<Case>) {
+        for c in text.chars() {
+            match once.take().or(until_end) {
+                Some(Case::Upper) => result.extend(c.to_uppercase()),
+                Some(Case::Lower) => result.extend(c.to_lowercase()),
+                None => result.push(c),
+            }
+        }
+    }
+
+    let mut result = String::new();
+    let (mut once, mut until_end) = (None, None);
+    let mut chars = replacement.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => push(&mut result, &captures[0], &mut once, until_end),
+            '\\' => match chars.next() {
+                Some(digit @ '0'..='9') => {
+                    let group = captures.get(digit as usize - '0' as usize).map_or("", |group| group.as_str());
+                    push(&mut result, group, &mut once, until_end);
+                }
+                Some('n' | 'r') => result.push('\n'),
+                Some('t') => result.push('\t'),
+                Some('u') => once = Some(Case::Upper),
+                Some('l') => once = Some(Case::Lower),
+                Some('U') => until_end = Some(Case::Upper),
+                Some('L') => until_end = Some(Case::Lower),
+                Some('E' | 'e') => until_end = None,
+                Some(c) => push(&mut result, c.encode_utf8(&mut [0; 4]), &mut once, until_end),
+                None => result.push('\\'),
+            },
+            c => push(&mut result, c.encode_utf8(&mut [0; 4]), &mut once, until_end),
+        }
+    }
+
+    result
+}
+
+pub(crate) fn plural(count: usize, word: &str) -> String {
+    match count {
+        1 => format!("1 {}", word),
+        _ => format!("{} {}s", count, word),
+    }
+}
+
+impl Editor {
+    /// `:[range]s/{pattern}/{replacement}/[flags] [count]`: replaces matches of `pattern` on `lines`. With `g` every
+    /// match on a line is replaced instead of the first, with `c` each one is confirmed, and `i` or `I` ignore or
+    /// match case whatever the pattern. An empty pattern reuses the last search, and a bare `:s` repeats the last
+    /// substitution.
+    pub(crate) fn substitute(&mut self, lines: RangeInclusive<usize>, args: &str) -> Result<(), Report> {
+        let args = args.trim_start();
+        let (pattern, replacement, flags) = match args.chars().next() {
+            Some(delimiter) if !delimiter.is_alphanumeric() && !matches!(delimiter, '\\' | '"' | '|' | '&') => {
+                let (pattern, rest) = search::split_delimited(&args[delimiter.len_utf8()..], delimiter);
+                let (replacement, flags) = match rest.map(|rest| search::split_delimited(rest, delimiter)) {
+                    Some((replacement, flags)) => (replacement, flags.unwrap_or("")),
+                    None => (String::new(), ""),
+                };
+                (pattern, replacement, flags)
+            }
+            _ => {
+                let replacement = self.substitute.replacement.clone();
+                (String::new(), replacement.ok_or_else(|| eyre!("No previous substitute regular expression"))?, args)
+            }
+        };
+
+        let pattern = match pattern.is_empty() {
+            true => self.search.pattern.clone().ok_or_else(|| eyre!("No previous regular expression"))?,
+            false => pattern,
+        };
+        self.search.pattern = Some(pattern.clone());
+        self.substitute.replacement = Some(replacement.clone());
+
+        let flags = flags.trim_start();
+        let (flags, count) = flags.split_at(flags.find(|c| !"&gciI".contains(c)).unwrap_or(flags.len()));
+        let lines = match count.trim() {
+            "" => lines,
+            trailing => {
+                let count = trailing.parse::<usize>().ok().filter(|&count| count > 0);
+                let count = count.ok_or_else(|| eyre!("Trailing characters: {}", trailing))?;
+                *lines.end()..=lines.end().saturating_add(count - 1).min(self.buffer.len_lines() - 1)
+            }
+        };
+
+        let case = match (flags.contains('i'), flags.contains('I')) {
+            (true, _) => "\\c",
+            (_, true) => "\\C",
+            _ => "",
+        };
+        let regex = search::compile(&format!("{}{}", pattern, case))?;
+
+        self.substitute.pending = Some(Substitution {
+            pattern,
+            regex,
+            replacement,
+            global: flags.contains('g'),
+            confirm: flags.contains('c'),
+            line: *lines.start(),
+            column: 0,
+            last_line: *lines.end(),
+            after_empty: None,
+            current: None,
+            matches: 0,
+            substitutions: 0,
+            changed_lines: 0,
+            last_changed: None,
+        });
+        self.continue_substitution()
+    }
+
+    /// Replaces matches until one needs confirming, then asks about it with the match highlighted.
+    fn continue_substitution(&mut self) -> Result<(), Report> {
+        while self.find_substitution() {
+            let Some(substitution) = &self.substitute.pending else {
+                break;
+            };
+
+            if substitution.confirm {
+                let (line, replacement) = (substitution.line, substitution.replacement.clone());
+                let columns = substitution.current.as_ref().map_or(0..0, |(columns, _)| columns.clone());
+
+                let start = self.buffer.line_to_char(line);
+                self.search.incremental = Some(start + columns.start..start + columns.end);
+                self.set_cursor((columns.start, line));
+                self.error = Some(format!("replace with {} (y/n/a/q/l)?", replacement));
+                self.await_char(|e, answer| e.answer_substitution(answer));
+                return Ok(());
+            }
+
+            self.replace_substitution(true);
+        }
+
+        self.finish_substitution()
+    }
+
+    /// Answer to the confirm prompt: `y` replaces the match, `n` skips it, `a` replaces it and all remaining ones,
+    /// `l` replaces it and stops, and `q` stops.
+    fn answer_substitution(&mut self, answer: char) -> Result<(), Report> {
+        match answer {
+            'y' | 'n' => self.replace_substitution(answer == 'y'),
+            'a' => {
+                if let Some(substitution) = &mut self.substitute.pending {
+                    substitution.confirm = false;
+                }
+                self.replace_substitution(true);
+            }
+            'l' => {
+                self.replace_substitution(true);
+                return self.finish_substitution();
+            }
+            'q' => return self.finish_substitution(),
+            _ => {
+                self.await_char(|e, answer| e.answer_substitution(answer));
+                return Ok(());
+            }
+        }
+
+        self.continue_substitution()
+    }
+
+    /// Looks for the next match in the range, storing it with the text that would replace it.
+    fn find_substitution(&mut self) -> bool {
+        let Some(substitution) = &mut self.substitute.pending else {
+            return false;
+        };
+
+        while substitution.line <= substitution.last_line {
+            let text = self.buffer.line(substitution.line);
+            let mut column = substitution.column;
+
+            while let Some((from, _)) = text.char_indices().chain([(text.len(), ' ')]).nth(column) {
+                let Some(captures) = substitution.regex.captures_at(&text, from) else {
+                    break;
+                };
+                let found = &captures[0];
+                let start = text[..captures.get(0).map_or(0, |found| found.start())].chars().count();
+
+                if found.is_empty() && substitution.after_empty == Some(start) {
+                    column = start + 1;
+                    continue;
+                }
+
+                let replacement = expand(&substitution.replacement, &captures);
+                substitution.current = Some((start..start + found.chars().count(), replacement));
+                substitution.matches += 1;
+                return true;
+            }
+
+            substitution.line += 1;
+            substitution.column = 0;
+            substitution.after_empty = None;
+        }
+
+        false
+    }
+
+    /// Replaces the current match, or leaves it when `replace` is false, and moves past it.
+    fn replace_substitution(&mut self, replace: bool) {
+        let Some(substitution) = &mut self.substitute.pending else {
+            return;
+        };
+        let Some((columns, replacement)) = substitution.current.take() else {
+            return;
+        };
+
+        let line = substitution.line;
+        let start = self.buffer.line_to_char(line);
+        let text = match replace {
+            true => replacement,
+            false => self.buffer.slice(start + columns.start..start + columns.end),
+        };
+
+        let breaks = text.matches('\n').count();
+        let end = match text.rfind('\n') {
+            Some(i) => text[i + 1..].chars().count(),
+            None => columns.start + text.chars().count(),
+        };
+        substitution.last_line += breaks;
+
+        if replace {
+            substitution.substitutions += 1;
+            if substitution.last_changed != Some(line) {
+                substitution.changed_lines += 1;
+            }
+            substitution.last_changed = Some(line + breaks);
+        }
+
+        if substitution.global {
+            (substitution.line, substitution.column) = (line + breaks, end);
+            substitution.after_empty = columns.is_empty().then_some(end);
+        } else {
+            (substitution.line, substitution.column) = (line + breaks + 1, 0);
+            substitution.after_empty = None;
+        }
+
+        if replace {
+            self.replace_text(start + columns.start..start + columns.end, &text);
+        }
+    }
+
+    /// Ends the substitution, leaving the cursor on the last changed line and reporting what was replaced. Any key
+    /// but a char ends confirm mode the same way.
+    pub(crate) fn finish_substitution(&mut self) -> Result<(), Report> {
+        let Some(substitution) = self.substitute.pending.take() else {
+            return Ok(());
+        };
+        self.search.incremental = None;
+        self.error = None;
+
+        if substitution.matches == 0 {
+            return Err(eyre!("Pattern not found: {}", substitution.pattern));
+        }
+
+        if let Some(line) = substitution.last_changed {
+            self.set_cursor((motion::first_non_blank_column(&self.buffer, line), line));
+        }
+        self.error = Some(format!(
+            "{} on {}",
+            plural(substitution.substitutions, "substitution"),
+            plural(substitution.changed_lines, "line")
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `replacement` expanded for the first match of `pattern` in `text`.
+    fn expanded(pattern: &str, text: &str, replacement: &str) -> String {
+        expand(replacement, &Regex::new(pattern).unwrap().captures(text).unwrap())
+    }
+
+    #[test]
+    fn expands_matches_and_groups() {
+        let words = r"(\w+) (\w+)";
+        assert_eq!(expanded(words, "hello world", r"\2 \1"), "world hello");
+        assert_eq!(expanded(words, "hello world", "[&]"), "[hello world]");
+        assert_eq!(expanded(words, "hello world", r"\0|\3|"), "hello world||");
+        assert_eq!(expanded(words, "hello world", r"\&\\\/"), r"&\/");
+        assert_eq!(expanded(words, "hello world", r"\1\r\2\t\"), "hello\nworld\t\\");
+    }
+
+    #[test]
+    fn expands_case_changes() {
+        let words = r"(\w+) (\w+)";
+        assert_eq!(expanded(words, "hello world", r"\u\1 \2"), "Hello world");
+        assert_eq!(expanded(words, "hello world", r"\U\1\E \2"), "HELLO world");
+        assert_eq!(expanded(words, "HELLO WORLD", r"\L\u&"), "Hello world");
+        assert_eq!(expanded(words, "HELLO WORLD", r"\l\1 \e\2"), "hELLO WORLD");
+    }
+
+    #[test]
+    fn substitutes_over_lines() {
+        let mut editor = Editor::with_text("a b a\nb a\na");
+
+        editor.substitute(0..=1, "/a/x/").unwrap();
+        assert_eq!(editor.buffer.to_string(), "x b a\nb x\na");
+        editor.substitute(0..=2, "/a/(&)/g").unwrap();
+        assert_eq!(editor.buffer.to_string(), "x b (a)\nb x\n(a)");
+
+        // Line breaks in the replacement add lines, and the range still ends on the line it did.
+        editor.substitute(0..=1, r"/ /\r/g").unwrap();
+        assert_eq!(editor.buffer.to_string(), "x\nb\n(a)\nb\nx\n(a)");
+
+        // A bare `:s` repeats the last substitution, and a count starts at the end of the range.
+        editor.substitute(2..=2, r"/\(a\)/a/").unwrap();
+        editor.substitute(4..=4, "&& 2").unwrap();
+        assert_eq!(editor.buffer.to_string(), "x\nb\na\nb\nx\na");
+        assert!(editor.substitute(0..=0, "/z/y/").is_err());
+
+        // The count runs to the last line however large it is, and anything else after the flags is an error.
+        editor.substitute(1..=1, "/./y/ 18446744073709551615").unwrap();
+        assert_eq!(editor.buffer.to_string(), "x\ny\ny\ny\ny\ny");
+        let error = editor.substitute(0..=0, "/x/z/ xyz").unwrap_err();
+        assert_eq!(error.to_string(), "Trailing characters: xyz");
+        assert_eq!(editor.substitute(0..=0, "/x/z/g 0").unwrap_err().to_string(), "Trailing characters: 0");
+    }
+
+    #[test]
+    fn confirms_each_match() {
+        let typed = |keys: &str| {
+            let mut editor = Editor::with_text("a a\na a");
+            editor.type_keys(keys);
+            editor.text()
+        };
+        assert_eq!(typed(":%s/a/x/gc<CR>ynyn"), "x a\nx a");
+        assert_eq!(typed(":%s/a/x/gc<CR>na"), "a x\nx x");
+        assert_eq!(typed(":%s/a/x/gc<CR>nl"), "a x\na a");
+        assert_eq!(typed(":%s/a/x/gc<CR>yq"), "x a\na a");
+        assert_eq!(typed(":%s/a/x/gc<CR>y<Esc>"), "x a\na a");
+    }
+}
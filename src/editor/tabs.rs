@@ -0,0 +1,229 @@
+use color_eyre::{eyre::eyre, Report};
+use std::mem::take;
+
+use crate::editor::window::Windows;
+use crate::editor::Editor;
+
+/// Tab pages other than the current one. The current tab page's windows are the editor's own, and are swapped with
+/// one of these to move to another tab page.
+#[derive(Default)]
+pub(crate) struct Tabs {
+    /// In order, with the current tab page left out from between `others[..current]` and `others[current..]`.
+    others: Vec<Windows>,
+    current: usize,
+}
+
+impl Tabs {
+    pub(crate) fn count(&self) -> usize {
+        self.others.len() + 1
+    }
+
+    /// Whether a window in a tab page other than the current one shows buffer `number`.
+    pub(crate) fn shows(&self, number: usize) -> bool {
+        self.others.iter().any(|windows| windows.shows(number))
+    }
+
+    /// Closes the windows showing buffer `number` in tab pages other than the current one, as when it is deleted.
+    pub(crate) fn forget_buffer(&mut self, number: usize, fallback: usize) {
+        for windows in &mut self.others {
+            windows.forget_buffer(number, fallback);
+        }
+    }
+}
+
+/// Tab page number that `:tabn`, `:tabp` and `:tabc` take as their argument, if any.
+pub(crate) fn number_argument(args: &str) -> Result<Option<usize>, Report> {
+    match args.split_whitespace().next() {
+        Some(number) => Ok(Some(number.parse::<usize>().map_err(|_| eyre!("Invalid argument: {}", number))?)),
+        None => Ok(None),
+    }
+}
+
+impl Editor {
+    /// `:tabnew [file]` and `:tabe[dit] [file]`: opens a tab page after the current one, with a single window editing
+    /// `file`, or a new empty buffer.
+    pub(crate) fn new_tab(&mut self, filename: Option<&str>) -> Result<(), Report> {
+        self.leave_window();
+        self.tabs.others.insert(self.tabs.current, take(&mut self.windows));
+        self.tabs.current += 1;
+        self.arrange_windows();
+
+        match filename {
+            Some(filename) => self.edit_file(Some(filename), false),
+            None => {
+                self.edit_new_buffer();
+                Ok(())
+            }
+        }
+    }
+
+    /// Makes tab page `index`, counting from 0, the current one, back in the window that was current in it.
+    fn switch_tab(&mut self, index: usize) {
+        if index == self.tabs.current {
+            return;
+        }
+
+        self.leave_window();
+        self.tabs.others.insert(self.tabs.current, take(&mut self.windows));
+        self.windows = self.tabs.others.remove(index);
+        self.tabs.current = index;
+        self.enter_window();
+    }
+
+    /// `gt` and `:tabn[ext]`: moves to the next tab page, wrapping around, or to tab page N given as a count.
+    pub(crate) fn next_tab(&mut self, number: Option<usize>) -> Result<(), Report> {
+        let index = match number {
+            Some(number) if number == 0 || number > self.tabs.count() => {
+                return Err(eyre!("Tab page {} does not exist", number))
+            }
+            Some(number) => number - 1,
+            None => (self.tabs.current + 1) % self.tabs.count(),
+        };
+        self.switch_tab(index);
+        Ok(())
+    }
+
+    /// `gT` and `:tabp[revious]`: moves back by `steps` tab pages, wrapping around.
+    pub(crate) fn previous_tab(&mut self, steps: usize) {
+        let count = self.tabs.count();
+        self.switch_tab((self.tabs.current + count - steps % count) % count);
+    }
+
+    /// `:tabc[lose] [N]`: closes tab page N, the current one by default, and moves to the one after it when it was
+    /// current, or else the one before. The buffers in its windows stay in the buffer list.
+    pub(crate) fn close_tab(&mut self, number: Option<usize>) -> Result<(), Report> {
+        if self.tabs.count() == 1 {
+            return Err(eyre!("Cannot close last tab page"));
+        }
+
+        let index = match number {
+            Some(number) if number == 0 || number > self.tabs.count() => {
+                return Err(eyre!("Tab page {} does not exist", number))
+            }
+            Some(number) => number - 1,
+            None => self.tabs.current,
+        };
+        if index != self.tabs.current {
+            self.tabs.others.remove(if index < self.tabs.current { index } else { index - 1 });
+            if index < self.tabs.current {
+                self.tabs.current -= 1;
+            }
+            self.arrange_windows();
+            return Ok(());
+        }
+
+        let next = self.tabs.current.min(self.tabs.others.len() - 1);
+        self.windows = self.tabs.others.remove(next);
+        self.tabs.current = next;
+        self.enter_window();
+        Ok(())
+    }
+
+    /// `:tabm[ove] [N]`: moves the current tab page to after tab page N, to the start for 0 and to the end by
+    /// default. `:tabm +N` and `:tabm -N` move it by N places.
+    pub(crate) fn move_tab(&mut self, args: &str) -> Result<(), Report> {
+        let invalid = || eyre!("Invalid argument: {}", args);
+        let number = |text: &str| text.parse::<usize>().map_err(|_| invalid());
+
+        let last = self.tabs.others.len();
+        self.tabs.current = match args.chars().next() {
+            Some('+') => self.tabs.current.checked_add(number(&args[1..])?).filter(|&index| index <= last),
+            Some('-') => self.tabs.current.checked_sub(number(&args[1..])?),
+            Some(_) => Some(number(args)?.min(last)),
+            None => Some(last),
+        }
+        .ok_or_else(invalid)?;
+        Ok(())
+    }
+
+    /// Labels of the tab pages for the tabline, with whether each is the current one, or none for a single tab page.
+    /// Each shows the file in its current window, after the number of windows when there are several and `+` when a
+    /// buffer in it is modified.
+    pub(crate) fn tab_labels(&self) -> Vec<(String, bool)> {
+        if self.tabs.count() == 1 {
+            return Vec::new();
+        }
+
+        let mut current = vec![self.buffers.current];
+        current.extend(self.windows.buffers());
+        let mut tabs = self.tabs.others.iter().map(|windows| (windows.buffers(), false)).collect::<Vec<_>>();
+        tabs.insert(self.tabs.current, (current, true));
+
+        tabs.into_iter()
+            .map(|(numbers, current)| {
+                let (filename, _) = self.buffer_state(numbers[0]);
+                let modified = numbers.iter().any(|&number| self.buffer_state(number).1);
+                let count = if numbers.len() > 1 { numbers.len().to_string() } else { String::new() };
+                let flag = if modified { "+" } else { "" };
+                let space = if count.is_empty() && !modified { "" } else { " " };
+                (format!(" {}{}{}{} ", count, flag, space, filename.unwrap_or("[No Name]")), current)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    /// Editor with tab pages editing the files `a`, `b` and `c`, which need not exist, after typing `keys`.
+    fn typed(keys: &str) -> Editor {
+        let mut editor = Editor::with_text("");
+        editor.type_keys(":e a<CR>:tabe b<CR>:tabe c<CR>");
+        editor.type_keys(keys);
+        editor
+    }
+
+    /// Files of the tab pages in order, with the current one in brackets.
+    fn tabs(editor: &Editor) -> String {
+        let labels = editor.tab_labels().into_iter().map(|(label, current)| match current {
+            true => format!("[{}]", label.trim()),
+            false => label.trim().to_string(),
+        });
+        labels.collect::<Vec<_>>().join(" ")
+    }
+
+    #[test]
+    fn moves_between_tab_pages() {
+        assert_eq!(tabs(&typed("")), "a b [c]");
+        assert_eq!(tabs(&typed("gt")), "[a] b c");
+        assert_eq!(tabs(&typed("gT")), "a [b] c");
+        assert_eq!(tabs(&typed("2gt")), "a [b] c");
+        assert_eq!(tabs(&typed("4gT")), "a [b] c");
+        assert_eq!(tabs(&typed(":tabn 1<CR>:tabn<CR>")), "a [b] c");
+        assert_eq!(tabs(&typed(":tabp 2<CR>")), "[a] b c");
+        assert_eq!(typed("4gt").error.as_deref(), Some("Tab page 4 does not exist"));
+        assert_eq!(tabs(&typed("gt:tabnew<CR>")), "a [[No Name]] b c");
+    }
+
+    #[test]
+    fn closes_tab_pages() {
+        assert_eq!(tabs(&typed(":tabc<CR>")), "a [b]");
+        assert_eq!(tabs(&typed("gt:tabc<CR>")), "[b] c");
+        assert_eq!(tabs(&typed(":tabc 1<CR>")), "b [c]");
+        assert_eq!(tabs(&typed(":tabc<CR>:tabc<CR>")), "");
+        assert_eq!(typed(":tabc<CR>:tabc<CR>:tabc<CR>").error.as_deref(), Some("Cannot close last tab page"));
+        assert!(typed(":tabc 0<CR>").error.is_some());
+        // Each tab page keeps its own windows.
+        let editor = typed("<C-w>sgt");
+        assert_eq!((editor.windows.count(), tabs(&editor)), (1, "[a] b 2 c".to_string()));
+    }
+
+    #[test]
+    fn moves_tab_pages() {
+        assert_eq!(tabs(&typed(":tabm 0<CR>")), "[c] a b");
+        assert_eq!(tabs(&typed(":tabm 1<CR>")), "a [c] b");
+        assert_eq!(tabs(&typed("gt:tabm<CR>")), "b c [a]");
+        // Past the last tab page is the end.
+        assert_eq!(tabs(&typed("gt:tabm 9<CR>")), "b c [a]");
+        assert_eq!(tabs(&typed("gt:tabm +2<CR>")), "b c [a]");
+        assert_eq!(tabs(&typed(":tabm -2<CR>")), "[c] a b");
+        assert_eq!(tabs(&typed(":tabm -1<CR>")), "a [c] b");
+
+        for args in ["+1", "-3", "x", "+", "-x", "+18446744073709551615"] {
+            let editor = typed(&format!(":tabm {}<CR>", args));
+            assert_eq!(editor.error, Some(format!("Invalid argument: {}", args)));
+            assert_eq!(tabs(&editor), "a b [c]");
+        }
+    }
+}
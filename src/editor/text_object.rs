@@ -0,0 +1,314 @@
+use std::ops::Range;
+
+use crate::editor::motion::{self, CharClass, Position};
+use crate::editor::operator::RangeKind;
+use crate::util::Rope;
+
+/// Char range selected by a text object, and whether operators treat it as whole lines.
+pub(crate) type Selection = (Range<usize>, RangeKind);
+
+/// Extends from item `at` over `count` runs of items of the same kind, as `iw` and `ip` do. With `around`, the
+/// blank run after each one is included too, or the one before when there is none after it.
+fn select_runs<K: Copy + PartialEq>(
+    len: usize,
+    at: usize,
+    count: Option<usize>,
+    around: bool,
+    blank: K,
+    kind: impl Fn(usize) -> K,
+) -> Range<usize> {
+    let run_end = |i: usize| (i..len).find(|&j| kind(j) != kind(i)).unwrap_or(len);
+    let mut start = (0..at).rev().find(|&j| kind(j) != kind(at)).map_or(0, |j| j + 1);
+    let on_blank = kind(at) == blank;
+
+    let mut end = at;
+    for _ in 0..count.unwrap_or(1) {
+        if end >= len {
+            break;
+        }
+
+        end = run_end(end);
+        if around && end < len && (on_blank || kind(end) == blank) {
+            end = run_end(end);
+        }
+    }
+
+    if around && !on_blank && kind(end - 1) != blank {
+        while start > 0 && kind(start - 1) == blank {
+            start -= 1;
+        }
+    }
+
+    start..end
+}
+
+/// `iw` and `aw`, or `iW` and `aW` when `big` is set. Blank runs between words count as words of their own.
+pub(crate) fn word(
+    buffer: &Rope,
+    (x, y): Position,
+    count: Option<usize>,
+    big: bool,
+    around: bool,
+) -> Option<Selection> {
+    let line = buffer.line(y).chars().collect::<Vec<_>>();
+    if line.is_empty() {
+        return None;
+    }
+
+    let at = x.min(line.len() - 1);
+    let run = select_runs(line.len(), at, count, around, CharClass::Blank, |i| motion::char_class(line[i], big));
+
+    let line_start = buffer.line_to_char(y);
+    Some((line_start + run.start..line_start + run.end, RangeKind::Charwise))
+}
+
+/// `is` and `as`: from the start of the sentence under the cursor to the start of the next one, without the
+/// whitespace in between for `is`.
+pub(crate) fn sentence(buffer: &Rope, position: Position, count: Option<usize>, around: bool) -> Option<Selection> {
+    let len = buffer.len_chars();
+    let cursor = motion::to_char(buffer, position);
+    let start = (0..=cursor).rev().find(|&i| motion::is_sentence_start(buffer, i)).unwrap_or(0);
+
+    let mut end = cursor;
+    for _ in 0..count.unwrap_or(1) {
+        end = (end + 1..len).find(|&i| motion::is_sentence_start(buffer, i)).unwrap_or(len);
+    }
+
+    if !around {
+        while end > start && buffer.char(end - 1).is_some_and(char::is_whitespace) {
+            end -= 1;
+        }
+    }
+
+    Some((start..end, RangeKind::Charwise))
+}
+
+/// `ip` and `ap`: the paragraph or run of empty lines under the cursor, and for `ap` the empty lines after it.
+pub(crate) fn paragraph(buffer: &Rope, (_x, y): Position, count: Option<usize>, around: bool) -> Option<Selection> {
+    let lines = select_runs(buffer.len_lines(), y, count, around, true, |line| buffer.line_len(line) == 0);
+    let (first, last) = (lines.start, lines.end - 1);
+
+    Some((buffer.line_to_char(first)..buffer.line_to_char(last) + buffer.line_len(last), RangeKind::Linewise))
+}
+
+/// `i"` and `a"` for any `quote` char. Quotes on the cursor line pair up from its start, and the pair the cursor is
+/// in or on is selected, or else the next one after it. `a"` includes the whitespace after the closing quote, or
+/// before the opening one when there is none after it.
+pub(crate) fn quote(buffer: &Rope, (x, y): Position, quote: char, around: bool) -> Option<Selection> {
+    let line = buffer.line(y).chars().collect::<Vec<_>>();
+    let quotes = (0..line.len()).filter(|&i| line[i] == quote && (i == 0 || line[i - 1] != '\\')).collect::<Vec<_>>();
+    let (open, close) = quotes.chunks_exact(2).find(|pair| x <= pair[1]).map(|pair| (pair[0], pair[1]))?;
+
+    let range = if around {
+        let end = (close + 1..line.len()).find(|&i| !line[i].is_whitespace()).unwrap_or(line.len());
+        let start = match end == close + 1 {
+            true => (0..open).rev().find(|&i| !line[i].is_whitespace()).map_or(0, |i| i + 1),
+            false => open,
+        };
+        start..end
+    } else {
+        open + 1..close
+    };
+
+    let line_start = buffer.line_to_char(y);
+    Some((line_start + range.start..line_start + range.end, RangeKind::Charwise))
+}
+
+/// `i(`, `a(` and the other bracket objects: the `count`th pair of `(open, close)` enclosing the cursor. When the
+/// brackets are on lines of their own, the inner object is the whole lines between them.
+pub(crate) fn bracket(
+    buffer: &Rope,
+    position: Position,
+    count: Option<usize>,
+    pair: (char, char),
+    around: bool,
+) -> Option<Selection> {
+    let cursor = motion::to_char(buffer, position);
+    let mut open = match buffer.char(cursor)? {
+        c if c == pair.1 => motion::find_unmatched(buffer, cursor.checked_sub(1)?, pair, false)?,
+        _ => motion::find_unmatched(buffer, cursor, pair, false)?,
+    };
+    for _ in 1..count.unwrap_or(1) {
+        open = motion::find_unmatched(buffer, open.checked_sub(1)?, pair, false)?;
+    }
+    let close = motion::find_unmatched(buffer, open + 1, pair, true)?;
+
+    if around {
+        return Some((open..close + 1, RangeKind::Charwise));
+    }
+
+    let close_line_start = buffer.line_to_char(buffer.char_to_line(close));
+    let close_on_own_line = (close_line_start..close).all(|i| buffer.char(i).is_some_and(char::is_whitespace));
+    if buffer.char(open + 1) == Some('\n') && close_on_own_line && close_line_start > open + 2 {
+        return Some((open + 2..close_line_start - 1, RangeKind::Linewise));
+    }
+
+    Some((open + 1..close, RangeKind::Charwise))
+}
+
+struct Tag {
+    range: Range<usize>,
+    name: String,
+    closing: bool,
+}
+
+/// Opening and closing tags in `text`, in order. Self-closing tags, comments and declarations are left out.
+fn parse_tags(text: &[char]) -> Vec<Tag> {
+    let mut tags = Vec::new();
+    let mut i = 0;
+    while i < text.len() {
+        if text[i] != '<' {
+            i += 1;
+            continue;
+        }
+
+        let Some(end) = (i + 1..text.len()).find(|&j| text[j] == '>') else {
+            break;
+        };
+
+        let closing = text[i + 1] == '/';
+        let name = text[i + 1 + closing as usize..end]
+            .iter()
+            .take_while(|&&c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+            .collect::<String>();
+
+        if name.is_empty() || text[end - 1] == '/' {
+            i += 1;
+            continue;
+        }
+
+        tags.push(Tag { range: i..end + 1, name, closing });
+        i = end + 1;
+    }
+
+    tags
+}
+
+/// Lines before and after the cursor first looked through for the tags of `it` and `at`, doubled until enough
+/// elements enclosing the cursor are found or the whole buffer is.
+const TAG_WINDOW: usize = 128;
+
+/// `it` and `at`: the `count`th XML/HTML element enclosing the cursor, with or without its tags.
+pub(crate) fn tag(buffer: &Rope, position: Position, count: Option<usize>, around: bool) -> Option<Selection> {
+    let cursor = motion::to_char(buffer, position);
+    let count = count.unwrap_or(1);
+
+    let mut window = TAG_WINDOW;
+    loop {
+        let first = position.1.saturating_sub(window);
+        let end = (position.1 + window + 1).min(buffer.len_lines());
+        let start = buffer.line_to_char(first);
+        let text = buffer.slice(start..buffer.line_to_char(end)).chars().collect::<Vec<_>>();
+
+        let mut open_tags: Vec<Tag> = Vec::new();
+        let mut enclosing = Vec::new();
+        for tag in parse_tags(&text) {
+            let range = start + tag.range.start..start + tag.range.end;
+            if !tag.closing {
+                open_tags.push(Tag { range, ..tag });
+            } else if let Some(index) = open_tags.iter().rposition(|open| open.name == tag.name) {
+                let open = open_tags.remove(index);
+                open_tags.truncate(index);
+                if open.range.start <= cursor && cursor < range.end {
+                    enclosing.push((open.range, range));
+                }
+            }
+        }
+
+        // Elements close from the inside out, so the innermost one enclosing the cursor comes first.
+        if let Some((open, close)) = enclosing.get(count - 1) {
+            let range = if around { open.start..close.end } else { open.end..close.start };
+            return Some((range, RangeKind::Charwise));
+        }
+        if first == 0 && end == buffer.len_lines() {
+            return None;
+        }
+        window *= 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    /// Text left by typing `keys` with the cursor at `column` of `line`.
+    fn typed(text: &str, (column, line): (usize, usize), keys: &str) -> String {
+        let mut editor = Editor::with_text(text);
+        editor.set_cursor((column, line));
+        editor.type_keys(keys);
+        editor.text()
+    }
+
+    #[test]
+    fn words() {
+        assert_eq!(typed("one two  three", (5, 0), "diw"), "one   three");
+        assert_eq!(typed("one two  three", (5, 0), "daw"), "one three");
+        assert_eq!(typed("one two  three", (5, 0), "d3iw"), "one ");
+        assert_eq!(typed("one two  three", (5, 0), "d2aw"), "one");
+        // On blanks, `iw` takes the blanks and `aw` the word after them too.
+        assert_eq!(typed("one two  three", (7, 0), "diw"), "one twothree");
+        assert_eq!(typed("one two  three", (7, 0), "daw"), "one two");
+        // At the end of a line `aw` takes the blanks before the word instead.
+        assert_eq!(typed("one two", (5, 0), "daw"), "one");
+        assert_eq!(typed("a.b c", (0, 0), "diW"), " c");
+        assert_eq!(typed("a.b c", (0, 0), "diw"), ".b c");
+    }
+
+    #[test]
+    fn sentences_and_paragraphs() {
+        assert_eq!(typed("One. Two three. Four.", (7, 0), "dis"), "One.  Four.");
+        assert_eq!(typed("One. Two three. Four.", (7, 0), "das"), "One. Four.");
+        assert_eq!(typed("a\nb\n\nc", (0, 1), "dip"), "\nc");
+        assert_eq!(typed("a\nb\n\nc", (0, 1), "dap"), "c");
+    }
+
+    #[test]
+    fn quotes() {
+        let text = r#"say "hello world" and 'x\'y'"#;
+        assert_eq!(typed(text, (7, 0), r#"di""#), r#"say "" and 'x\'y'"#);
+        assert_eq!(typed(text, (7, 0), r#"da""#), r#"say and 'x\'y'"#);
+        // Before the first pair on the line, the next pair is taken, and escaped quotes are skipped.
+        assert_eq!(typed(text, (0, 0), r#"di""#), r#"say "" and 'x\'y'"#);
+        assert_eq!(typed(text, (22, 0), "di'"), r#"say "hello world" and ''"#);
+        assert_eq!(typed("x 'a'", (0, 0), "da'"), "x");
+        assert_eq!(typed("no quotes", (0, 0), r#"di""#), "no quotes");
+    }
+
+    #[test]
+    fn brackets() {
+        let text = "f(a, (b + c), d)";
+        assert_eq!(typed(text, (7, 0), "di("), "f(a, (), d)");
+        assert_eq!(typed(text, (7, 0), "da("), "f(a, , d)");
+        assert_eq!(typed(text, (7, 0), "d2i("), "f()");
+        assert_eq!(typed(text, (7, 0), "2dab"), "f");
+        assert_eq!(typed(text, (11, 0), "di)"), "f(a, (), d)");
+        assert_eq!(typed(text, (2, 0), "di("), "f()");
+        assert_eq!(typed("[1, [2]]", (5, 0), "di["), "[1, []]");
+        assert_eq!(typed("x (a", (3, 0), "di("), "x (a");
+        // Brackets on lines of their own make the inner object the whole lines between them.
+        assert_eq!(typed("if {\n  a\n  b\n}", (2, 1), "di{"), "if {\n}");
+        assert_eq!(typed("if {\n  a\n  b\n}", (2, 1), "da{"), "if ");
+    }
+
+    #[test]
+    fn tags() {
+        let text = "<div><p>one <b>two</b></p><br/></div>";
+        assert_eq!(typed(text, (16, 0), "dit"), "<div><p>one <b></b></p><br/></div>");
+        assert_eq!(typed(text, (16, 0), "dat"), "<div><p>one </p><br/></div>");
+        assert_eq!(typed(text, (16, 0), "d2it"), "<div><p></p><br/></div>");
+        assert_eq!(typed(text, (16, 0), "d3at"), "");
+        // The cursor can be on the tags themselves, and self-closing tags are no elements.
+        assert_eq!(typed(text, (6, 0), "dit"), "<div><p></p><br/></div>");
+        assert_eq!(typed(text, (28, 0), "dit"), "<div></div>");
+        assert_eq!(typed("<a><b></a>", (4, 0), "dat"), "");
+        assert_eq!(typed("no tags", (0, 0), "dit"), "no tags");
+    }
+
+    #[test]
+    fn tags_far_from_the_cursor() {
+        // The element spans more lines than are first looked through, so the window has to grow to find it.
+        let text = format!("<div>\n{}</div>\nafter", "line\n".repeat(300));
+        assert_eq!(typed(&text, (0, 150), "dat"), "\nafter");
+        assert_eq!(typed(&text, (0, 150), "d2at"), text);
+    }
+}
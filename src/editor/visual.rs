@@ -0,0 +1,290 @@
+use color_eyre::{eyre::eyre, Report};
+use std::ops::{Range, RangeInclusive};
+
+use crate::editor::motion::{self, Position};
+use crate::editor::operator::{Operator, RangeKind};
+use crate::editor::register::Register;
+use crate::editor::{Editor, Mode};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum VisualKind {
+    Char,
+    Line,
+    Block,
+}
+
+/// Visual selection in progress, from `anchor` to the cursor.
+#[derive(Clone, Copy)]
+pub(crate) struct Visual {
+    pub(crate) kind: VisualKind,
+    pub(crate) anchor: Position,
+}
+
+/// Both ends of a Visual selection, as drawn by the renderer and reselected by `gv`.
+#[derive(Clone, Copy)]
+pub(crate) struct VisualArea {
+    pub(crate) kind: VisualKind,
+    pub(crate) anchor: Position,
+    pub(crate) cursor: Position,
+}
+
+/// Lines of a blockwise `c` waiting for the text typed on the first one, to repeat it on the others.
+pub(crate) struct BlockInsert {
+    lines: RangeInclusive<usize>,
+    column: usize,
+}
+
+impl VisualArea {
+    /// Ends of the selection in buffer order.
+    pub(crate) fn ordered(&self) -> (Position, Position) {
+        let (a, b) = (self.anchor, self.cursor);
+        if (a.1, a.0) <= (b.1, b.0) {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    pub(crate) fn lines(&self) -> RangeInclusive<usize> {
+        self.anchor.1.min(self.cursor.1)..=self.anchor.1.max(self.cursor.1)
+    }
+
+    /// Columns of `line` covered by the selection. A selection that continues past the end of the line also covers
+    /// its line break, one column after the last char.
+    pub(crate) fn columns(&self, line: usize, line_len: usize) -> Option<Range<usize>> {
+        if !self.lines().contains(&line) {
+            return None;
+        }
+
+        match self.kind {
+            VisualKind::Char => {
+                let (start, end) = self.ordered();
+                let from = if line == start.1 { start.0 } else { 0 };
+                let to = if line == end.1 { end.0 + 1 } else { line_len + 1 };
+                Some(from..to)
+            }
+            VisualKind::Line => Some(0..line_len + 1),
+            VisualKind::Block => {
+                let (from, to) = (self.anchor.0.min(self.cursor.0), self.anchor.0.max(self.cursor.0) + 1);
+                Some(from.min(line_len)..to.min(line_len))
+            }
+        }
+    }
+}
+
+impl Editor {
+    /// `v`, `V` and `<C-v>`: starts a selection of `kind`, switches an active one to it, or ends it when it already
+    /// is of that kind.
+    pub(crate) fn toggle_visual(&mut self, kind: VisualKind) {
+        match &mut self.visual {
+            Some(visual) if visual.kind != kind => visual.kind = kind,
+            Some(_) => self.exit_visual(),
+            None => {
+                self.visual = Some(Visual { kind, anchor: self.display.cursor.position });
+                self.mode = Mode::VISUAL;
+            }
+        }
+    }
+
+    /// Ends the selection, remembering it for `gv` and `'<,'>`.
+    pub(crate) fn exit_visual(&mut self) {
+        self.last_visual = self.visual_area();
+        self.visual = None;
+        if self.mode == Mode::VISUAL {
+            self.mode = Mode::NORMAL;
+        }
+    }
+
+    pub(crate) fn visual_area(&self) -> Option<VisualArea> {
+        let visual = self.visual?;
+        Some(VisualArea { kind: visual.kind, anchor: visual.anchor, cursor: self.display.cursor.position })
+    }
+
+    /// `gv`: selects the previous Visual area again.
+    pub(crate) fn reselect_visual(&mut self) -> Result<(), Report> {
+        let area = self.last_visual.ok_or_else(|| eyre!("No previous Visual area"))?;
+        self.visual = Some(Visual { kind: area.kind, anchor: area.anchor });
+        self.mode = Mode::VISUAL;
+        self.set_cursor(area.cursor);
+        Ok(())
+    }
+
+    /// `o` in Visual mode: moves the cursor to the other end of the selection.
+    pub(crate) fn swap_visual_ends(&mut self) {
+        if let Some(visual) = &mut self.visual {
+            let cursor = std::mem::replace(&mut visual.anchor, self.display.cursor.position);
+            self.set_cursor(cursor);
+        }
+    }
+
+    /// Selects the text of a text object, switching to linewise selection for linewise objects such as `ap`.
+    pub(crate) fn select_range(&mut self, range: Range<usize>, kind: RangeKind) {
+        let Some(visual) = &mut self.visual else {
+            return;
+        };
+
+        if kind == RangeKind::Linewise {
+            visual.kind = VisualKind::Line;
+        }
+        visual.anchor = motion::to_position(&self.buffer, range.start);
+        self.set_cursor(motion::to_position(&self.buffer, range.end.saturating_sub(1).max(range.start)));
+    }
+
+    /// Ends the selection and applies `operator` to it.
+    pub(crate) fn visual_operator(&mut self, operator: Operator) -> Result<(), Report> {
+        let Some(area) = self.visual_area() else {
+            return Ok(());
+        };
+        self.exit_visual();
+
+        let (start, end) = area.ordered();
+        match area.kind {
+            VisualKind::Char => {
+                let end = (motion::to_char(&self.buffer, end) + 1).min(self.buffer.len_chars());
+                self.apply_operator(operator, motion::to_char(&self.buffer, start)..end, RangeKind::Charwise)
+            }
+            VisualKind::Line => {
+                let (first, last) = (start.1, end.1);
+                let range =
+                    self.buffer.line_to_char(first)..self.buffer.line_to_char(last) + self.buffer.line_len(last);
+                self.apply_operator(operator, range, RangeKind::Linewise)
+            }
+            VisualKind::Block => {
+                let columns = area.anchor.0.min(area.cursor.0)..area.anchor.0.max(area.cursor.0) + 1;
+                self.apply_block_operator(operator, area.lines(), columns)
+            }
+        }
+    }
+
+    /// `J` in Visual mode, joining the selected lines, or the line with the next one when only one is selected.
+    pub(crate) fn visual_join(&mut self) -> Result<(), Report> {
+        let Some(area) = self.visual_area() else {
+            return Ok(());
+        };
+        self.exit_visual();
+
+        let lines = area.lines();
+        self.join_lines(*lines.start(), (*lines.end()).max(lines.start() + 1));
+        Ok(())
+    }
+
+    /// Applies `operator` to `columns` of every line in `lines`. Lines too short to reach the block are left alone.
+    fn apply_block_operator(
+        &mut self,
+        operator: Operator,
+        lines: RangeInclusive<usize>,
+        columns: Range<usize>,
+    ) -> Result<(), Report> {
+        let block_range = |editor: &Editor, line: usize| {
+            let (start, len) = (editor.buffer.line_to_char(line), editor.buffer.line_len(line));
+            start + columns.start.min(len)..start + columns.end.min(len)
+        };
+
+        let first = *lines.start();
+        match operator {
+            Operator::Yank | Operator::Delete | Operator::Change => {
+                let text = lines.clone().map(|line| self.buffer.slice(block_range(self, line))).collect::<Vec<_>>();
+                self.store_register(
+                    Register { text: text.join("\n"), kind: RangeKind::Blockwise },
+                    operator != Operator::Yank,
+                )?;
+
+                if operator != Operator::Yank {
+                    for line in lines.clone().rev() {
+                        self.remove_text(block_range(self, line));
+                    }
+                }
+                if operator == Operator::Change {
+                    self.block_insert = Some(BlockInsert { lines, column: columns.start });
+                    self.mode = Mode::INSERT;
+                }
+            }
+            Operator::ShiftRight | Operator::ShiftLeft | Operator::Filter => {
+                let range = self.buffer.line_to_char(first)..self.buffer.line_to_char(*lines.end());
+                return self.apply_operator(operator, range, RangeKind::Linewise);
+            }
+            Operator::Lowercase | Operator::Uppercase | Operator::ToggleCase => {
+                for line in lines {
+                    self.apply_operator(operator, block_range(self, line), RangeKind::Charwise)?;
+                }
+            }
+        }
+
+        self.set_cursor((columns.start, first));
+        Ok(())
+    }
+
+    /// Repeats the text typed after a blockwise `c` on the other lines of the block, when leaving Insert mode.
+    pub(crate) fn finish_block_insert(&mut self) {
+        let Some(BlockInsert { lines, column }) = self.block_insert.take() else {
+            return;
+        };
+
+        let (x, y) = self.display.cursor.position;
+        if y != *lines.start() || x <= column {
+            return;
+        }
+
+        let start = self.buffer.line_to_char(y);
+        let text = self.buffer.slice(start + column..start + x);
+        for line in lines.skip(1) {
+            if self.buffer.line_len(line) >= column {
+                self.insert_text(self.buffer.line_to_char(line) + column, &text);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    fn typed(text: &str, keys: &str) -> Editor {
+        let mut editor = Editor::with_text(text);
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn charwise_and_linewise() {
+        assert_eq!(typed("hello world", "lvlld").text(), "ho world");
+        assert_eq!(typed("one\ntwo\nthree", "lvjd").text(), "oo\nthree");
+        assert_eq!(typed("one\ntwo\nthree", "jVd").text(), "one\nthree");
+        assert_eq!(typed("one\ntwo\nthree", "Vjd").text(), "three");
+        assert_eq!(typed("one\ntwo\nthree", "Vj>").text(), "    one\n    two\nthree");
+        assert_eq!(typed("hello", "vll~").text(), "HELlo");
+        assert_eq!(typed("Hello", "vllU").text(), "HELlo");
+        assert_eq!(typed("hello world", "vllcX<Esc>").text(), "Xlo world");
+        assert_eq!(typed("one\ntwo\nthree", "VjJ").text(), "one two\nthree");
+        assert_eq!(typed("one\ntwo\nthree", "vJ").text(), "one two\nthree");
+
+        let editor = typed("hello world", "wvey");
+        assert_eq!(editor.read_register('"').unwrap().text, "world");
+        assert_eq!(editor.text(), "hello world");
+    }
+
+    #[test]
+    fn switching_ending_and_reselecting() {
+        // `V` switches a charwise selection to linewise and `o` moves the cursor to its other end.
+        assert_eq!(typed("one\ntwo\nthree", "lvjVd").text(), "three");
+        assert_eq!(typed("hello world", "lvllohd").text(), "o world");
+        assert_eq!(typed("hello world", "vl<Esc>dl").text(), "hllo world");
+        assert_eq!(typed("hello world", "vl<Esc>wgvd").text(), "llo world");
+        assert!(typed("hello", "gv").error.is_some());
+    }
+
+    #[test]
+    fn blockwise() {
+        let text = "abcd\nij\nefgh\nklmn";
+        assert_eq!(typed(text, "l<C-v>jjld").text(), "ad\ni\neh\nklmn");
+        assert_eq!(typed(text, "l<C-v>jjlU").text(), "aBCd\niJ\neFGh\nklmn");
+
+        let editor = typed(text, "jj<C-v>jly");
+        assert_eq!(editor.read_register('"').unwrap().text, "ef\nkl");
+
+        // The text typed after `c` on the first line is repeated on the others, but not on lines too short for the
+        // block.
+        assert_eq!(typed(text, "l<C-v>jjjlcXY<Esc>").text(), "aXYd\niXY\neXYh\nkXYn");
+        assert_eq!(typed("abcd\n\nefgh", "l<C-v>jjlcX<Esc>").text(), "aXd\n\neXh");
+    }
+}
@@ -0,0 +1,412 @@
+use color_eyre::{eyre::eyre, Report};
+use std::collections::HashMap;
+
+use crate::editor::motion::Position;
+use crate::editor::Editor;
+use crate::util::display::{WindowView, GUTTER_WIDTH};
+use crate::util::layout::{Layout, Rect};
+use crate::util::statusline::StatusInfo;
+
+/// A window other than the current one. The current window's buffer, cursor and scroll offset are the editor's own,
+/// and are swapped with one of these to move to another window.
+pub(crate) struct Window {
+    buffer: usize,
+    cursor: Position,
+    offset: (usize, usize),
+}
+
+/// Windows of a tab page, numbered in the order they were opened. Numbers are not reused. In a tab page other than
+/// the current one, its current window is among the others too.
+pub(crate) struct Windows {
+    layout: Layout,
+    others: HashMap<usize, Window>,
+    current: usize,
+    /// Window that was current before, which `<C-w>p` goes back to.
+    previous: Option<usize>,
+    last_id: usize,
+}
+
+impl Default for Windows {
+    fn default() -> Self {
+        Self { layout: Layout::Window(1), others: HashMap::new(), current: 1, previous: None, last_id: 1 }
+    }
+}
+
+impl Windows {
+    pub(crate) fn count(&self) -> usize {
+        self.layout.windows().len()
+    }
+
+    /// Whether a window besides the current one shows buffer `number`.
+    pub(crate) fn shows(&self, number: usize) -> bool {
+        self.others.values().any(|window| window.buffer == number)
+    }
+
+    /// Buffers shown in the windows put away, the current window's first when it is among them.
+    pub(crate) fn buffers(&self) -> Vec<usize> {
+        let mut ids = self.layout.windows();
+        ids.sort_by_key(|&id| id != self.current);
+        ids.iter().filter_map(|id| self.others.get(id)).map(|window| window.buffer).collect()
+    }
+
+    /// Closes the windows of a tab page other than the current one that show buffer `number`, or has the last one
+    /// show buffer `fallback` instead.
+    pub(crate) fn forget_buffer(&mut self, number: usize, fallback: usize) {
+        for id in self.layout.windows() {
+            if self.others.get(&id).map(|window| window.buffer) != Some(number) {
+                continue;
+            }
+
+            if !self.layout.close(id) {
+                self.others.insert(id, Window { buffer: fallback, cursor: (0, 0), offset: (0, 0) });
+                continue;
+            }
+            self.others.remove(&id);
+            if self.previous == Some(id) {
+                self.previous = None;
+            }
+            if self.current == id {
+                self.current = self.layout.windows()[0];
+            }
+        }
+    }
+}
+
+/// Side of the current window that `<C-w>h`, `<C-w>j`, `<C-w>k` and `<C-w>l` move to.
+#[derive(Clone, Copy)]
+pub(crate) enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+impl Editor {
+    /// `:sp[lit] [file]` and `:vs[plit] [file]`: splits the current window in two showing the same buffer, and moves
+    /// to the new one, above it or to its left when `vertical` is set. `file` is then edited in the new window.
+    pub(crate) fn split_window(&mut self, vertical: bool, filename: Option<&str>) -> Result<(), Report> {
+        self.windows.last_id += 1;
+        let (old, new) = (self.windows.current, self.windows.last_id);
+
+        self.windows.layout.split(old, new, vertical);
+        self.windows.others.insert(old, self.current_window());
+        self.windows.previous = Some(old);
+        self.windows.current = new;
+        self.arrange_windows();
+
+        match filename {
+            Some(filename) => self.edit_file(Some(filename), false),
+            None => Ok(()),
+        }
+    }
+
+    fn current_window(&self) -> Window {
+        Window { buffer: self.buffers.current, cursor: self.display.cursor.position, offset: self.display.offset }
+    }
+
+    /// Makes window `id` the current one, editing its buffer where its cursor was left.
+    pub(crate) fn focus_window(&mut self, id: usize) {
+        if id == self.windows.current || !self.windows.others.contains_key(&id) {
+            return;
+        }
+
+        self.leave_window();
+        self.windows.previous = Some(self.windows.current);
+        self.windows.current = id;
+        self.enter_window();
+    }
+
+    /// Puts the current window away among the others, as before moving to another window or tab page.
+    pub(crate) fn leave_window(&mut self) {
+        let current = self.current_window();
+        self.windows.others.insert(self.windows.current, current);
+    }
+
+    /// Takes the window that is to be current from among the others, and edits its buffer where its cursor was left.
+    pub(crate) fn enter_window(&mut self) {
+        let Some(window) = self.windows.others.remove(&self.windows.current) else {
+            return;
+        };
+
+        self.show_buffer(window.buffer);
+        self.display.offset = window.offset;
+        self.arrange_windows();
+        self.set_cursor(window.cursor);
+    }
+
+    /// `:clo[se]` and `<C-w>c`: closes the current window, moving to the previous one, or else the first. Its buffer
+    /// stays in the buffer list. The last window of a tab page closes the tab page.
+    pub(crate) fn close_window(&mut self) -> Result<(), Report> {
+        if self.windows.count() == 1 {
+            return match self.tabs.count() {
+                1 => Err(eyre!("Cannot close last window")),
+                _ => self.close_tab(None),
+            };
+        }
+
+        let closing = self.windows.current;
+        let next = self.windows.previous.filter(|id| self.windows.others.contains_key(id));
+        let next = next.or_else(|| self.windows.layout.windows().into_iter().find(|&id| id != closing));
+        if let Some(next) = next {
+            self.focus_window(next);
+        }
+
+        self.windows.others.remove(&closing);
+        self.windows.layout.close(closing);
+        self.windows.previous = None;
+        self.arrange_windows();
+        Ok(())
+    }
+
+    /// `:q[uit][!]` and `<C-w>q`: closes the current window, or its tab page when it is the last window in it, or
+    /// quits the editor when it is the last one of all. Quitting with changes to a buffer not written needs `force`,
+    /// while closing a window keeps its buffer in the list with its changes.
+    pub(crate) fn quit_window(&mut self, force: bool) -> Result<(), Report> {
+        if self.windows.count() > 1 || self.tabs.count() > 1 {
+            return self.close_window();
+        }
+        if !force {
+            self.check_all_saved()?;
+        }
+        self.stop = true;
+        Ok(())
+    }
+
+    /// `:x[it][!] [file]` and `ZZ`: writes the buffer when it has changes, or to `file` when given, then quits the
+    /// window like `:q`.
+    pub(crate) fn exit_window(&mut self, filename: Option<&str>, force: bool) -> Result<(), Report> {
+        if self.history.modified() || filename.is_some() {
+            self.write_lines(0..=self.buffer.len_lines() - 1, filename, force)?;
+        }
+        self.quit_window(force)
+    }
+
+    /// `:qa[ll][!]`: quits the editor, which needs `force` when a buffer has changes not written.
+    pub(crate) fn quit_all(&mut self, force: bool) -> Result<(), Report> {
+        if !force {
+            self.check_all_saved()?;
+        }
+        self.stop = true;
+        Ok(())
+    }
+
+    /// Closes the windows besides the current one that show buffer `number`, as when it is deleted. In other tab
+    /// pages, one left with no other window shows the current buffer instead.
+    pub(crate) fn close_windows_showing(&mut self, number: usize) {
+        self.tabs.forget_buffer(number, self.buffers.current);
+
+        let closing = self.windows.others.iter().filter(|(_, window)| window.buffer == number).map(|(&id, _)| id);
+        for id in closing.collect::<Vec<_>>() {
+            self.windows.others.remove(&id);
+            self.windows.layout.close(id);
+            if self.windows.previous == Some(id) {
+                self.windows.previous = None;
+            }
+        }
+        self.arrange_windows();
+    }
+
+    /// `:on[ly]` and `<C-w>o`: closes every window but the current one.
+    pub(crate) fn only_window(&mut self) {
+        self.windows.others.clear();
+        self.windows.layout = Layout::Window(self.windows.current);
+        self.windows.previous = None;
+        self.arrange_windows();
+    }
+
+    /// `<C-w>w` and `<C-w>W`: moves to the next or previous window, wrapping around, or to window N in screen order
+    /// given as a count.
+    pub(crate) fn cycle_window(&mut self, forward: bool) {
+        let order = self.windows.layout.windows();
+        let index = order.iter().position(|&id| id == self.windows.current).unwrap_or(0);
+        let target = match self.count {
+            Some(count) => count.clamp(1, order.len()) - 1,
+            None if forward => (index + 1) % order.len(),
+            None => (index + order.len() - 1) % order.len(),
+        };
+        self.focus_window(order[target]);
+    }
+
+    /// `<C-w>p`: moves back to the window that was current before.
+    pub(crate) fn previous_window(&mut self) -> Result<(), Report> {
+        let previous = self.windows.previous.filter(|id| self.windows.others.contains_key(id));
+        self.focus_window(previous.ok_or_else(Editor::failed)?);
+        Ok(())
+    }
+
+    /// `<C-w>h`, `<C-w>j`, `<C-w>k` and `<C-w>l`: moves to the window next to the current one in `direction`, the
+    /// one beside the cursor when there are several, as many times as the count.
+    pub(crate) fn move_to_window(&mut self, direction: Direction) {
+        for _ in 0..self.repeats() {
+            let (windows, _) = self.arrange_windows();
+            let Some(&(_, rect)) = windows.iter().find(|(id, _)| *id == self.windows.current) else {
+                return;
+            };
+
+            let (x, y) = self.cursor_on_screen(rect);
+            // Windows side by side have a separator column between them, and stacked ones are right below each other.
+            let point = match direction {
+                Direction::Left => (rect.x.wrapping_sub(2), y),
+                Direction::Right => (rect.x + rect.width + 1, y),
+                Direction::Up => (x, rect.y.wrapping_sub(1)),
+                Direction::Down => (x, rect.y + rect.height),
+            };
+
+            match windows.iter().find(|(_, rect)| rect.contains(point)) {
+                Some(&(id, _)) => self.focus_window(id),
+                None => return,
+            }
+        }
+    }
+
+    fn cursor_on_screen(&self, rect: Rect) -> (usize, usize) {
+        let (x, y) = self.display.cursor.position;
+        let (offset_x, offset_y) = self.display.offset;
+        (
+            (rect.x + GUTTER_WIDTH + x.saturating_sub(offset_x)).min(rect.x + rect.width - 1),
+            rect.y + y.saturating_sub(offset_y),
+        )
+    }
+
+    /// `<C-w>+`, `<C-w>-`, `<C-w>>` and `<C-w><`: makes the current window taller or wider by `delta`, or shorter
+    /// or narrower when it is negative.
+    pub(crate) fn resize_window(&mut self, vertical: bool, delta: isize) {
+        self.windows.layout.resize(self.windows.current, vertical, delta);
+        self.arrange_windows();
+    }
+
+    /// `<C-w>_` and `<C-w>|`, and `:res[ize] N`: sets the height, or the width when `vertical` is set, of the current
+    /// window to `size`, or makes it as large as possible.
+    pub(crate) fn set_window_size(&mut self, vertical: bool, size: Option<usize>) {
+        let (windows, _) = self.arrange_windows();
+        let Some(&(_, rect)) = windows.iter().find(|(id, _)| *id == self.windows.current) else {
+            return;
+        };
+
+        // The status line counts towards the height, as in Vim.
+        let current = if vertical { rect.width } else { rect.height.saturating_sub(1) };
+        let target = size.unwrap_or(usize::MAX / 2);
+        self.resize_window(vertical, target as isize - current as isize);
+    }
+
+    /// `<C-w>=`: makes all windows about the same size.
+    pub(crate) fn equalize_windows(&mut self) {
+        self.windows.layout.equalize();
+        self.arrange_windows();
+    }
+
+    /// Lays the windows out on the screen, and scrolls the current one to keep the cursor in the area it gets above
+    /// its status line.
+    pub(crate) fn arrange_windows(&mut self) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let (windows, separators) = self.windows.layout.arrange(self.display.screen(self.tabs.count() > 1));
+        if let Some(&(_, rect)) = windows.iter().find(|(id, _)| *id == self.windows.current) {
+            self.display.set_area(rect);
+        }
+        (windows, separators)
+    }
+
+    /// Draws every window with its status line, the current one with the Visual selection or incremental search
+    /// match, and the tab pages when there are several.
+    pub(crate) fn render(&mut self) -> Result<(), Report> {
+        let (windows, separators) = self.arrange_windows();
+        let mode = self.mode_name();
+        let pending = if self.command_pending() { self.pending_keys.as_str() } else { "" };
+        let visual = self.visual_area();
+        let search = self.search.incremental.clone();
+        let command = self.command_line();
+        let (cursor, offset) = (self.display.cursor.position, self.display.offset);
+
+        let views = windows
+            .into_iter()
+            .filter_map(|(id, rect)| {
+                let current = id == self.windows.current;
+                let (number, cursor, offset) = match current {
+                    true => (self.buffers.current, cursor, offset),
+                    false => {
+                        self.windows.others.get(&id).map(|window| (window.buffer, window.cursor, window.offset))?
+                    }
+                };
+                let (buffer, filename, modified, readonly, line_ending) = match self.buffers.get(number) {
+                    Some(buffer) => {
+                        (&buffer.text, &buffer.filename, buffer.modified(), buffer.readonly, buffer.line_ending)
+                    }
+                    None => (&self.buffer, &self.filename, self.history.modified(), self.readonly, self.line_ending),
+                };
+                let info = StatusInfo {
+                    mode: if current { mode } else { "" },
+                    filename: filename.as_deref(),
+                    modified,
+                    readonly,
+                    line_ending: line_ending.name(),
+                    cursor,
+                    lines: buffer.len_lines(),
+                    pending: if current { pending } else { "" },
+                };
+
+                Some(WindowView {
+                    rect,
+                    buffer,
+                    cursor,
+                    offset,
+                    visual: visual.filter(|_| current),
+                    search: search.clone().filter(|_| current),
+                    status: Some(self.statusline.render(&info, rect.width)),
+                    current,
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let tabs = self.tab_labels();
+        self.display.render(&tabs, &views, &separators, &command, &self.error, &self.mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::Editor;
+
+    fn typed(keys: &str) -> Editor {
+        let mut editor = Editor::with_text("one\ntwo\nthree");
+        editor.type_keys(keys);
+        editor
+    }
+
+    #[test]
+    fn splits_and_moves_between_windows() {
+        let editor = typed("<C-w>s<C-w>v");
+        assert_eq!((editor.windows.count(), editor.windows.current), (3, 3));
+        assert_eq!(editor.windows.layout.windows(), vec![3, 2, 1]);
+
+        assert_eq!(typed("<C-w>s<C-w>v<C-w>l").windows.current, 2);
+        assert_eq!(typed("<C-w>s<C-w>v<C-w>j").windows.current, 1);
+        assert_eq!(typed("<C-w>s<C-w>v<C-w>j<C-w>k").windows.current, 3);
+        assert_eq!(typed("<C-w>s<C-w>v<C-w>w").windows.current, 2);
+        assert_eq!(typed("<C-w>s<C-w>v<C-w>W").windows.current, 1);
+        assert_eq!(typed("<C-w>s<C-w>v3<C-w>w").windows.current, 1);
+        assert_eq!(typed("<C-w>s<C-w>v<C-w>j<C-w>p").windows.current, 3);
+    }
+
+    #[test]
+    fn windows_keep_their_cursor() {
+        let mut editor = typed("<C-w>sjj<C-w>j");
+        assert_eq!(editor.display.cursor.position, (0, 0));
+        editor.type_keys("<C-w>k");
+        assert_eq!(editor.display.cursor.position, (0, 2));
+
+        // Edits show in every window on the buffer.
+        editor.type_keys("dd<C-w>j");
+        assert_eq!(editor.text(), "one\ntwo");
+    }
+
+    #[test]
+    fn closes_windows() {
+        let editor = typed("<C-w>s<C-w>v<C-w>c");
+        assert_eq!((editor.windows.count(), editor.windows.current), (2, 2));
+        assert_eq!(typed("<C-w>s<C-w>v<C-w>o").windows.count(), 1);
+        assert_eq!(typed("<C-w>c").error.as_deref(), Some("Cannot close last window"));
+
+        let editor = typed("<C-w>s:q<CR>");
+        assert!(editor.windows.count() == 1 && !editor.stop);
+        assert!(typed("<C-w>s:q<CR>:q<CR>").stop);
+    }
+}
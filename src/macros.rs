@@ -1,7 +1,12 @@
 use color_eyre::eyre::eyre;
-use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::mem::take;
 
+use crate::editor::motion::{self, CharSearch};
+use crate::editor::operator::{Operator, RangeKind};
+use crate::editor::tabs;
+use crate::editor::text_object;
+use crate::editor::visual::VisualKind;
+use crate::editor::window::Direction;
 use crate::editor::{Editor, Mode};
 
 macro_rules! add_keybind {
@@ -13,107 +18,50 @@ macro_rules! add_keybind {
         );
     };
 
-    (@parse_keys $keyseq:expr) => {{
-        let mut keys = Vec::new();
-        let mut chars = $keyseq.chars().peekable();
-
-        while let Some(c) = chars.next() {
-            if c == '<' {
-                let mut key_name = String::new();
-                while let Some(&peek) = chars.peek() {
-                    if peek == '>' {
-                        chars.next();
-                        break;
-                    }
-                    key_name.push(chars.next().unwrap());
-                }
-
-                let (modifiers, key) = if let Some(split_idx) = key_name.find('-') {
-                    let (modifier, key) = key_name.split_at(split_idx);
-                    (modifier, &key[1..].to_string())
-                } else {
-                    ("", &key_name)
-                };
-
-                let modifiers = match modifiers {
-                    "C" => KeyModifiers::CONTROL,
-                    "S" => KeyModifiers::SHIFT,
-                    "A" => KeyModifiers::ALT,
-                    _ => KeyModifiers::NONE,
-                };
-
-                let key_event = match key.as_str() {
-                    "BS" => KeyEvent::new(KeyCode::Backspace, modifiers),
-                    "Tab" => KeyEvent::new(KeyCode::Tab, modifiers),
-                    "CR" | "Enter" | "Return" => KeyEvent::new(KeyCode::Enter, modifiers),
-                    "Esc" => KeyEvent::new(KeyCode::Esc, modifiers),
-                    "Space" => KeyEvent::new(KeyCode::Char(' '), modifiers),
-                    "Up" => KeyEvent::new(KeyCode::Up, modifiers),
-                    "Down" => KeyEvent::new(KeyCode::Down, modifiers),
-                    "Left" => KeyEvent::new(KeyCode::Left, modifiers),
-                    "Right" => KeyEvent::new(KeyCode::Right, modifiers),
-                    "Insert" => KeyEvent::new(KeyCode::Insert, modifiers),
-                    "Del" => KeyEvent::new(KeyCode::Delete, modifiers),
-                    "Home" => KeyEvent::new(KeyCode::Home, modifiers),
-                    "End" => KeyEvent::new(KeyCode::End, modifiers),
-                    "PageUp" => KeyEvent::new(KeyCode::PageUp, modifiers),
-                    "PageDown" => KeyEvent::new(KeyCode::PageDown, modifiers),
-                    key if key.starts_with('F') && key[1..].parse::<u8>().is_ok() => {
-                        let f_num = key[1..].parse::<u8>().unwrap();
-                        KeyEvent::new(KeyCode::F(f_num), modifiers)
-                    }
-                    key if key.len() == 1 => {
-                        KeyEvent::new(KeyCode::Char(key.chars().next().unwrap()), modifiers)
-                    }
-                    _ => panic!("Unknown key: {}", key),
-                };
-
-                keys.push(key_event);
-            } else {
-                keys.push(KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE));
-            }
-        }
-
-        keys
-    }};
+    (@parse_keys $keyseq:expr) => {
+        $crate::util::keymap::parse_keys(&$keyseq)
+    };
 
     (@parse_modes $modes:expr) => {{
-        $modes
-            .chars()
-            .filter_map(|c| match c {
-                'n' => Some(Mode::NORMAL),
-                'v' => Some(Mode::VISUAL),
-                'c' => Some(Mode::COMMAND),
-                'i' => Some(Mode::INSERT),
-                _ => None,
-            })
-            .collect::<Vec<_>>()
+        $modes.chars().filter_map($crate::util::keymap::mode_from_char).collect::<Vec<_>>()
     }};
 }
 
+macro_rules! add_command {
+    ($editor:expr, $usage:expr, $flags:expr, $completion:ident, $help:expr, $handler:expr) => {
+        $editor.commands.add_command(
+            $usage,
+            $flags,
+            $crate::util::ex_commands::Completion::$completion,
+            $help,
+            $handler,
+        )
+    };
+}
+
 pub fn default_keybinds(editor: &mut Editor) {
-    add_keybind!(editor, "n", "k", |e| {
-        e.display.cursor_move_by((0, -1), &e.buffer);
-        Ok(())
-    });
+    add_keybind!(editor, "nvo", "k", |e| e.motion(motion::up));
 
-    add_keybind!(editor, "n", "j", |e| {
-        e.display.cursor_move_by((0, 1), &e.buffer);
-        Ok(())
-    });
+    add_keybind!(editor, "nvo", "j", |e| e.motion(motion::down));
+
+    add_keybind!(editor, "nvo", "h", |e| e.motion(motion::left));
 
-    add_keybind!(editor, "n", "h", |e| {
-        e.display.cursor_move_by((-1, 0), &e.buffer);
+    add_keybind!(editor, "nvo", "l", |e| e.motion(motion::right));
+
+    add_keybind!(editor, "n", "i", |e| {
+        e.mode = Mode::INSERT;
         Ok(())
     });
 
-    add_keybind!(editor, "n", "l", |e| {
+    add_keybind!(editor, "n", "a", |e| {
+        e.mode = Mode::INSERT;
         e.display.cursor_move_by((1, 0), &e.buffer);
         Ok(())
     });
 
-    add_keybind!(editor, "n", "i", |e| {
+    add_keybind!(editor, "n", "A", |e| {
         e.mode = Mode::INSERT;
+        e.display.cursor_move_x(e.buffer.line_len(e.display.cursor.position.1), &e.buffer);
         Ok(())
     });
 
@@ -122,8 +70,16 @@ pub fn default_keybinds(editor: &mut Editor) {
         Ok(())
     });
 
-    add_keybind!(editor, "ic", "<Esc>", |e| {
+    add_keybind!(editor, "ico", "<Esc>", |e| {
+        if e.search.prompt.is_some() {
+            e.cancel_search();
+            return Ok(());
+        }
+        if e.mode == Mode::INSERT {
+            e.finish_block_insert();
+        }
         e.mode = Mode::NORMAL;
+        e.operator = None;
         e.command.clear();
         Ok(())
     });
@@ -136,82 +92,632 @@ pub fn default_keybinds(editor: &mut Editor) {
         Ok(())
     });
 
-    add_keybind!(editor, "n", "$", |e| {
-        let (_x, y) = e.display.cursor.position;
-        let line_len = e.buffer[y as usize].len() as u16 - 1;
-        e.display.cursor_move_x(line_len, &e.buffer);
+    add_keybind!(editor, "nvo", "$", |e| e.motion(motion::line_end));
+
+    add_keybind!(editor, "nvo", "_", |e| e.motion(motion::first_non_blank));
+
+    add_keybind!(editor, "nvo", "gg", |e| e.motion(motion::first_line));
+
+    add_keybind!(editor, "nvo", "G", |e| e.motion(motion::last_line));
+
+    add_keybind!(editor, "nvo", "0", |e| e.motion(motion::line_start));
+
+    add_keybind!(editor, "nvo", "^", |e| e.motion(motion::first_non_blank));
+
+    // Word motions, with `true` selecting the WORD variant that only splits on whitespace.
+    let word_motions = [("w", "b", "e", "ge", false), ("W", "B", "E", "gE", true)];
+
+    for (forward, backward, end, end_backward, big) in word_motions {
+        add_keybind!(editor, "nv", forward, move |e| e.motion(|b, p, c| motion::word_forward(b, p, c, big)));
+
+        // `cw` on a word changes only up to its end, like `ce`.
+        add_keybind!(editor, "o", forward, move |e| {
+            let cursor = motion::to_char(&e.buffer, e.display.cursor.position);
+            let on_word = e.buffer.char(cursor).is_some_and(|c| !c.is_whitespace());
+            match e.operator.as_ref().map(|pending| pending.operator) {
+                Some(Operator::Change) if on_word => e.motion(|b, p, c| motion::change_word(b, p, c, big)),
+                _ => e.motion(|b, p, c| motion::word_forward_operator(b, p, c, big)),
+            }
+        });
+
+        add_keybind!(editor, "nvo", backward, move |e| e.motion(|b, p, c| motion::word_backward(b, p, c, big)));
+
+        add_keybind!(editor, "nvo", end, move |e| e.motion(|b, p, c| motion::word_end(b, p, c, big)));
+
+        add_keybind!(editor, "nvo", end_backward, move |e| {
+            e.motion(|b, p, c| motion::word_end_backward(b, p, c, big))
+        });
+    }
+
+    for (prefix, around) in [("i", false), ("a", true)] {
+        let object = |keys: &str| format!("{}{}", prefix, keys);
+
+        add_keybind!(editor, "vo", object("w"), move |e| {
+            e.text_object(|b, p, c| text_object::word(b, p, c, false, around))
+        });
+
+        add_keybind!(editor, "vo", object("W"), move |e| {
+            e.text_object(|b, p, c| text_object::word(b, p, c, true, around))
+        });
+
+        add_keybind!(editor, "vo", object("s"), move |e| e
+            .text_object(|b, p, c| text_object::sentence(b, p, c, around)));
+
+        add_keybind!(editor, "vo", object("p"), move |e| {
+            e.text_object(|b, p, c| text_object::paragraph(b, p, c, around))
+        });
+
+        add_keybind!(editor, "vo", object("t"), move |e| e.text_object(|b, p, c| text_object::tag(b, p, c, around)));
+
+        for quote in ['"', '\'', '`'] {
+            add_keybind!(editor, "vo", object(&quote.to_string()), move |e| {
+                e.text_object(|b, p, _| text_object::quote(b, p, quote, around))
+            });
+        }
+
+        // Each bracket pair with the keys selecting it, as in `i(`, `i)` and `ib`.
+        let brackets: [(&[&str], _); 4] = [
+            (&["(", ")", "b"], ('(', ')')),
+            (&["[", "]"], ('[', ']')),
+            (&["{", "}", "B"], ('{', '}')),
+            (&["<lt>", ">"], ('<', '>')),
+        ];
+
+        for (keys, pair) in brackets {
+            for &key in keys {
+                add_keybind!(editor, "vo", object(key), move |e| {
+                    e.text_object(|b, p, c| text_object::bracket(b, p, c, pair, around))
+                });
+            }
+        }
+    }
+
+    add_keybind!(editor, "nvo", "}", |e| e.motion(motion::paragraph_forward));
+
+    add_keybind!(editor, "nvo", "{", |e| e.motion(motion::paragraph_backward));
+
+    add_keybind!(editor, "nvo", ")", |e| e.motion(motion::sentence_forward));
+
+    add_keybind!(editor, "nvo", "(", |e| e.motion(motion::sentence_backward));
+
+    add_keybind!(editor, "nvo", "%", |e| e.motion(motion::matching_bracket));
+
+    // `f`, `F`, `t` and `T` as (forward, till).
+    let char_searches = [("f", true, false), ("F", false, false), ("t", true, true), ("T", false, true)];
+
+    for (keys, forward, till) in char_searches {
+        add_keybind!(editor, "nvo", keys, move |e| {
+            e.await_char(move |e, target| {
+                let search = CharSearch { target, forward, till };
+                e.last_find = Some(search);
+                e.motion(|b, p, c| motion::find_char(b, p, c, search, false))
+            });
+            Ok(())
+        });
+    }
+
+    add_keybind!(editor, "nvo", ";", |e| {
+        let search = e.last_find.ok_or_else(|| eyre!("No previous char search"))?;
+        e.motion(|b, p, c| motion::find_char(b, p, c, search, true))
+    });
+
+    add_keybind!(editor, "nvo", ",", |e| {
+        let search = e.last_find.ok_or_else(|| eyre!("No previous char search"))?.reversed();
+        e.motion(|b, p, c| motion::find_char(b, p, c, search, true))
+    });
+
+    // Each operator with the key that repeats it to act on whole lines, as in `dd`, `>>` and `guu`.
+    let operators = [
+        ("d", "d", Operator::Delete),
+        ("c", "c", Operator::Change),
+        ("y", "y", Operator::Yank),
+        (">", ">", Operator::ShiftRight),
+        ("<lt>", "<lt>", Operator::ShiftLeft),
+        ("gu", "u", Operator::Lowercase),
+        ("gU", "U", Operator::Uppercase),
+        ("g~", "~", Operator::ToggleCase),
+        ("!", "!", Operator::Filter),
+    ];
+
+    for (keys, line_keys, operator) in operators {
+        add_keybind!(editor, "n", keys, move |e| {
+            e.begin_operator(operator);
+            Ok(())
+        });
+
+        add_keybind!(editor, "o", keys, move |e| e.operator_lines(operator));
+        add_keybind!(editor, "o", line_keys, move |e| e.operator_lines(operator));
+
+        // In Visual mode the operator acts on the selection straight away, and `u`, `U` and `~` work on their own.
+        add_keybind!(editor, "v", keys, move |e| e.visual_operator(operator));
+        add_keybind!(editor, "v", line_keys, move |e| e.visual_operator(operator));
+    }
+
+    add_keybind!(editor, "v", "x", |e| e.visual_operator(Operator::Delete));
+
+    add_keybind!(editor, "v", "s", |e| e.visual_operator(Operator::Change));
+
+    add_keybind!(editor, "nv", "v", |e| {
+        e.toggle_visual(VisualKind::Char);
+        Ok(())
+    });
+
+    add_keybind!(editor, "nv", "V", |e| {
+        e.toggle_visual(VisualKind::Line);
+        Ok(())
+    });
+
+    add_keybind!(editor, "nv", "<C-v>", |e| {
+        e.toggle_visual(VisualKind::Block);
+        Ok(())
+    });
+
+    add_keybind!(editor, "v", "<Esc>", |e| {
+        e.exit_visual();
+        Ok(())
+    });
+
+    add_keybind!(editor, "v", "o", |e| {
+        e.swap_visual_ends();
+        Ok(())
+    });
+
+    add_keybind!(editor, "n", "gv", |e| e.reselect_visual());
+
+    add_keybind!(editor, "v", ":", |e| {
+        e.exit_visual();
+        e.command = "'<,'>".to_string();
+        e.mode = Mode::COMMAND;
+        Ok(())
+    });
+
+    add_keybind!(editor, "nv", "\"", |e| {
+        let count = e.count;
+        e.await_char(move |e, name| {
+            e.keymap.set_count(count);
+            e.select_register(name)
+        });
         Ok(())
     });
 
-    add_keybind!(editor, "n", "_", |e| {
-        let current_line = &e.buffer[e.display.cursor.position.1 as usize];
-        if let Some((index, _)) = current_line.char_indices().find(|&(_, c)| !c.is_whitespace()) {
-            e.display.cursor_move_x(index as u16, &e.buffer);
+    add_keybind!(editor, "n", "q", |e| {
+        match e.recording {
+            Some(_) => e.stop_recording()?,
+            None => e.await_char(|e, register| e.start_recording(register)),
         }
+        Ok(())
+    });
+
+    add_keybind!(editor, "n", "@", |e| {
+        e.await_char(|e, register| e.execute_macro(register));
+        Ok(())
+    });
+
+    add_keybind!(editor, "nvo", "/", |e| {
+        e.open_search(true);
+        Ok(())
+    });
+
+    add_keybind!(editor, "nvo", "?", |e| {
+        e.open_search(false);
+        Ok(())
+    });
+
+    add_keybind!(editor, "nvo", "n", |e| e.search_next(true));
+
+    add_keybind!(editor, "nvo", "N", |e| e.search_next(false));
+
+    add_keybind!(editor, "nvo", "*", |e| e.search_word(true));
 
+    add_keybind!(editor, "nvo", "#", |e| e.search_word(false));
+
+    add_keybind!(editor, "n", "m", |e| {
+        e.await_char(|e, mark| e.set_mark(mark));
+        Ok(())
+    });
+
+    add_keybind!(editor, "nvo", "'", |e| {
+        e.await_char(|e, mark| e.jump_to_mark(mark, false));
         Ok(())
     });
 
-    add_keybind!(editor, "n", "gg", |e| {
-        e.display.cursor_move_y(0, &e.buffer);
+    add_keybind!(editor, "nvo", "`", |e| {
+        e.await_char(|e, mark| e.jump_to_mark(mark, true));
         Ok(())
     });
 
-    add_keybind!(editor, "n", "G", |e| {
-        e.display.cursor_move_y(e.buffer.len() as u16, &e.buffer);
+    add_keybind!(editor, "n", ".", |e| e.repeat_change());
+
+    add_keybind!(editor, "n", "p", |e| e.put(false));
+
+    add_keybind!(editor, "n", "P", |e| e.put(true));
+
+    add_keybind!(editor, "n", "J", |e| {
+        let y = e.display.cursor.position.1;
+        e.join_lines(y, y.saturating_add(e.repeats().max(2) - 1));
         Ok(())
     });
 
+    add_keybind!(editor, "v", "J", |e| e.visual_join());
+
     add_keybind!(editor, "n", "o", |e| {
-        e.buffer.insert(e.display.cursor.position.1 as usize + 1, String::new());
+        let y = e.display.cursor.position.1;
+        e.insert_text(e.buffer.line_to_char(y) + e.buffer.line_len(y), "\n");
         e.display.cursor_move_by((0, 1), &e.buffer);
         e.mode = Mode::INSERT;
         Ok(())
     });
 
     add_keybind!(editor, "n", "O", |e| {
-        e.buffer.insert(e.display.cursor.position.1 as usize, String::new());
-        e.display.cursor_move_by((0, 0), &e.buffer);
+        e.insert_text(e.buffer.line_to_char(e.display.cursor.position.1), "\n");
+        e.display.cursor_move_x(0, &e.buffer);
         e.mode = Mode::INSERT;
         Ok(())
     });
 
+    add_keybind!(editor, "n", "u", |e| e.undo());
+
+    add_keybind!(editor, "n", "<C-r>", |e| e.redo());
+
+    for keys in ["<C-w>s", "<C-w>S", "<C-w><C-s>"] {
+        add_keybind!(editor, "n", keys, |e| e.split_window(false, None));
+    }
+
+    for keys in ["<C-w>v", "<C-w><C-v>"] {
+        add_keybind!(editor, "n", keys, |e| e.split_window(true, None));
+    }
+
+    add_keybind!(editor, "n", "<C-w>n", |e| {
+        e.split_window(false, None)?;
+        e.edit_new_buffer();
+        Ok(())
+    });
+
+    for keys in ["<C-w>w", "<C-w><C-w>"] {
+        add_keybind!(editor, "n", keys, |e| {
+            e.cycle_window(true);
+            Ok(())
+        });
+    }
+
+    add_keybind!(editor, "n", "<C-w>W", |e| {
+        e.cycle_window(false);
+        Ok(())
+    });
+
+    add_keybind!(editor, "n", "<C-w>p", |e| e.previous_window());
+
+    let directions = [("h", "<Left>", Direction::Left), ("j", "<Down>", Direction::Down)];
+    for (key, arrow, direction) in
+        directions.into_iter().chain([("k", "<Up>", Direction::Up), ("l", "<Right>", Direction::Right)])
+    {
+        for keys in [format!("<C-w>{}", key), format!("<C-w><C-{}>", key), format!("<C-w>{}", arrow)] {
+            add_keybind!(editor, "n", keys, move |e| {
+                e.move_to_window(direction);
+                Ok(())
+            });
+        }
+    }
+
+    for keys in ["<C-w>c", "<C-w><C-c>"] {
+        add_keybind!(editor, "n", keys, |e| e.close_window());
+    }
+
+    for keys in ["<C-w>q", "<C-w><C-q>"] {
+        add_keybind!(editor, "n", keys, |e| e.quit_window(false));
+    }
+
+    for keys in ["<C-w>o", "<C-w><C-o>"] {
+        add_keybind!(editor, "n", keys, |e| {
+            e.only_window();
+            Ok(())
+        });
+    }
+
+    // Heights change by the count, one row by default, and `_` and `|` set the size to the count or the most there is.
+    for (keys, vertical, sign) in
+        [("<C-w>+", false, 1), ("<C-w>-", false, -1), ("<C-w>>", true, 1), ("<C-w><lt>", true, -1)]
+    {
+        add_keybind!(editor, "n", keys, move |e| {
+            e.resize_window(vertical, sign * e.repeats() as isize);
+            Ok(())
+        });
+    }
+
+    for (keys, vertical) in [("<C-w>_", false), ("<C-w>|", true)] {
+        add_keybind!(editor, "n", keys, move |e| {
+            e.set_window_size(vertical, e.count);
+            Ok(())
+        });
+    }
+
+    add_keybind!(editor, "n", "<C-w>=", |e| {
+        e.equalize_windows();
+        Ok(())
+    });
+
+    add_keybind!(editor, "n", "ZZ", |e| e.exit_window(None, false));
+
+    add_keybind!(editor, "n", "ZQ", |e| e.quit_window(true));
+
+    add_keybind!(editor, "n", "gt", |e| e.next_tab(e.count));
+
+    add_keybind!(editor, "n", "gT", |e| {
+        e.previous_tab(e.repeats());
+        Ok(())
+    });
+
+    // Terminals send `<C-^>` as `<C-6>`.
+    for keys in ["<C-^>", "<C-6>"] {
+        add_keybind!(editor, "n", keys, |e| e.switch_to_alternate());
+    }
+
+    add_keybind!(editor, "n", "g-", |e| e.undo_time(-1));
+
+    add_keybind!(editor, "n", "g+", |e| e.undo_time(1));
+
     add_keybind!(editor, "c", "<CR>", |e| {
-        if e.command.is_empty() {
-            e.mode = Mode::NORMAL;
-            return Ok(());
+        if e.search.prompt.is_some() {
+            return e.confirm_search();
         }
 
         let command = take(&mut e.command);
-        match command.split_whitespace().next() {
-            Some("q") => e.stop = true,
-            Some("e") => {
-                if let Some(filename) = command.split_whitespace().nth(1) {
-                    e.load_file(filename)?;
-                } else {
-                    return Err(eyre!("No filename specified"));
-                }
+        e.mode = Mode::NORMAL;
+        if command.is_empty() {
+            return Ok(());
+        }
+
+        e.registers.last_command = command.clone();
+        e.execute_command(&command)
+    });
+
+    add_keybind!(editor, "c", "<Tab>", |e| {
+        e.complete_command();
+        Ok(())
+    });
+}
+
+pub fn default_commands(editor: &mut Editor) {
+    add_command!(editor, "q[uit]", "!", None, "Close the window, or quit the editor in the last one", |e, c| {
+        e.quit_window(c.bang)
+    });
+
+    add_command!(editor, "qa[ll]", "!", None, "Quit the editor", |e, c| e.quit_all(c.bang));
+
+    add_command!(editor, "w[rite]", "!%", File, "Write the buffer, or lines of it, to a file", |e, c| {
+        e.write_lines(c.lines.clone(), c.args.split_whitespace().next(), c.bang)
+    });
+
+    add_command!(editor, "wq", "!%", File, "Write the buffer and close the window, or quit", |e, c| {
+        e.write_lines(c.lines.clone(), c.args.split_whitespace().next(), c.bang)?;
+        e.quit_window(c.bang)
+    });
+
+    add_command!(editor, "x[it]", "!", File, "Write the buffer if it changed and close the window, or quit", |e, c| {
+        e.exit_window(c.args.split_whitespace().next(), c.bang)
+    });
+
+    add_command!(editor, "wa[ll]", "", None, "Write every modified buffer", |e, _| e.write_all());
+
+    for usage in ["xa[ll]", "wqa[ll]"] {
+        add_command!(editor, usage, "", None, "Write every modified buffer and quit the editor", |e, _| {
+            e.write_all()?;
+            e.quit_all(false)
+        });
+    }
+
+    add_command!(editor, "e[dit]", "!", File, "Edit a file, or read the current one again", |e, c| {
+        e.edit_file(c.args.split_whitespace().next(), c.bang)
+    });
+
+    add_command!(editor, "b[uffer]", "!", None, "Edit a buffer given by number or name", |e, c| {
+        match e.find_buffer(&c.args)? {
+            Some(number) => e.switch_buffer(number, c.bang),
+            None => Ok(()),
+        }
+    });
+
+    for (usage, forward) in [("bn[ext]", true), ("bp[revious]", false)] {
+        add_command!(editor, usage, "!", None, "Edit the next or previous buffer in the list", move |e, c| {
+            e.cycle_buffer(forward, c.bang)
+        });
+    }
+
+    add_command!(editor, "bd[elete]", "!", None, "Remove a buffer from the list", |e, c| {
+        let number = e.find_buffer(&c.args)?;
+        e.delete_buffer(number, c.bang)
+    });
+
+    for (usage, vertical) in [("sp[lit]", false), ("vs[plit]", true)] {
+        add_command!(editor, usage, "", File, "Split the window, editing a file in the new one", move |e, c| {
+            e.split_window(vertical, c.args.split_whitespace().next())
+        });
+    }
+
+    for (usage, vertical) in [("new", false), ("vne[w]", true)] {
+        add_command!(
+            editor,
+            usage,
+            "",
+            None,
+            "Split the window, with a new empty buffer in the new one",
+            move |e, _| {
+                e.split_window(vertical, None)?;
+                e.edit_new_buffer();
+                Ok(())
             }
-            Some("w") => {
-                if let Some(filename) = command.split_whitespace().nth(1).or(e.filename.clone().as_deref()) {
-                    e.save_file(filename)?;
-                } else {
-                    return Err(eyre!("No filename specified"));
-                }
+        );
+    }
+
+    add_command!(editor, "clo[se]", "", None, "Close the window", |e, _| e.close_window());
+
+    add_command!(editor, "on[ly]", "", None, "Close every other window", |e, _| {
+        e.only_window();
+        Ok(())
+    });
+
+    add_command!(editor, "res[ize]", "", None, "Set the window height, or change it by +N or -N", |e, c| {
+        let args = c.args.as_str();
+        let number = |text: &str| text.parse::<usize>().map_err(|_| eyre!("Invalid argument: {}", args));
+        match args.chars().next() {
+            Some('+') => e.resize_window(false, number(&args[1..])? as isize),
+            Some('-') => e.resize_window(false, -(number(&args[1..])? as isize)),
+            Some(_) => e.set_window_size(false, Some(number(args)?)),
+            None => e.set_window_size(false, None),
+        }
+        Ok(())
+    });
+
+    for usage in ["tabnew", "tabe[dit]"] {
+        add_command!(editor, usage, "", File, "Open a tab page, editing a file or a new buffer", |e, c| {
+            e.new_tab(c.args.split_whitespace().next())
+        });
+    }
+
+    add_command!(editor, "tabn[ext]", "", None, "Go to the next tab page, or to tab page N", |e, c| {
+        e.next_tab(tabs::number_argument(&c.args)?)
+    });
+
+    for usage in ["tabp[revious]", "tabN[ext]"] {
+        add_command!(editor, usage, "", None, "Go back to the previous tab page, or N tab pages back", |e, c| {
+            e.previous_tab(tabs::number_argument(&c.args)?.unwrap_or(1));
+            Ok(())
+        });
+    }
+
+    add_command!(editor, "tabc[lose]", "!", None, "Close the tab page, or tab page N", |e, c| {
+        e.close_tab(tabs::number_argument(&c.args)?)
+    });
+
+    add_command!(editor, "tabm[ove]", "", None, "Move the tab page after tab page N, or by +N or -N", |e, c| {
+        e.move_tab(&c.args)
+    });
+
+    for usage in ["ls", "buffers", "files"] {
+        add_command!(editor, usage, "", None, "List the buffers", |e, _| {
+            e.error = Some(e.list_buffers());
+            Ok(())
+        });
+    }
+
+    add_command!(editor, "u[ndo]", "", None, "Undo a change, or go back to undo state N", |e, c| {
+        match c.args.split_whitespace().next() {
+            Some(seq) => {
+                let seq = seq.parse::<usize>().map_err(|_| eyre!("Invalid undo number: {}", seq))?;
+                let steps = seq as isize - e.history.current as isize;
+                e.undo_time(steps)
             }
-            Some("wq") => {
-                if let Some(filename) = command.split_whitespace().nth(1).or(e.filename.clone().as_deref()) {
-                    e.save_file(filename)?;
-                    e.stop = true;
-                } else {
-                    return Err(eyre!("No filename specified"));
-                }
+            None => e.undo(),
+        }
+    });
+
+    add_command!(editor, "red[o]", "", None, "Redo a change", |e, _| e.redo());
+
+    add_command!(editor, "undol[ist]", "", None, "List the leaves of the undo tree", |e, _| {
+        e.error = Some(e.undo_list());
+        Ok(())
+    });
+
+    for usage in ["reg[isters]", "di[splay]"] {
+        add_command!(editor, usage, "", None, "List the contents of registers", |e, c| {
+            let names = c.args.split_whitespace().collect::<String>();
+            e.error = Some(e.list_registers(&names));
+            Ok(())
+        });
+    }
+
+    add_command!(editor, "s[ubstitute]", ".", None, "Replace matches of a pattern", |e, c| {
+        e.substitute(c.lines.clone(), &c.args)
+    });
+
+    add_command!(editor, "d[elete]", ".", None, "Delete lines into a register", |e, c| {
+        e.delete_lines(c.lines.clone(), &c.args, true)
+    });
+
+    add_command!(editor, "y[ank]", ".", None, "Yank lines into a register", |e, c| {
+        e.delete_lines(c.lines.clone(), &c.args, false)
+    });
+
+    add_command!(editor, "m[ove]", ".", None, "Move lines below another line", |e, c| {
+        let target = e.parse_target(&c.args)?;
+        e.move_lines(c.lines.clone(), target)
+    });
+
+    for usage in ["t", "co[py]"] {
+        add_command!(editor, usage, ".", None, "Copy lines below another line", |e, c| {
+            let target = e.parse_target(&c.args)?;
+            e.copy_lines(c.lines.clone(), target)
+        });
+    }
+
+    // Each extra `>` or `<`, as in `:>>`, shifts one more level.
+    for (usage, operator) in [(">", Operator::ShiftRight), ("<", Operator::ShiftLeft)] {
+        add_command!(editor, usage, ".", None, "Shift lines by one level per repeated char", move |e, c| {
+            for _ in 0..1 + c.args.chars().take_while(|&next| usage.starts_with(next)).count() {
+                e.apply_operator(operator, e.line_range(&c.lines), RangeKind::Linewise)?;
             }
-            _ => {}
+            Ok(())
+        });
+    }
+
+    add_command!(editor, "j[oin]", ".", None, "Join lines", |e, c| {
+        let (first, last) = (*c.lines.start(), *c.lines.end());
+        e.join_lines(first, last.max(first + 1));
+        Ok(())
+    });
+
+    add_command!(editor, "sor[t]", "!%", None, "Sort lines", |e, c| e.sort_lines(c.lines.clone(), c.bang, &c.args));
+
+    add_command!(editor, "p[rint]", ".", None, "Show lines", |e, c| {
+        e.print_lines(c.lines.clone());
+        Ok(())
+    });
+
+    add_command!(editor, "g[lobal]", "!%", None, "Run a command on lines matching a pattern", |e, c| {
+        e.global(c.lines.clone(), &c.args, c.bang)
+    });
+
+    add_command!(editor, "v[global]", "%", None, "Run a command on lines not matching a pattern", |e, c| {
+        e.global(c.lines.clone(), &c.args, true)
+    });
+
+    add_command!(editor, "norm[al]", "!.", None, "Execute Normal mode keys, on each line of a range", |e, c| {
+        e.execute_normal(c.range.then(|| c.lines.clone()), &c.args, c.bang)
+    });
+
+    // `:map` covers Normal, Visual and Operator-pending mode, and `:map!` Insert and Command-line mode instead.
+    let maps = [
+        ("map", "no[remap]", "nvo"),
+        ("nm[ap]", "nn[oremap]", "n"),
+        ("vm[ap]", "vn[oremap]", "v"),
+        ("om[ap]", "ono[remap]", "o"),
+        ("im[ap]", "ino[remap]", "i"),
+        ("cm[ap]", "cno[remap]", "c"),
+    ];
+    for (map, noremap, modes) in maps {
+        for (usage, noremap) in [(map, false), (noremap, true)] {
+            let flags = if modes == "nvo" { "!" } else { "" };
+            add_command!(editor, usage, flags, None, "Map keys to other keys, or list mappings", move |e, c| {
+                e.map_keys(if c.bang { "ic" } else { modes }, &c.args, noremap)
+            });
         }
+    }
 
-        e.mode = Mode::NORMAL;
-        e.command.clear();
+    add_command!(editor, "!", ".", None, "Run a shell command, or filter lines through it", |e, c| {
+        e.shell_command(c.range.then(|| c.lines.clone()), &c.args)
+    });
+
+    add_command!(
+        editor,
+        "r[ead]",
+        ".",
+        File,
+        "Insert a file, or the output of a shell command, below a line",
+        |e, c| { e.read_lines(*c.lines.end(), &c.args) }
+    );
+
+    add_command!(editor, "h[elp]", "", Command, "List Ex commands, or describe one", |e, c| {
+        e.error = Some(e.list_commands(&c.args)?);
         Ok(())
     });
 }
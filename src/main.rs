@@ -10,7 +10,9 @@ use crossterm::terminal;
 use tracing_subscriber::EnvFilter;
 
 use editor::Editor;
-use macros::default_keybinds;
+use macros::{default_commands, default_keybinds};
+use util::statusline::{self, StatusLine};
+use util::Clipboard;
 
 struct RawModeGuard;
 impl Drop for RawModeGuard {
@@ -38,6 +40,15 @@ fn setup() -> Result<(), Report> {
 struct Args {
     #[arg(short, long)]
     filename: Option<String>,
+
+    /// Clipboard for the "+ and "* registers: auto, osc52, none, wl-copy, xclip, xsel or pbcopy.
+    #[arg(long, default_value = "auto")]
+    clipboard: String,
+
+    /// Status line format: text with %M mode, %f file, %m modified, %r readonly, %y filetype, %e encoding, %E line
+    /// ending, %l line, %c column, %L lines, %p percentage, %S pending keys, %= to right-align the rest and %% for %.
+    #[arg(long, default_value = statusline::DEFAULT_FORMAT)]
+    statusline: String,
 }
 
 fn main() -> Result<(), Report> {
@@ -51,6 +62,9 @@ fn main() -> Result<(), Report> {
 
         let mut editor = Editor::new();
         default_keybinds(&mut editor);
+        default_commands(&mut editor);
+        editor.clipboard = Clipboard::from_name(&args.clipboard)?;
+        editor.statusline = StatusLine::parse(&args.statusline)?;
 
         if let Some(filename) = &args.filename {
             editor.load_file(filename)?;
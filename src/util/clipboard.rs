@@ -0,0 +1,253 @@
+use color_eyre::{eyre::eyre, Report};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Which system selection a clipboard register refers to: `"+` is the clipboard and `"*` the primary selection.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum Selection {
+    Clipboard,
+    Primary,
+}
+
+impl Selection {
+    pub(crate) fn from_register(name: char) -> Option<Self> {
+        match name {
+            '+' => Some(Self::Clipboard),
+            '*' => Some(Self::Primary),
+            _ => None,
+        }
+    }
+}
+
+/// Local program that reads and writes the system clipboard.
+pub(crate) trait ClipboardProvider {
+    fn copy(&mut self, selection: Selection, text: &str) -> Result<(), Report>;
+    fn paste(&self, selection: Selection) -> Result<String, Report>;
+}
+
+/// Provider running a command for each copy and paste, such as `wl-copy` and `wl-paste`. The text goes to the copy
+/// command's stdin and is read from the paste command's stdout.
+pub(crate) struct CommandProvider {
+    copy: [Vec<&'static str>; 2],
+    paste: [Vec<&'static str>; 2],
+}
+
+impl CommandProvider {
+    /// Provider called `name`: `wl-copy`, `xclip`, `xsel` or `pbcopy`.
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let (copy, paste) = match name {
+            "wl-copy" => (
+                [vec!["wl-copy"], vec!["wl-copy", "--primary"]],
+                [vec!["wl-paste", "--no-newline"], vec!["wl-paste", "--no-newline", "--primary"]],
+            ),
+            "xclip" => (
+                [vec!["xclip", "-selection", "clipboard", "-in"], vec!["xclip", "-selection", "primary", "-in"]],
+                [vec!["xclip", "-selection", "clipboard", "-out"], vec!["xclip", "-selection", "primary", "-out"]],
+            ),
+            "xsel" => (
+                [vec!["xsel", "--clipboard", "--input"], vec!["xsel", "--primary", "--input"]],
+                [vec!["xsel", "--clipboard", "--output"], vec!["xsel", "--primary", "--output"]],
+            ),
+            // macOS has no primary selection, so both registers use the clipboard.
+            "pbcopy" => ([vec!["pbcopy"], vec!["pbcopy"]], [vec!["pbpaste"], vec!["pbpaste"]]),
+            _ => return None,
+        };
+
+        Some(Self { copy, paste })
+    }
+
+    /// Provider for the display server the editor runs under, if any.
+    pub(crate) fn detect() -> Option<Self> {
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            Self::from_name("wl-copy")
+        } else if std::env::var_os("DISPLAY").is_some() {
+            Self::from_name("xclip")
+        } else if cfg!(target_os = "macos") {
+            Self::from_name("pbcopy")
+        } else {
+            None
+        }
+    }
+}
+
+impl ClipboardProvider for CommandProvider {
+    fn copy(&mut self, selection: Selection, text: &str) -> Result<(), Report> {
+        let args = &self.copy[selection as usize];
+        let mut child = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|err| eyre!("Failed to run {}: {}", args[0], err))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+
+        match child.wait()?.success() {
+            true => Ok(()),
+            false => Err(eyre!("{} failed", args[0])),
+        }
+    }
+
+    fn paste(&self, selection: Selection) -> Result<String, Report> {
+        let args = &self.paste[selection as usize];
+        let output = Command::new(args[0])
+            .args(&args[1..])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .map_err(|err| eyre!("Failed to run {}: {}", args[0], err))?;
+
+        match output.status.success() {
+            true => Ok(String::from_utf8_lossy(&output.stdout).into_owned()),
+            false => Err(eyre!("{} failed", args[0])),
+        }
+    }
+}
+
+/// System clipboard behind the `"+` and `"*` registers. Copies are sent to the terminal as OSC 52 escape sequences,
+/// which reach the host clipboard over SSH and through tmux, and to the local provider when there is one. Pastes can
+/// only come from the provider, since reading the clipboard through the terminal is rarely allowed.
+pub(crate) struct Clipboard {
+    osc52: bool,
+    provider: Option<Box<dyn ClipboardProvider>>,
+}
+
+impl Clipboard {
+    pub(crate) fn new(osc52: bool, provider: Option<Box<dyn ClipboardProvider>>) -> Self {
+        Self { osc52, provider }
+    }
+
+    /// OSC 52 together with the detected local provider.
+    pub(crate) fn detect() -> Self {
+        let provider = CommandProvider::detect().map(|provider| Box::new(provider) as Box<dyn ClipboardProvider>);
+        Self::new(true, provider)
+    }
+
+    /// Clipboard configured by name: `osc52` alone, `none`, `auto`, or a provider name, which is used as well as
+    /// OSC 52.
+    pub(crate) fn from_name(name: &str) -> Result<Self, Report> {
+        match name {
+            "auto" => Ok(Self::detect()),
+            "osc52" => Ok(Self::new(true, None)),
+            "none" => Ok(Self::new(false, None)),
+            _ => {
+                let provider = CommandProvider::from_name(name).ok_or_else(|| eyre!("Unknown clipboard: {}", name))?;
+                Ok(Self::new(true, Some(Box::new(provider))))
+            }
+        }
+    }
+
+    /// Sends `text` to `selection`, writing the OSC 52 sequence to `out`.
+    pub(crate) fn copy(&mut self, out: &mut impl Write, selection: Selection, text: &str) -> Result<(), Report> {
+        if self.osc52 {
+            out.write_all(osc52_sequence(selection, text).as_bytes())?;
+            out.flush()?;
+        }
+
+        match &mut self.provider {
+            Some(provider) => provider.copy(selection, text),
+            None => Ok(()),
+        }
+    }
+
+    pub(crate) fn paste(&self, selection: Selection) -> Result<String, Report> {
+        match &self.provider {
+            Some(provider) => provider.paste(selection),
+            None => Err(eyre!("No clipboard provider to paste from")),
+        }
+    }
+}
+
+/// OSC 52 sequence setting `selection` to `text`, wrapped for passthrough when running inside tmux.
+fn osc52_sequence(selection: Selection, text: &str) -> String {
+    let target = match selection {
+        Selection::Clipboard => 'c',
+        Selection::Primary => 'p',
+    };
+
+    let sequence = format!("\x1b]52;{};{}\x07", target, base64(text.as_bytes()));
+    match std::env::var_os("TMUX") {
+        Some(_) => format!("\x1bPtmux;{}\x1b\\", sequence.replace('\x1b', "\x1b\x1b")),
+        None => sequence,
+    }
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, &byte)| group | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(group >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{cell::RefCell, rc::Rc};
+
+    /// Provider keeping the clipboard in memory, shared with the test so it can inspect what was copied.
+    #[derive(Clone, Default)]
+    struct FakeProvider {
+        contents: Rc<RefCell<Vec<(Selection, String)>>>,
+    }
+
+    impl ClipboardProvider for FakeProvider {
+        fn copy(&mut self, selection: Selection, text: &str) -> Result<(), Report> {
+            self.contents.borrow_mut().push((selection, text.to_string()));
+            Ok(())
+        }
+
+        fn paste(&self, selection: Selection) -> Result<String, Report> {
+            let contents = self.contents.borrow();
+            let copied = contents.iter().rev().find(|(copied, _)| *copied == selection);
+            copied.map(|(_, text)| text.clone()).ok_or_else(|| eyre!("Empty"))
+        }
+    }
+
+    #[test]
+    fn base64_matches_reference() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar\n"), "Zm9vYmFyCg==");
+    }
+
+    #[test]
+    fn copy_emits_osc52_and_reaches_provider() {
+        let provider = FakeProvider::default();
+        let mut clipboard = Clipboard::new(true, Some(Box::new(provider.clone())));
+
+        let mut out = Vec::new();
+        clipboard.copy(&mut out, Selection::Primary, "foo").unwrap();
+
+        assert!(String::from_utf8(out).unwrap().contains("\x1b]52;p;Zm9v\x07"));
+        assert_eq!(*provider.contents.borrow(), vec![(Selection::Primary, "foo".to_string())]);
+        assert_eq!(clipboard.paste(Selection::Primary).unwrap(), "foo");
+        assert!(clipboard.paste(Selection::Clipboard).is_err());
+    }
+
+    #[test]
+    fn osc52_only_writes_nothing_else() {
+        let mut clipboard = Clipboard::new(true, None);
+        let mut out = Vec::new();
+        clipboard.copy(&mut out, Selection::Clipboard, "bar").unwrap();
+
+        assert!(String::from_utf8(out).unwrap().contains("]52;c;YmFy"));
+        assert!(clipboard.paste(Selection::Clipboard).is_err());
+    }
+}
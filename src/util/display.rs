@@ -4,21 +4,51 @@ use crossterm::{
     terminal::{self, ClearType},
 };
 use std::io::{self, Write};
+use std::ops::Range;
 
+use crate::editor::search;
+use crate::editor::visual::VisualArea;
 use crate::editor::Mode;
+use crate::util::layout::Rect;
+use crate::util::Rope;
 
+/// First `width` chars of `text`.
+fn clip(text: &str, width: usize) -> String {
+    text.chars().take(width).collect()
+}
+
+/// Columns taken by the line numbers at the left of a window.
+pub const GUTTER_WIDTH: usize = 6;
+
+/// What `Display::render` draws of a window.
+pub struct WindowView<'a> {
+    /// Area of the window, including its status line.
+    pub rect: Rect,
+    pub buffer: &'a Rope,
+    pub cursor: (usize, usize),
+    pub offset: (usize, usize),
+    pub visual: Option<VisualArea>,
+    pub search: Option<Range<usize>>,
+    /// Text of the status line on the last row of the window, if it has one.
+    pub status: Option<String>,
+    pub current: bool,
+}
+
+/// The terminal, and the cursor and scroll offset of the current window.
 pub struct Display {
     size: (u16, u16),
-    offset: (u16, u16),
+    /// Columns and rows of text the current window shows, which it scrolls to keep the cursor in.
+    area: (usize, usize),
+    pub(crate) offset: (usize, usize),
 
     pub(crate) cursor: Cursor,
 
-    pub(crate) out: io::Stdout,
+    pub(crate) out: Box<dyn Write>,
 }
 
 pub struct Cursor {
-    pub(crate) position: (u16, u16),
-    pub(crate) max_column: u16,
+    pub(crate) position: (usize, usize),
+    pub(crate) max_column: usize,
 }
 
 impl Cursor {
@@ -26,14 +56,8 @@ impl Cursor {
         Self { position: (0, 0), max_column: 0 }
     }
 
-    fn move_by(&mut self, delta: (i16, i16), buffer: &Vec<String>) {
-        let saturate = |pos: u16, delta: i16| {
-            if delta.is_negative() {
-                pos.saturating_sub(delta.abs() as u16)
-            } else {
-                pos.saturating_add(delta as u16)
-            }
-        };
+    fn move_by(&mut self, delta: (isize, isize), buffer: &Rope) {
+        let saturate = |pos: usize, delta: isize| pos.saturating_add_signed(delta);
 
         let (mut x, mut y) = self.position;
         let (dx, dy) = delta;
@@ -51,26 +75,35 @@ impl Cursor {
         self.validate_cursor(buffer);
     }
 
-    fn move_x(&mut self, new_x: u16, buffer: &Vec<String>) {
+    fn move_x(&mut self, new_x: usize, buffer: &Rope) {
         self.position.0 = new_x;
         self.max_column = new_x;
 
         self.validate_cursor(buffer);
     }
 
-    fn move_y(&mut self, new_y: u16, buffer: &Vec<String>) {
+    fn move_y(&mut self, new_y: usize, buffer: &Rope) {
         self.position.1 = new_y;
         self.validate_cursor(buffer);
     }
 
-    fn validate_cursor(&mut self, buffer: &Vec<String>) {
+    /// Keeps the cursor on a char rather than past the end of the line, as everything but Insert mode requires.
+    /// The remembered column is left alone so vertical motions still return to it.
+    fn clamp_to_last_char(&mut self, buffer: &Rope) {
+        let line_len = buffer.line_len(self.position.1);
+        if self.position.0 >= line_len {
+            self.position.0 = line_len.saturating_sub(1);
+        }
+    }
+
+    fn validate_cursor(&mut self, buffer: &Rope) {
         let (_x, y) = self.position;
 
-        if y >= buffer.len() as u16 {
-            self.position.1 = buffer.len().saturating_sub(1) as u16;
+        if y >= buffer.len_lines() {
+            self.position.1 = buffer.len_lines() - 1;
         }
 
-        let line_len = buffer[self.position.1 as usize].len() as u16;
+        let line_len = buffer.line_len(self.position.1);
         self.position.0 = self.max_column.min(line_len);
     }
 }
@@ -83,130 +116,245 @@ impl Drop for Display {
 
 impl Display {
     pub fn new() -> Self {
-        let mut display =
-            Self { size: terminal::size().unwrap(), offset: (0, 0), cursor: Cursor::new(), out: io::stdout() };
+        Self::with_output(terminal::size().unwrap(), Box::new(io::stdout()))
+    }
+
+    /// Display of `size` cells drawing to `out` rather than the terminal the editor runs in.
+    pub fn with_output(size: (u16, u16), out: Box<dyn Write>) -> Self {
+        let mut display = Self {
+            size,
+            area: ((size.0 as usize).saturating_sub(GUTTER_WIDTH), (size.1 as usize).saturating_sub(2)),
+            offset: (0, 0),
+            cursor: Cursor::new(),
+            out,
+        };
 
         let _ = execute!(display.out, terminal::EnterAlternateScreen);
 
         display
     }
 
+    /// Leaves the alternate screen and raw mode, for a command to use the terminal as the shell would.
+    pub fn suspend(&mut self) -> Result<(), Report> {
+        execute!(
+            self.out,
+            style::ResetColor,
+            cursor::SetCursorStyle::DefaultUserShape,
+            terminal::LeaveAlternateScreen
+        )?;
+        terminal::disable_raw_mode()?;
+        Ok(())
+    }
+
+    /// Takes the terminal back after `suspend`, picking up any change to its size in the meantime.
+    pub fn resume(&mut self) -> Result<(), Report> {
+        terminal::enable_raw_mode()?;
+        execute!(self.out, terminal::EnterAlternateScreen)?;
+        self.size = terminal::size()?;
+        Ok(())
+    }
+
+    /// Area the windows share: the whole terminal but the last row, which is for the command line and messages, and
+    /// the first one when it has a tabline.
+    pub fn screen(&self, tabline: bool) -> Rect {
+        let top = usize::from(tabline);
+        Rect { x: 0, y: top, width: self.size.0 as usize, height: (self.size.1 as usize).saturating_sub(1 + top) }
+    }
+
+    /// Sets the area of the current window, whose last row is its status line, and scrolls to keep the cursor in it.
+    pub fn set_area(&mut self, rect: Rect) {
+        self.area = (rect.width.saturating_sub(GUTTER_WIDTH).max(1), rect.height.saturating_sub(1).max(1));
+        self.validate_offset();
+    }
+
+    /// Draws the tabline when `tabs` has labels, with whether each one is the current tab page, then the windows,
+    /// and the command line and messages at the bottom.
     pub fn render(
         &mut self,
-        buffer: &Vec<String>,
-        command: &String,
+        tabs: &[(String, bool)],
+        windows: &[WindowView],
+        separators: &[Rect],
+        command: &str,
         error: &Option<String>,
         mode: &Mode,
     ) -> Result<(), Report> {
-        queue!(self.out, style::ResetColor, terminal::Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+        queue!(self.out, style::ResetColor, terminal::Clear(ClearType::All))?;
 
-        let mut max_lines = self.size.1 as usize;
-        if *mode == Mode::COMMAND {
-            max_lines -= 1;
-        }
-        if let Some(error) = error {
-            max_lines -= error.matches('\n').count();
+        if !tabs.is_empty() {
+            self.render_tabline(tabs)?;
         }
-
-        let max_columns = self.size.0 as usize;
-
-        let cursor_line = self.cursor.position.1 as usize;
-        let render = buffer[self.offset.1 as usize..]
-            .iter()
-            .enumerate()
-            .take(max_lines)
-            .map(|(i, line)| {
-                let rendering_line = self.offset.1 as usize + i;
-                let relative_number = if rendering_line == cursor_line {
-                    self.cursor.position.1.to_string()
-                } else {
-                    (cursor_line as isize - rendering_line as isize).abs().to_string()
-                };
-
-                let padded_number = format!("{:>4}  ", relative_number);
-
-                let trimmed_line = if line.len() > self.cursor.position.0 as usize {
-                    let start = self.offset.0 as usize;
-                    let end = (start + max_columns).min(line.len());
-                    &line[start..end]
-                } else {
-                    ""
-                };
-
-                format!("{}{}", padded_number, trimmed_line)
-            })
-            .collect::<Vec<_>>();
-
-        for line in &render {
-            queue!(self.out, style::Print(line), cursor::MoveToNextLine(1))?;
+        for window in windows {
+            self.render_window(window)?;
         }
-
-        let rendered_lines = render.len();
-        if rendered_lines < max_lines {
-            let empty_lines = max_lines - rendered_lines;
-            for _ in 0..empty_lines {
-                queue!(self.out, style::Print("   ~ "), cursor::MoveToNextLine(1))?;
+        for separator in separators {
+            for y in separator.y..separator.y + separator.height {
+                queue!(self.out, cursor::MoveTo(separator.x as u16, y as u16), style::Print('│'))?;
             }
         }
 
-        if let Some(error) = error {
-            for line in error.split("\n") {
-                queue!(
-                    self.out,
-                    style::SetAttribute(style::Attribute::Italic),
-                    style::Print(line),
-                    style::SetAttribute(style::Attribute::Reset),
-                    cursor::MoveToNextLine(1)
-                )?;
-            }
+        // Messages and the command line take the last rows, drawn over the windows when there are several of them.
+        let mut bottom = error.as_deref().map_or(Vec::new(), |error| error.split('\n').collect::<Vec<_>>());
+        if *mode == Mode::COMMAND {
+            bottom.push(command);
+        }
+        let first_row = (self.size.1 as usize).saturating_sub(bottom.len());
+        for (row, line) in bottom.iter().enumerate() {
+            let italic = *mode != Mode::COMMAND || row + 1 < bottom.len();
+            queue!(
+                self.out,
+                cursor::MoveTo(0, (first_row + row) as u16),
+                terminal::Clear(ClearType::CurrentLine),
+                style::SetAttribute(if italic { style::Attribute::Italic } else { style::Attribute::Bold }),
+                style::Print(line),
+                style::SetAttribute(style::Attribute::Reset)
+            )?;
         }
 
+        let current = windows.iter().find(|window| window.current).map_or(Rect::default(), |window| window.rect);
+        let (x, y) = (
+            current.x + GUTTER_WIDTH + self.cursor.position.0.saturating_sub(self.offset.0),
+            current.y + self.cursor.position.1.saturating_sub(self.offset.1),
+        );
         match mode {
-            Mode::INSERT => queue!(
+            Mode::INSERT => queue!(self.out, cursor::SetCursorStyle::BlinkingBar, cursor::MoveTo(x as u16, y as u16))?,
+            Mode::COMMAND => queue!(
                 self.out,
                 cursor::SetCursorStyle::BlinkingBar,
-                cursor::MoveTo(self.cursor.position.0 - self.offset.0 + 6, self.cursor.position.1 - self.offset.1),
+                cursor::MoveTo(command.chars().count() as u16, self.size.1.saturating_sub(1))
             )?,
-            Mode::COMMAND => queue!(
+            _ => queue!(self.out, cursor::SetCursorStyle::DefaultUserShape, cursor::MoveTo(x as u16, y as u16))?,
+        }
+
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Draws the tab page labels on the first row, the current one in bold and the others reversed, filling the rest
+    /// of the row.
+    fn render_tabline(&mut self, tabs: &[(String, bool)]) -> Result<(), Report> {
+        let width = self.size.0 as usize;
+        let mut used = 0;
+        queue!(self.out, cursor::MoveTo(0, 0))?;
+
+        for (label, current) in tabs {
+            let label = clip(label, width - used);
+            used += label.chars().count();
+            queue!(
                 self.out,
-                style::SetAttribute(style::Attribute::Bold),
-                style::Print(format!(":{}", command)),
+                style::SetAttribute(if *current { style::Attribute::Bold } else { style::Attribute::Reverse }),
+                style::Print(label),
+                style::SetAttribute(style::Attribute::Reset)
+            )?;
+        }
+
+        queue!(
+            self.out,
+            style::SetAttribute(style::Attribute::Reverse),
+            style::Print(" ".repeat(width - used)),
+            style::SetAttribute(style::Attribute::Reset)
+        )?;
+        Ok(())
+    }
+
+    /// Draws the lines of a window with relative line numbers, and its status line if it has one.
+    fn render_window(&mut self, window: &WindowView) -> Result<(), Report> {
+        let rect = window.rect;
+        let text_rows = rect.height.saturating_sub(usize::from(window.status.is_some()));
+        let max_columns = rect.width.saturating_sub(GUTTER_WIDTH);
+        let (offset, cursor_line) = (window.offset, window.cursor.1);
+        let buffer = window.buffer;
+
+        for row in 0..text_rows {
+            queue!(self.out, cursor::MoveTo(rect.x as u16, (rect.y + row) as u16))?;
+
+            let rendering_line = offset.1 + row;
+            if rendering_line >= buffer.len_lines() {
+                queue!(self.out, style::Print(clip("   ~ ", rect.width)))?;
+                continue;
+            }
+
+            let relative_number = if rendering_line == cursor_line {
+                cursor_line.to_string()
+            } else {
+                cursor_line.abs_diff(rendering_line).to_string()
+            };
+
+            let line = buffer.line(rendering_line);
+            let trimmed_line = line.chars().skip(offset.0).take(max_columns).collect::<Vec<_>>();
+
+            // Selected or matched columns relative to the horizontal offset, padded with a space where they cover the
+            // line break.
+            let columns =
+                window.visual.and_then(|visual| visual.columns(rendering_line, line.chars().count())).or_else(|| {
+                    window.search.as_ref().and_then(|found| search::match_columns(buffer, found, rendering_line))
+                });
+            let (from, to) = match columns {
+                Some(columns) => (
+                    columns.start.saturating_sub(offset.0).min(trimmed_line.len()),
+                    columns.end.saturating_sub(offset.0).min(max_columns),
+                ),
+                None => (trimmed_line.len(), trimmed_line.len()),
+            };
+            let to = to.max(from);
+
+            let selected = (from..to).map(|i| trimmed_line.get(i).copied().unwrap_or(' ')).collect::<String>();
+            queue!(
+                self.out,
+                style::Print(clip(&format!("{:>4}  ", relative_number), rect.width)),
+                style::Print(trimmed_line[..from].iter().collect::<String>()),
+                style::SetAttribute(style::Attribute::Reverse),
+                style::Print(selected),
                 style::SetAttribute(style::Attribute::Reset),
-                cursor::SetCursorStyle::BlinkingBar,
-                cursor::MoveTo(command.len() as u16 + 1, self.size.1)
-            )?,
-            _ => queue!(
+                style::Print(trimmed_line[to.min(trimmed_line.len())..].iter().collect::<String>()),
+            )?;
+        }
+
+        if let Some(status) = &window.status {
+            queue!(
                 self.out,
-                cursor::SetCursorStyle::DefaultUserShape,
-                cursor::MoveTo(self.cursor.position.0 - self.offset.0 + 6, self.cursor.position.1 - self.offset.1)
-            )?,
+                cursor::MoveTo(rect.x as u16, (rect.y + text_rows) as u16),
+                style::SetAttribute(style::Attribute::Reverse),
+                style::SetAttribute(if window.current {
+                    style::Attribute::Bold
+                } else {
+                    style::Attribute::NormalIntensity
+                }),
+                style::Print(format!("{:<width$}", clip(status, rect.width), width = rect.width)),
+                style::SetAttribute(style::Attribute::Reset)
+            )?;
         }
 
-        self.out.flush()?;
         Ok(())
     }
 
-    pub fn cursor_move_by(&mut self, delta: (i16, i16), buffer: &Vec<String>) {
+    pub fn cursor_move_by(&mut self, delta: (isize, isize), buffer: &Rope) {
         self.cursor.move_by(delta, buffer);
         self.validate_offset();
     }
 
-    pub fn cursor_move_x(&mut self, new_x: u16, buffer: &Vec<String>) {
+    pub fn cursor_move_x(&mut self, new_x: usize, buffer: &Rope) {
         self.cursor.move_x(new_x, buffer);
         self.validate_offset();
     }
 
-    pub fn cursor_move_y(&mut self, new_y: u16, buffer: &Vec<String>) {
+    pub fn cursor_move_y(&mut self, new_y: usize, buffer: &Rope) {
         self.cursor.move_y(new_y, buffer);
         self.validate_offset();
     }
 
+    pub fn cursor_clamp(&mut self, buffer: &Rope) {
+        self.cursor.clamp_to_last_char(buffer);
+        self.validate_offset();
+    }
+
     fn validate_offset(&mut self) {
-        if self.cursor.position.0 >= self.offset.0 + self.size.0 {
-            self.offset.1 = self.cursor.position.1 - self.size.0 + 1;
+        let (width, height) = self.area;
+
+        if self.cursor.position.0 >= self.offset.0 + width {
+            self.offset.0 = self.cursor.position.0 - width + 1;
         }
-        if self.cursor.position.1 >= self.offset.1 + self.size.1 {
-            self.offset.1 = self.cursor.position.1 - self.size.1 + 1;
+        if self.cursor.position.1 >= self.offset.1 + height {
+            self.offset.1 = self.cursor.position.1 - height + 1;
         }
         if self.cursor.position.0 < self.offset.0 {
             self.offset.0 = self.cursor.position.0;
@@ -0,0 +1,174 @@
+use color_eyre::Report;
+use std::{ops::RangeInclusive, rc::Rc};
+
+use crate::editor::Editor;
+
+type HandlerFn = dyn Fn(&mut Editor, &ExArgs) -> Result<(), Report>;
+
+/// Lines an Ex command acts on when it is typed without a range.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum DefaultRange {
+    /// The command takes no range.
+    None,
+    CurrentLine,
+    WholeBuffer,
+}
+
+/// What `<Tab>` completes in the arguments of an Ex command.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    None,
+    File,
+    Command,
+}
+
+/// Parsed command line passed to a handler.
+pub struct ExArgs {
+    /// Range typed before the command, or its default one.
+    pub lines: RangeInclusive<usize>,
+    /// Whether `lines` was typed rather than the default.
+    pub range: bool,
+    pub bang: bool,
+    /// Everything after the name and `!`, with surrounding whitespace trimmed.
+    pub args: String,
+}
+
+#[derive(Clone)]
+pub struct ExCommand {
+    /// Full name, and the shortest abbreviation of it that is accepted.
+    pub name: String,
+    pub short: String,
+    pub bang: bool,
+    pub range: DefaultRange,
+    pub completion: Completion,
+    pub help: String,
+    pub handler: Rc<HandlerFn>,
+}
+
+pub struct ExCommands {
+    commands: Vec<ExCommand>,
+}
+
+impl ExCommand {
+    /// Name as Vim's help writes it, with the optional part in brackets, as in `d[elete]`.
+    pub fn usage(&self) -> String {
+        match self.name.len() > self.short.len() {
+            true => format!("{}[{}]", self.short, &self.name[self.short.len()..]),
+            false => self.name.clone(),
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        name.len() >= self.short.len() && self.name.starts_with(name)
+    }
+}
+
+impl ExCommands {
+    pub fn new() -> Self {
+        Self { commands: Vec::new() }
+    }
+
+    /// Registers a command under `usage`, such as `sor[t]`: the part before the brackets is the shortest accepted
+    /// abbreviation and the whole of it, without brackets, the full name. Earlier commands win over later ones that
+    /// an abbreviation also matches.
+    pub fn add_command<F>(&mut self, usage: &str, flags: &str, completion: Completion, help: &str, handler: F)
+    where
+        F: Fn(&mut Editor, &ExArgs) -> Result<(), Report> + 'static,
+    {
+        let (short, rest) = usage.split_once('[').unwrap_or((usage, ""));
+        let range = match (flags.contains('.'), flags.contains('%')) {
+            (true, _) => DefaultRange::CurrentLine,
+            (_, true) => DefaultRange::WholeBuffer,
+            _ => DefaultRange::None,
+        };
+
+        self.commands.push(ExCommand {
+            name: format!("{}{}", short, rest.trim_end_matches(']')),
+            short: short.to_string(),
+            bang: flags.contains('!'),
+            range,
+            completion,
+            help: help.to_string(),
+            handler: Rc::new(handler),
+        });
+    }
+
+    /// Command that `name`, possibly abbreviated, refers to.
+    pub fn find(&self, name: &str) -> Option<&ExCommand> {
+        self.commands
+            .iter()
+            .find(|command| command.name == name)
+            .or_else(|| self.commands.iter().find(|command| command.matches(name)))
+    }
+
+    /// Full names of the commands starting with `prefix`.
+    pub fn complete(&self, prefix: &str) -> Vec<&str> {
+        let mut names = self
+            .commands
+            .iter()
+            .filter(|command| command.name.starts_with(prefix))
+            .map(|command| command.name.as_str())
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &ExCommand> {
+        self.commands.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commands() -> ExCommands {
+        let mut commands = ExCommands::new();
+        for (usage, flags) in [("d[elete]", "."), ("de[bug]", ""), ("sor[t]", "!%"), ("t", "."), ("tab", "")] {
+            commands.add_command(usage, flags, Completion::None, "", |_, _| Ok(()));
+        }
+        commands
+    }
+
+    fn found(commands: &ExCommands, name: &str) -> Option<String> {
+        commands.find(name).map(|command| command.name.clone())
+    }
+
+    #[test]
+    fn registers_usage_and_flags() {
+        let commands = commands();
+        let sort = commands.find("sort").unwrap();
+        assert_eq!((sort.short.as_str(), sort.usage()), ("sor", "sor[t]".to_string()));
+        assert!(sort.bang && sort.range == DefaultRange::WholeBuffer);
+
+        let delete = commands.find("delete").unwrap();
+        assert!(!delete.bang && delete.range == DefaultRange::CurrentLine);
+        assert!(commands.find("tab").unwrap().range == DefaultRange::None);
+        assert_eq!(commands.find("t").unwrap().usage(), "t");
+    }
+
+    #[test]
+    fn finds_abbreviations() {
+        let commands = commands();
+        assert_eq!(found(&commands, "d"), Some("delete".to_string()));
+        assert_eq!(found(&commands, "del"), Some("delete".to_string()));
+        // `de` is the shortest name of `:debug`, but `:delete`, registered first, takes it.
+        assert_eq!(found(&commands, "de"), Some("delete".to_string()));
+        assert_eq!(found(&commands, "deb"), Some("debug".to_string()));
+        assert_eq!(found(&commands, "so"), None);
+        assert_eq!(found(&commands, "sorted"), None);
+        // A full name wins over an abbreviation of an earlier command.
+        assert_eq!(found(&commands, "t"), Some("t".to_string()));
+        assert_eq!(found(&commands, "ta"), None);
+        assert_eq!(found(&commands, "tab"), Some("tab".to_string()));
+    }
+
+    #[test]
+    fn completes_names() {
+        let commands = commands();
+        assert_eq!(commands.complete("de"), ["debug", "delete"]);
+        assert_eq!(commands.complete("t"), ["t", "tab"]);
+        assert_eq!(commands.complete(""), ["debug", "delete", "sort", "t", "tab"]);
+        assert!(commands.complete("x").is_empty());
+    }
+}
@@ -1,5 +1,5 @@
-use color_eyre::Report;
-use crossterm::event::KeyEvent;
+use color_eyre::{eyre::eyre, Report};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
 use crate::editor::{Editor, Mode};
@@ -12,8 +12,22 @@ struct KeyNode {
     action: Option<Rc<RefCell<ActionFn>>>,
 }
 
+/// Key sequence mapped with `:map` and its variants, as it is listed back.
+pub struct Mapping {
+    /// Mode letters as given to `add_keybind!`, such as `nvo`.
+    pub modes: String,
+    pub lhs: String,
+    pub rhs: String,
+    pub noremap: bool,
+}
+
 pub struct Keymap {
     root: HashMap<Mode, Rc<RefCell<KeyNode>>>,
+    /// Mappings added while running, looked up before the keybinds in `root` unless keys are fed without remapping.
+    user: HashMap<Mode, Rc<RefCell<KeyNode>>>,
+    mappings: Vec<Mapping>,
+    /// Mappings expanded since a key was last typed, to catch one that keeps expanding into itself.
+    expansions: usize,
     current: Option<Rc<RefCell<KeyNode>>>,
     numeric_prefix: Option<usize>,
 }
@@ -34,7 +48,7 @@ impl KeyNode {
         }
 
         let (key, sequence) = sequence.split_first().unwrap();
-        let next_node = self.children.entry(key.clone()).or_insert_with(KeyNode::new);
+        let next_node = self.children.entry(*key).or_insert_with(KeyNode::new);
 
         next_node.borrow_mut().insert(sequence.to_vec(), action)
     }
@@ -42,7 +56,14 @@ impl KeyNode {
 
 impl Keymap {
     pub fn new() -> Self {
-        Self { root: HashMap::new(), current: None, numeric_prefix: None }
+        Self {
+            root: HashMap::new(),
+            user: HashMap::new(),
+            mappings: Vec::new(),
+            expansions: 0,
+            current: None,
+            numeric_prefix: None,
+        }
     }
 
     pub fn add_keybind<F>(&mut self, modes: Vec<Mode>, sequence: Vec<KeyEvent>, action: F)
@@ -57,17 +78,70 @@ impl Keymap {
         }
     }
 
-    pub fn traverse(&mut self, mode: &Mode, event: KeyEvent) -> Result<Option<KeyEvent>, Report> {
-        let current_node = match self.current {
-            Some(ref node) => node.clone(),
-            None => self.root.get(mode).unwrap().clone(),
+    /// Maps `lhs` to `rhs` in `modes`, replacing an earlier mapping of it. The keys of `rhs` are fed back through the
+    /// keymap, where other mappings apply to them unless `noremap` is set.
+    pub fn add_mapping(&mut self, modes: &str, lhs: &str, rhs: &str, noremap: bool) {
+        let keys = parse_keys(rhs);
+        let action = Rc::new(RefCell::new(move |e: &mut Editor| {
+            e.keymap.expand_mapping()?;
+            e.keymap.set_count(e.count);
+            e.feed_keys(keys.clone(), !noremap);
+            Ok(())
+        }));
+
+        for mode in modes.chars().filter_map(mode_from_char) {
+            let mut root_node = self.user.entry(mode).or_insert_with(KeyNode::new).borrow_mut();
+            root_node.insert(parse_keys(lhs), action.clone());
+        }
+
+        self.mappings.retain(|mapping| mapping.lhs != lhs || mapping.modes != modes);
+        self.mappings.push(Mapping { modes: modes.to_string(), lhs: lhs.to_string(), rhs: rhs.to_string(), noremap });
+    }
+
+    pub fn mappings(&self) -> impl Iterator<Item = &Mapping> {
+        self.mappings.iter()
+    }
+
+    /// Counts a mapping being expanded, failing once so many have been in a row that one must be expanding into
+    /// itself.
+    fn expand_mapping(&mut self) -> Result<(), Report> {
+        self.expansions += 1;
+        if self.expansions > 1000 {
+            return Err(eyre!("Recursive mapping"));
+        }
+        Ok(())
+    }
+
+    /// Called for every key typed, which ends any chain of mappings expanding.
+    pub fn key_typed(&mut self) {
+        self.expansions = 0;
+    }
+
+    /// Follows `event` down the keymap of `mode`. At the start of a sequence, a key that starts a mapping leads into
+    /// the mappings instead of the keybinds, unless `remap` is unset.
+    pub fn traverse(&mut self, mode: &Mode, event: KeyEvent, remap: bool) -> Result<Option<KeyEvent>, Report> {
+        let user = self.user.get(mode).filter(|node| remap && node.borrow().children.contains_key(&event));
+        let current_node = match (&self.current, user, self.root.get(mode)) {
+            (Some(node), _, _) | (None, Some(node), _) | (None, None, Some(node)) => node.clone(),
+            (None, None, None) => return Ok(Some(event)),
         };
 
+        // Digits typed before a sequence are its count, except for a leading `0`, which is left for a keybind. Modes
+        // that take text, such as Insert and Command-line, have no counts.
+        let digit = event_to_digit(&event).filter(|_| matches!(mode, Mode::NORMAL | Mode::VISUAL | Mode::OPERATOR));
+        if let (None, Some(digit)) = (&self.current, digit) {
+            if digit != 0 || self.numeric_prefix.is_some() {
+                self.numeric_prefix = Some(self.numeric_prefix.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                return Ok(None);
+            }
+        }
+
         let next_node = match current_node.borrow().children.get(&event) {
             Some(node) => node.clone(),
             None => {
-                if let Some(digit) = event_to_digit(&event) {
-                    self.numeric_prefix = Some(self.numeric_prefix.unwrap_or(0) * 10 + digit);
+                if let Some(digit) = digit {
+                    self.numeric_prefix =
+                        Some(self.numeric_prefix.unwrap_or(0).saturating_mul(10).saturating_add(digit));
                     return Ok(None);
                 }
 
@@ -87,10 +161,6 @@ impl Keymap {
     }
 
     pub fn get_action(&self) -> Option<Rc<RefCell<ActionFn>>> {
-        if self.current.is_none() {
-            return None;
-        }
-
         self.current.as_ref()?.borrow().action.clone()
     }
 
@@ -102,14 +172,135 @@ impl Keymap {
         self.current.is_none()
     }
 
-    pub fn repeats(&mut self) -> usize {
-        self.numeric_prefix.take().unwrap_or(1)
+    /// Whether digits of a count have been typed for the next sequence.
+    pub fn has_count(&self) -> bool {
+        self.numeric_prefix.is_some()
+    }
+
+    /// Restores a count taken by an action, so it applies to the next sequence instead, as with `3"ayy`.
+    pub fn set_count(&mut self, count: Option<usize>) {
+        self.numeric_prefix = count;
+    }
+
+    /// Takes the numeric prefix typed before the current sequence, if any.
+    pub fn count(&mut self) -> Option<usize> {
+        self.numeric_prefix.take()
+    }
+}
+
+/// Mode a letter of `add_keybind!` and `:map` mode lists stands for.
+pub(crate) fn mode_from_char(c: char) -> Option<Mode> {
+    match c {
+        'n' => Some(Mode::NORMAL),
+        'v' => Some(Mode::VISUAL),
+        'c' => Some(Mode::COMMAND),
+        'i' => Some(Mode::INSERT),
+        'o' => Some(Mode::OPERATOR),
+        _ => None,
+    }
+}
+
+/// Parses a key sequence in Vim's notation, such as `dw`, `<C-r>` or `<lt>`. Anything between angle brackets that is
+/// not a key name is taken literally.
+pub(crate) fn parse_keys(sequence: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = sequence;
+
+    while let Some(c) = rest.chars().next() {
+        let named = match c {
+            '<' => rest.find('>').and_then(|end| Some((parse_key_name(&rest[1..end])?, end + 1))),
+            _ => None,
+        };
+
+        let (key, len) = named.unwrap_or((KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE), c.len_utf8()));
+        keys.push(key);
+        rest = &rest[len..];
+    }
+
+    keys
+}
+
+fn parse_key_name(name: &str) -> Option<KeyEvent> {
+    let (modifiers, key) = match name.split_once('-') {
+        Some((modifier, key)) if !key.is_empty() => {
+            let modifiers = match modifier {
+                "C" => KeyModifiers::CONTROL,
+                "S" => KeyModifiers::SHIFT,
+                "A" | "M" => KeyModifiers::ALT,
+                _ => return None,
+            };
+            (modifiers, key)
+        }
+        _ => (KeyModifiers::NONE, name),
+    };
+
+    let code = match key {
+        "BS" => KeyCode::Backspace,
+        "Tab" => KeyCode::Tab,
+        "CR" | "Enter" | "Return" => KeyCode::Enter,
+        "Esc" => KeyCode::Esc,
+        "Space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "Up" => KeyCode::Up,
+        "Down" => KeyCode::Down,
+        "Left" => KeyCode::Left,
+        "Right" => KeyCode::Right,
+        "Insert" => KeyCode::Insert,
+        "Del" => KeyCode::Delete,
+        "Home" => KeyCode::Home,
+        "End" => KeyCode::End,
+        "PageUp" => KeyCode::PageUp,
+        "PageDown" => KeyCode::PageDown,
+        key if key.starts_with('F') && key[1..].parse::<u8>().is_ok() => KeyCode::F(key[1..].parse().unwrap()),
+        key if key.chars().count() == 1 && modifiers != KeyModifiers::NONE => KeyCode::Char(key.chars().next()?),
+        _ => return None,
+    };
+
+    Some(KeyEvent::new(code, modifiers))
+}
+
+/// Writes `key` in the notation read by `parse_keys`, so recorded keys can be kept as text in a register.
+pub(crate) fn key_notation(key: &KeyEvent) -> String {
+    let name = match key.code {
+        KeyCode::Char('<') => "lt".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Backspace => "BS".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Enter => "CR".to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Insert => "Insert".to_string(),
+        KeyCode::Delete => "Del".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        _ => return String::new(),
+    };
+
+    let modifier = if key.modifiers.contains(KeyModifiers::CONTROL) {
+        "C-"
+    } else if key.modifiers.contains(KeyModifiers::ALT) {
+        "A-"
+    } else {
+        ""
+    };
+
+    match key.code {
+        KeyCode::Char(c) if modifier.is_empty() && c != '<' => name,
+        _ => format!("<{}{}>", modifier, name),
     }
 }
 
 fn event_to_digit(event: &KeyEvent) -> Option<usize> {
     match event {
-        KeyEvent { code: crossterm::event::KeyCode::Char(c), .. } if c.is_ascii_digit() => {
+        KeyEvent { code: KeyCode::Char(c), modifiers, .. }
+            if c.is_ascii_digit() && !modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+        {
             c.to_digit(10).map(|d| d as usize)
         }
         _ => None,
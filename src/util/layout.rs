@@ -0,0 +1,304 @@
+/// Area of the screen, in cells.
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn contains(&self, (x, y): (usize, usize)) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+}
+
+/// How windows share the screen: a single window, or several side by side or stacked, each with its width or height
+/// in cells. Side by side windows have a column between each pair for a separator.
+pub enum Layout {
+    Window(usize),
+    Split { vertical: bool, children: Vec<(Layout, usize)> },
+}
+
+/// Scales `sizes` to add up to `total`, keeping their proportions and at least one cell each where there is room.
+fn fit(sizes: &mut [usize], total: usize) {
+    let sum = sizes.iter().sum::<usize>().max(1);
+    for size in sizes.iter_mut() {
+        *size = (*size * total / sum).max(1);
+    }
+
+    // Rounding leaves the sizes short of the total, and the minimum of one cell can take them over it, which the
+    // largest one makes up for.
+    let mut fitted = sizes.iter().sum::<usize>();
+    while fitted != total {
+        let Some(largest) = (0..sizes.len()).max_by_key(|&i| sizes[i]) else {
+            return;
+        };
+        if fitted < total {
+            sizes[largest] += total - fitted;
+            fitted = total;
+        } else {
+            let cut = (fitted - total).min(sizes[largest] - 1);
+            if cut == 0 {
+                return;
+            }
+            sizes[largest] -= cut;
+            fitted -= cut;
+        }
+    }
+}
+
+impl Layout {
+    /// Windows in the order they appear, from the top left.
+    pub fn windows(&self) -> Vec<usize> {
+        match self {
+            Layout::Window(id) => vec![*id],
+            Layout::Split { children, .. } => children.iter().flat_map(|(child, _)| child.windows()).collect(),
+        }
+    }
+
+    fn contains(&self, window: usize) -> bool {
+        match self {
+            Layout::Window(id) => *id == window,
+            Layout::Split { children, .. } => children.iter().any(|(child, _)| child.contains(window)),
+        }
+    }
+
+    /// Gives half the place of `window` to `new`, which goes to its left when `vertical` is set and above it
+    /// otherwise.
+    pub fn split(&mut self, window: usize, new: usize, vertical: bool) {
+        match self {
+            Layout::Window(id) if *id == window => {
+                let children = vec![(Layout::Window(new), 1), (Layout::Window(window), 1)];
+                *self = Layout::Split { vertical, children };
+            }
+            Layout::Window(_) => {}
+            Layout::Split { vertical: split_vertical, children } => {
+                let index = children.iter().position(|(child, _)| matches!(child, Layout::Window(id) if *id == window));
+                match index {
+                    // Splitting the same way as the parent adds a sibling rather than nesting another split.
+                    Some(index) if *split_vertical == vertical => {
+                        let size = children[index].1;
+                        children[index].1 = size - size / 2;
+                        children.insert(index, (Layout::Window(new), size / 2));
+                    }
+                    _ => children.iter_mut().for_each(|(child, _)| child.split(window, new, vertical)),
+                }
+            }
+        }
+    }
+
+    /// Removes `window`, giving its place to the window before it, or after it when it is the first. The only window
+    /// left is never removed, for which this returns false.
+    pub fn close(&mut self, window: usize) -> bool {
+        let Layout::Split { vertical, children } = self else {
+            return false;
+        };
+
+        match children.iter().position(|(child, _)| matches!(child, Layout::Window(id) if *id == window)) {
+            Some(index) => {
+                let (_, size) = children.remove(index);
+                let neighbour = index.saturating_sub(1);
+                children[neighbour].1 += size + usize::from(*vertical);
+            }
+            None => {
+                if !children.iter_mut().any(|(child, _)| child.close(window)) {
+                    return false;
+                }
+            }
+        }
+
+        if children.len() == 1 {
+            let (child, _) = children.remove(0);
+            *self = child;
+        }
+        true
+    }
+
+    /// Grows `window` by `delta` cells, or shrinks it when negative, in width when `vertical` is set and in height
+    /// otherwise, within the nearest split of windows side by side or stacked that it is part of. Growing takes cells
+    /// from the windows after it, nearest first, then from the ones before it, and shrinking gives them to the next
+    /// one, or the one before it when it is the last. Returns whether there is such a split.
+    pub fn resize(&mut self, window: usize, vertical: bool, delta: isize) -> bool {
+        let Layout::Split { vertical: split_vertical, children } = self else {
+            return false;
+        };
+        let Some(index) = children.iter().position(|(child, _)| child.contains(window)) else {
+            return false;
+        };
+        if children[index].0.resize(window, vertical, delta) {
+            return true;
+        }
+        if *split_vertical != vertical {
+            return false;
+        }
+
+        if delta < 0 {
+            let shrink = delta.unsigned_abs().min(children[index].1 - 1);
+            let neighbour = if index + 1 < children.len() { index + 1 } else { index - 1 };
+            children[index].1 -= shrink;
+            children[neighbour].1 += shrink;
+            return true;
+        }
+
+        let mut grow = delta.unsigned_abs();
+        for neighbour in (index + 1..children.len()).chain((0..index).rev()) {
+            let taken = grow.min(children[neighbour].1.saturating_sub(1));
+            children[neighbour].1 -= taken;
+            children[index].1 += taken;
+            grow -= taken;
+        }
+        true
+    }
+
+    /// Makes all windows the same size, as far as the splits they are in allow.
+    pub fn equalize(&mut self) {
+        if let Layout::Split { children, .. } = self {
+            for (child, size) in children {
+                *size = 1;
+                child.equalize();
+            }
+        }
+    }
+
+    /// Places every window in `area`, fitting the sizes of each split to it. Returns the area of each window, and
+    /// the columns between windows side by side.
+    pub fn arrange(&mut self, area: Rect) -> (Vec<(usize, Rect)>, Vec<Rect>) {
+        let (mut windows, mut separators) = (Vec::new(), Vec::new());
+        self.place(area, &mut windows, &mut separators);
+        (windows, separators)
+    }
+
+    fn place(&mut self, area: Rect, windows: &mut Vec<(usize, Rect)>, separators: &mut Vec<Rect>) {
+        let (vertical, children) = match self {
+            Layout::Window(id) => return windows.push((*id, area)),
+            Layout::Split { vertical, children } => (*vertical, children),
+        };
+
+        let (start, total) = if vertical { (area.x, area.width) } else { (area.y, area.height) };
+        let gaps = if vertical { children.len() - 1 } else { 0 };
+        let mut sizes = children.iter().map(|(_, size)| *size).collect::<Vec<_>>();
+        fit(&mut sizes, total.saturating_sub(gaps));
+
+        let mut start = start;
+        let count = children.len();
+        for (i, ((child, size), fitted)) in children.iter_mut().zip(sizes).enumerate() {
+            *size = fitted;
+            let rect = match vertical {
+                true => Rect { x: start, width: fitted, ..area },
+                false => Rect { y: start, height: fitted, ..area },
+            };
+            child.place(rect, windows, separators);
+
+            start += fitted;
+            if vertical && i + 1 < count {
+                separators.push(Rect { x: start, width: 1, ..area });
+                start += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect { x: 0, y: 0, width: 80, height: 20 };
+
+    /// A window with its position and size.
+    type Placed = (usize, (usize, usize, usize, usize));
+
+    /// Position and size of each window once `layout` is arranged in `AREA`, with the number of separators.
+    fn arranged(layout: &mut Layout) -> (Vec<Placed>, usize) {
+        let (windows, separators) = layout.arrange(AREA);
+        let windows = windows.into_iter().map(|(id, rect)| (id, (rect.x, rect.y, rect.width, rect.height))).collect();
+        (windows, separators.len())
+    }
+
+    /// Splits as the editor does, which arranges the windows after each split so sizes are in cells.
+    fn split(layout: &mut Layout, window: usize, new: usize, vertical: bool) {
+        layout.split(window, new, vertical);
+        arranged(layout);
+    }
+
+    #[test]
+    fn fit_adds_up_to_total() {
+        for (sizes, total) in [(vec![1, 1, 1], 10), (vec![1, 100], 5), (vec![7, 3, 0], 79), (vec![5], 1)] {
+            let mut sizes = sizes;
+            fit(&mut sizes, total);
+            assert_eq!(sizes.iter().sum::<usize>(), total);
+            assert!(sizes.iter().all(|&size| size >= 1));
+        }
+
+        let mut sizes = vec![3, 1];
+        fit(&mut sizes, 8);
+        assert_eq!(sizes, vec![6, 2]);
+
+        // Without room for a cell each, every size stays at one.
+        let mut sizes = vec![1, 1, 1];
+        fit(&mut sizes, 2);
+        assert_eq!(sizes, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn split_fills_area_minus_separators() {
+        let mut layout = Layout::Window(0);
+        split(&mut layout, 0, 1, true);
+        split(&mut layout, 1, 2, false);
+        split(&mut layout, 0, 3, true);
+        assert_eq!(layout.windows(), vec![2, 1, 3, 0]);
+
+        // Stacked windows share the height, and windows side by side the width left by the separators between them.
+        let (windows, separators) = arranged(&mut layout);
+        let [(_, top), (_, bottom), (_, middle), (_, right)] = windows[..] else { panic!() };
+        assert_eq!(separators, 2);
+        assert_eq!((top.2, top.1 + top.3, top.3 + bottom.3), (bottom.2, bottom.1, AREA.height));
+        assert_eq!((middle.0, right.0), (top.2 + 1, middle.0 + middle.2 + 1));
+        assert_eq!(top.2 + middle.2 + right.2 + separators, AREA.width);
+        assert_eq!((middle.3, right.3), (AREA.height, AREA.height));
+        assert!(middle.2 >= 19 && right.2 >= 19);
+    }
+
+    #[test]
+    fn close_collapses_single_child_split() {
+        let mut layout = Layout::Window(0);
+        split(&mut layout, 0, 1, true);
+        split(&mut layout, 1, 2, false);
+        arranged(&mut layout);
+
+        assert!(layout.close(2));
+        assert!(matches!(&layout, Layout::Split { children, .. } if matches!(children[0].0, Layout::Window(1))));
+        let (windows, separators) = arranged(&mut layout);
+        assert_eq!(windows.iter().map(|(_, rect)| rect.2).sum::<usize>() + separators, AREA.width);
+
+        assert!(layout.close(1));
+        assert!(matches!(layout, Layout::Window(0)));
+        assert!(!layout.close(0));
+        assert!(!layout.close(5));
+    }
+
+    #[test]
+    fn resize_keeps_a_cell_for_each_window() {
+        let mut layout = Layout::Window(0);
+        split(&mut layout, 0, 1, true);
+        split(&mut layout, 0, 2, true);
+        let widths = |layout: &mut Layout| arranged(layout).0.into_iter().map(|(_, rect)| rect.2).collect::<Vec<_>>();
+        layout.equalize();
+        assert_eq!(widths(&mut layout), vec![26, 26, 26]);
+
+        assert!(layout.resize(2, true, 10));
+        assert_eq!(widths(&mut layout), vec![26, 36, 16]);
+        assert!(layout.resize(2, true, 100));
+        assert_eq!(widths(&mut layout), vec![1, 76, 1]);
+        assert!(layout.resize(2, true, -100));
+        assert_eq!(widths(&mut layout), vec![1, 1, 76]);
+        assert!(layout.resize(0, true, -10));
+        assert_eq!(widths(&mut layout), vec![1, 11, 66]);
+
+        // Windows side by side have no split to grow in height with.
+        assert!(!layout.resize(2, false, 1));
+
+        layout.equalize();
+        assert_eq!(widths(&mut layout), vec![26, 26, 26]);
+    }
+}
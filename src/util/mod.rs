@@ -1,5 +1,14 @@
+pub(crate) mod clipboard;
 pub(crate) mod display;
+pub(crate) mod ex_commands;
 pub(crate) mod keymap;
+pub(crate) mod layout;
+pub(crate) mod rope;
+pub(crate) mod statusline;
 
+pub(crate) use self::clipboard::Clipboard;
 pub(crate) use self::display::Display;
+pub(crate) use self::ex_commands::ExCommands;
 pub(crate) use self::keymap::Keymap;
+pub(crate) use self::rope::Rope;
+pub(crate) use self::statusline::StatusLine;
@@ -1,91 +1,661 @@
 use std::fmt;
-use std::rc::Rc;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Upper bound on the size of a leaf in bytes. Leaves are split on construction and adjacent leaves are merged
+/// whenever the result stays under this size, so edits never degrade the tree into single-char leaves.
+const MAX_LEAF: usize = 1024;
+
+#[derive(Clone, Copy, Default)]
+struct TextInfo {
+    bytes: usize,
+    chars: usize,
+    newlines: usize,
+}
+
+impl TextInfo {
+    fn of(text: &str) -> Self {
+        Self { bytes: text.len(), chars: text.chars().count(), newlines: text.matches('\n').count() }
+    }
+
+    fn combine(self, other: Self) -> Self {
+        Self {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars,
+            newlines: self.newlines + other.newlines,
+        }
+    }
+}
 
 enum Node {
-    Leaf(String),
-    Internal {
-        left: Rc<Node>,
-        right: Rc<Node>,
-        weight: usize,
-    },
+    Leaf { text: String, info: TextInfo },
+    Internal { left: Arc<Node>, right: Arc<Node>, info: TextInfo, height: usize },
 }
 
 impl Node {
-    fn new_leaf(text: &str) -> Rc<Self> {
-        Rc::new(Node::Leaf(text.to_string()))
+    fn new_leaf(text: String) -> Arc<Self> {
+        Arc::new(Node::Leaf { info: TextInfo::of(&text), text })
+    }
+
+    fn empty() -> Arc<Self> {
+        Node::new_leaf(String::new())
     }
 
-    fn new_internal(left: Rc<Self>, right: Rc<Self>) -> Rc<Self> {
-        Rc::new(Node::Internal {
-            weight: left.len(),
+    /// Joins two subtrees of similar height, merging them into a single leaf when both are small leaves.
+    fn new_internal(left: Arc<Self>, right: Arc<Self>) -> Arc<Self> {
+        if let (Node::Leaf { text: l, .. }, Node::Leaf { text: r, .. }) = (left.as_ref(), right.as_ref()) {
+            if l.len() + r.len() <= MAX_LEAF {
+                return Node::new_leaf(format!("{}{}", l, r));
+            }
+        }
+
+        Arc::new(Node::Internal {
+            info: left.info().combine(right.info()),
+            height: left.height().max(right.height()) + 1,
             left,
             right,
         })
     }
 
-    fn len(&self) -> usize {
+    /// Builds a balanced tree out of `text`, chunked into leaves of at most `MAX_LEAF` bytes.
+    fn from_str(text: &str) -> Arc<Self> {
+        let mut leaves = Vec::with_capacity(text.len() / MAX_LEAF + 1);
+        let mut rest = text;
+        while rest.len() > MAX_LEAF {
+            let mut split = MAX_LEAF;
+            while !rest.is_char_boundary(split) {
+                split -= 1;
+            }
+
+            let (chunk, tail) = rest.split_at(split);
+            leaves.push(Node::new_leaf(chunk.to_string()));
+            rest = tail;
+        }
+        leaves.push(Node::new_leaf(rest.to_string()));
+
+        Node::build(&leaves)
+    }
+
+    fn build(leaves: &[Arc<Self>]) -> Arc<Self> {
+        match leaves {
+            [] => Node::empty(),
+            [leaf] => leaf.clone(),
+            _ => {
+                let (left, right) = leaves.split_at(leaves.len() / 2);
+                Node::new_internal(Node::build(left), Node::build(right))
+            }
+        }
+    }
+
+    fn info(&self) -> TextInfo {
+        match self {
+            Node::Leaf { info, .. } | Node::Internal { info, .. } => *info,
+        }
+    }
+
+    fn height(&self) -> usize {
         match self {
-            Node::Leaf(s) => s.len(),
-            Node::Internal { weight, right, .. } => weight + right.len(),
+            Node::Leaf { .. } => 0,
+            Node::Internal { height, .. } => *height,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.info().bytes == 0
+    }
+
+    /// Concatenates two trees of arbitrary height, keeping the result height-balanced.
+    fn join(left: Arc<Self>, right: Arc<Self>) -> Arc<Self> {
+        if left.is_empty() {
+            return right;
+        }
+        if right.is_empty() {
+            return left;
+        }
+
+        let (left_height, right_height) = (left.height(), right.height());
+        if left_height > right_height + 1 {
+            Node::join_right(left, right)
+        } else if right_height > left_height + 1 {
+            Node::join_left(left, right)
+        } else {
+            Node::new_internal(left, right)
+        }
+    }
+
+    /// Joins `right` into the right spine of the taller `left` tree.
+    fn join_right(left: Arc<Self>, right: Arc<Self>) -> Arc<Self> {
+        let Node::Internal { left: outer, right: inner, .. } = left.as_ref() else {
+            return Node::new_internal(left, right);
+        };
+
+        if inner.height() <= right.height() + 1 {
+            let joined = Node::new_internal(inner.clone(), right);
+            if joined.height() <= outer.height() + 1 {
+                Node::new_internal(outer.clone(), joined)
+            } else {
+                Node::rotate_left(Node::new_internal(outer.clone(), Node::rotate_right(joined)))
+            }
+        } else {
+            let joined = Node::join_right(inner.clone(), right);
+            let node = Node::new_internal(outer.clone(), joined.clone());
+            if joined.height() <= outer.height() + 1 {
+                node
+            } else {
+                Node::rotate_left(node)
+            }
         }
     }
+
+    /// Joins `left` into the left spine of the taller `right` tree.
+    fn join_left(left: Arc<Self>, right: Arc<Self>) -> Arc<Self> {
+        let Node::Internal { left: inner, right: outer, .. } = right.as_ref() else {
+            return Node::new_internal(left, right);
+        };
+
+        if inner.height() <= left.height() + 1 {
+            let joined = Node::new_internal(left, inner.clone());
+            if joined.height() <= outer.height() + 1 {
+                Node::new_internal(joined, outer.clone())
+            } else {
+                Node::rotate_right(Node::new_internal(Node::rotate_left(joined), outer.clone()))
+            }
+        } else {
+            let joined = Node::join_left(left, inner.clone());
+            let node = Node::new_internal(joined.clone(), outer.clone());
+            if joined.height() <= outer.height() + 1 {
+                node
+            } else {
+                Node::rotate_right(node)
+            }
+        }
+    }
+
+    fn rotate_left(node: Arc<Self>) -> Arc<Self> {
+        match node.as_ref() {
+            Node::Internal { left, right, .. } => match right.as_ref() {
+                Node::Internal { left: middle, right: outer, .. } => {
+                    Node::new_internal(Node::new_internal(left.clone(), middle.clone()), outer.clone())
+                }
+                Node::Leaf { .. } => node,
+            },
+            Node::Leaf { .. } => node,
+        }
+    }
+
+    fn rotate_right(node: Arc<Self>) -> Arc<Self> {
+        match node.as_ref() {
+            Node::Internal { left, right, .. } => match left.as_ref() {
+                Node::Internal { left: outer, right: middle, .. } => {
+                    Node::new_internal(outer.clone(), Node::new_internal(middle.clone(), right.clone()))
+                }
+                Node::Leaf { .. } => node,
+            },
+            Node::Leaf { .. } => node,
+        }
+    }
+
+    /// Splits the tree before `char_idx`, rebalancing both halves.
+    fn split(node: &Arc<Self>, char_idx: usize) -> (Arc<Self>, Arc<Self>) {
+        match node.as_ref() {
+            Node::Leaf { text, .. } => {
+                let (left, right) = text.split_at(byte_index(text, char_idx));
+                (Node::new_leaf(left.to_string()), Node::new_leaf(right.to_string()))
+            }
+            Node::Internal { left, right, .. } => {
+                let left_chars = left.info().chars;
+                if char_idx < left_chars {
+                    let (a, b) = Node::split(left, char_idx);
+                    (a, Node::join(b, right.clone()))
+                } else if char_idx > left_chars {
+                    let (a, b) = Node::split(right, char_idx - left_chars);
+                    (Node::join(left.clone(), a), b)
+                } else {
+                    (left.clone(), right.clone())
+                }
+            }
+        }
+    }
+}
+
+/// Byte offset of the char at `index` within `text`, or `text.len()` when `index` is past the end.
+fn byte_index(text: &str, index: usize) -> usize {
+    text.char_indices().nth(index).map_or(text.len(), |(i, _)| i)
 }
 
+/// Text buffer stored as a height-balanced binary tree of string leaves.
+///
+/// Every node caches the byte, char and newline counts of its subtree, so index conversions and line lookups are
+/// O(log n). All indices are char indices unless stated otherwise, and lines are separated by `'\n'`.
+///
+/// Nodes are immutable and shared between versions, so cloning a rope is O(1) and edits only copy the path to the
+/// changed leaves. Nodes are reference counted with `Arc`, so snapshots can be handed to other threads or tasks.
+#[derive(Clone)]
 pub struct Rope {
-    root: Rc<Node>,
+    root: Arc<Node>,
 }
 
 impl Rope {
     pub fn new(text: &str) -> Self {
-        Self {
-            root: Node::new_leaf(text),
+        Self { root: Node::from_str(text) }
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.root.info().chars
+    }
+
+    #[allow(dead_code)]
+    pub fn len_bytes(&self) -> usize {
+        self.root.info().bytes
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.root.info().newlines + 1
+    }
+
+    pub fn char_to_byte(&self, char_idx: usize) -> usize {
+        let (mut node, mut char_idx, mut bytes) = (&self.root, char_idx.min(self.len_chars()), 0);
+        loop {
+            match node.as_ref() {
+                Node::Leaf { text, .. } => return bytes + byte_index(text, char_idx),
+                Node::Internal { left, right, .. } => {
+                    let info = left.info();
+                    if char_idx < info.chars {
+                        node = left;
+                    } else {
+                        (node, char_idx, bytes) = (right, char_idx - info.chars, bytes + info.bytes);
+                    }
+                }
+            }
         }
     }
 
-    pub fn len(&self) -> usize {
-        self.root.len()
+    /// Char index of the char containing `byte_idx`.
+    pub fn byte_to_char(&self, byte_idx: usize) -> usize {
+        let (mut node, mut byte_idx, mut chars) = (&self.root, byte_idx.min(self.len_bytes()), 0);
+        loop {
+            match node.as_ref() {
+                Node::Leaf { text, .. } => {
+                    return chars + text.char_indices().take_while(|&(i, _)| i < byte_idx).count();
+                }
+                Node::Internal { left, right, .. } => {
+                    let info = left.info();
+                    if byte_idx < info.bytes {
+                        node = left;
+                    } else {
+                        (node, byte_idx, chars) = (right, byte_idx - info.bytes, chars + info.chars);
+                    }
+                }
+            }
+        }
     }
 
-    pub fn insert(&mut self, index: usize, text: &str) {
-        self.root = self.insert_rec(&self.root, index, text);
+    /// Char index of the first char of `line_idx`, clamped to the end of the rope.
+    pub fn line_to_char(&self, line_idx: usize) -> usize {
+        if line_idx == 0 {
+            return 0;
+        }
+        if line_idx >= self.len_lines() {
+            return self.len_chars();
+        }
+
+        // Find the newline terminating the previous line; `line_idx` counts the newlines still to skip.
+        let (mut node, mut line_idx, mut chars) = (&self.root, line_idx, 0);
+        loop {
+            match node.as_ref() {
+                Node::Leaf { text, .. } => {
+                    let newline = text.chars().enumerate().filter(|&(_, c)| c == '\n').nth(line_idx - 1);
+                    return chars + newline.map_or(0, |(i, _)| i) + 1;
+                }
+                Node::Internal { left, right, .. } => {
+                    let info = left.info();
+                    if line_idx <= info.newlines {
+                        node = left;
+                    } else {
+                        (node, line_idx, chars) = (right, line_idx - info.newlines, chars + info.chars);
+                    }
+                }
+            }
+        }
     }
 
-    fn insert_rec(&self, node: &Rc<Node>, index: usize, text: &str) -> Rc<Node> {
-        match node.as_ref() {
-            Node::Leaf(existing) => {
-                let mut new_text = String::with_capacity(existing.len() + text.len());
-                new_text.push_str(&existing[..index]);
-                new_text.push_str(text);
-                new_text.push_str(&existing[index..]);
-                Node::new_leaf(&new_text)
-            }
-            Node::Internal { left, right, weight } => {
-                if index < *weight {
-                    let left = self.insert_rec(left, index, text);
-                    return Node::new_internal(left, right.clone());
-                } else {
-                    let right = self.insert_rec(right, index - weight, text);
-                    return Node::new_internal(left.clone(), right);
+    pub fn char_to_line(&self, char_idx: usize) -> usize {
+        let (mut node, mut char_idx, mut lines) = (&self.root, char_idx.min(self.len_chars()), 0);
+        loop {
+            match node.as_ref() {
+                Node::Leaf { text, .. } => return lines + text.chars().take(char_idx).filter(|&c| c == '\n').count(),
+                Node::Internal { left, right, .. } => {
+                    let info = left.info();
+                    if char_idx < info.chars {
+                        node = left;
+                    } else {
+                        (node, char_idx, lines) = (right, char_idx - info.chars, lines + info.newlines);
+                    }
                 }
             }
         }
     }
 
-    fn collect_text(&self, node: &Rc<Node>, result: &mut String) {
+    /// Contents of `line_idx` without its trailing newline.
+    pub fn line(&self, line_idx: usize) -> String {
+        let start = self.line_to_char(line_idx);
+        self.slice(start..start + self.line_len(line_idx))
+    }
+
+    /// Length of `line_idx` in chars, excluding its trailing newline.
+    pub fn line_len(&self, line_idx: usize) -> usize {
+        let start = self.line_to_char(line_idx);
+        let end = self.line_to_char(line_idx + 1);
+        if line_idx + 1 < self.len_lines() {
+            end - start - 1
+        } else {
+            end - start
+        }
+    }
+
+    /// The char at `char_idx`, or `None` past the end of the rope.
+    pub fn char(&self, char_idx: usize) -> Option<char> {
+        let (mut node, mut char_idx) = (&self.root, char_idx);
+        loop {
+            match node.as_ref() {
+                Node::Leaf { text, .. } => return text.chars().nth(char_idx),
+                Node::Internal { left, right, .. } => {
+                    let left_chars = left.info().chars;
+                    if char_idx < left_chars {
+                        node = left;
+                    } else {
+                        (node, char_idx) = (right, char_idx - left_chars);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn slice(&self, range: Range<usize>) -> String {
+        let mut result = String::new();
+        Self::collect_range(&self.root, range.start..range.end.min(self.len_chars()), &mut result);
+        result
+    }
+
+    pub fn insert(&mut self, char_idx: usize, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+
+        let (left, right) = Node::split(&self.root, char_idx);
+        self.root = Node::join(Node::join(left, Node::from_str(text)), right);
+    }
+
+    pub fn remove(&mut self, range: Range<usize>) {
+        if range.start >= range.end {
+            return;
+        }
+
+        let (left, rest) = Node::split(&self.root, range.start);
+        let (_, right) = Node::split(&rest, range.end - range.start);
+        self.root = Node::join(left, right);
+    }
+
+    /// Splits the rope before `char_idx`, returning both halves and leaving `self` untouched.
+    #[allow(dead_code)]
+    pub fn split_at(&self, char_idx: usize) -> (Rope, Rope) {
+        let (left, right) = Node::split(&self.root, char_idx);
+        (Rope { root: left }, Rope { root: right })
+    }
+
+    /// Appends `other` to the end of the rope in O(log n).
+    #[allow(dead_code)]
+    pub fn append(&mut self, other: Rope) {
+        self.root = Node::join(self.root.clone(), other.root);
+    }
+
+    /// Takes an O(1) immutable copy of the rope that later edits to `self` will not affect.
+    #[allow(dead_code)]
+    pub fn snapshot(&self) -> Rope {
+        self.clone()
+    }
+
+    /// Iterates over the leaves of the rope in order.
+    pub fn chunks(&self) -> Chunks<'_> {
+        Chunks { stack: vec![&self.root] }
+    }
+
+    #[allow(dead_code)]
+    pub fn chars(&self) -> impl Iterator<Item = char> + '_ {
+        self.chunks().flat_map(str::chars)
+    }
+
+    /// Iterates over the lines of the rope without their trailing newlines.
+    #[allow(dead_code)]
+    pub fn lines(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.len_lines()).map(|line_idx| self.line(line_idx))
+    }
+
+    fn collect_range(node: &Arc<Node>, range: Range<usize>, result: &mut String) {
+        if range.start >= range.end {
+            return;
+        }
+
         match node.as_ref() {
-            Node::Leaf(text) => result.push_str(text),
+            Node::Leaf { text, .. } => {
+                result.push_str(&text[byte_index(text, range.start)..byte_index(text, range.end)]);
+            }
             Node::Internal { left, right, .. } => {
-                self.collect_text(left, result);
-                self.collect_text(right, result);
+                let left_chars = left.info().chars;
+                if range.start < left_chars {
+                    Self::collect_range(left, range.start..range.end.min(left_chars), result);
+                }
+                if range.end > left_chars {
+                    Self::collect_range(right, range.start.saturating_sub(left_chars)..range.end - left_chars, result);
+                }
             }
         }
     }
 }
 
+pub struct Chunks<'a> {
+    stack: Vec<&'a Arc<Node>>,
+}
+
+impl<'a> Iterator for Chunks<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(node) = self.stack.pop() {
+            match node.as_ref() {
+                Node::Leaf { text, .. } if text.is_empty() => continue,
+                Node::Leaf { text, .. } => return Some(text),
+                Node::Internal { left, right, .. } => {
+                    self.stack.push(right);
+                    self.stack.push(left);
+                }
+            }
+        }
+
+        None
+    }
+}
+
 impl fmt::Display for Rope {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let mut result = String::new();
-        self.collect_text(&self.root, &mut result);
-        f.write_str(&result)
+        self.chunks().try_for_each(|chunk| f.write_str(chunk))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(usize, String),
+        Remove(usize, usize),
+        SplitAppend(usize),
+    }
+
+    fn op() -> impl Strategy<Value = Op> {
+        let text = prop_oneof![
+            6 => "[a-z\n]{0,8}",
+            3 => "[a-zé\u{1F600}\n ]{0,64}",
+            1 => "[a-z\n]{1500,3000}",
+        ];
+
+        prop_oneof![
+            4 => (any::<usize>(), text).prop_map(|(i, s)| Op::Insert(i, s)),
+            2 => (any::<usize>(), 0..200usize).prop_map(|(i, n)| Op::Remove(i, n)),
+            1 => any::<usize>().prop_map(Op::SplitAppend),
+        ]
+    }
+
+    fn char_count(model: &str) -> usize {
+        model.chars().count()
+    }
+
+    fn byte_of(model: &str, char_idx: usize) -> usize {
+        byte_index(model, char_idx)
+    }
+
+    /// Checks the structural invariants: cached info matches the leaves, leaves respect `MAX_LEAF`, and the tree
+    /// stays within a logarithmic height of its leaf count.
+    fn check_node(node: &Arc<Node>) -> (TextInfo, usize) {
+        match node.as_ref() {
+            Node::Leaf { text, info } => {
+                assert!(text.len() <= MAX_LEAF);
+                assert_eq!(TextInfo::of(text).chars, info.chars);
+                assert_eq!(TextInfo::of(text).newlines, info.newlines);
+                (*info, 1)
+            }
+            Node::Internal { left, right, info, height } => {
+                assert!(!left.is_empty() && !right.is_empty());
+                assert_eq!(*height, left.height().max(right.height()) + 1);
+                let (left_info, left_leaves) = check_node(left);
+                let (right_info, right_leaves) = check_node(right);
+                let combined = left_info.combine(right_info);
+                assert_eq!(
+                    (combined.bytes, combined.chars, combined.newlines),
+                    (info.bytes, info.chars, info.newlines)
+                );
+                (combined, left_leaves + right_leaves)
+            }
+        }
+    }
+
+    fn check(rope: &Rope, model: &str) {
+        assert_eq!(rope.to_string(), model);
+        assert_eq!(rope.len_bytes(), model.len());
+        assert_eq!(rope.len_chars(), char_count(model));
+
+        let (_, leaves) = check_node(&rope.root);
+        let bound = 2 * (usize::BITS - leaves.leading_zeros()) as usize + 2;
+        assert!(rope.root.height() <= bound, "height {} exceeds {} for {} leaves", rope.root.height(), bound, leaves);
+
+        let lines = model.split('\n').collect::<Vec<_>>();
+        assert_eq!(rope.len_lines(), lines.len());
+
+        let (mut line_start, step) = (0, lines.len() / 64 + 1);
+        for (i, line) in lines.iter().enumerate() {
+            if i % step == 0 || i + 1 == lines.len() {
+                assert_eq!(rope.line_to_char(i), line_start);
+                assert_eq!(rope.line(i), *line);
+                assert_eq!(rope.line_len(i), char_count(line));
+            }
+            line_start += char_count(line) + 1;
+        }
+        assert_eq!(rope.line_to_char(lines.len()), char_count(model));
+
+        let (mut newlines, step) = (0, model.len() / 64 + 1);
+        for (char_idx, (byte_idx, c)) in model.char_indices().enumerate() {
+            if char_idx % step == 0 {
+                assert_eq!(rope.char(char_idx), Some(c));
+                assert_eq!(rope.char_to_byte(char_idx), byte_idx);
+                assert_eq!(rope.byte_to_char(byte_idx), char_idx);
+                assert_eq!(rope.char_to_line(char_idx), newlines);
+            }
+            newlines += (c == '\n') as usize;
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn matches_string_model(initial in "[a-z\n]{0,4000}", ops in prop::collection::vec(op(), 1..32)) {
+            let mut rope = Rope::new(&initial);
+            let mut model = initial.clone();
+            check(&rope, &model);
+
+            for op in ops {
+                let len = char_count(&model);
+                match op {
+                    Op::Insert(i, text) => {
+                        let i = i % (len + 1);
+                        rope.insert(i, &text);
+                        model.insert_str(byte_of(&model, i), &text);
+                    }
+                    Op::Remove(i, n) => {
+                        let start = i % (len + 1);
+                        let end = (start + n).min(len);
+                        rope.remove(start..end);
+                        model.replace_range(byte_of(&model, start)..byte_of(&model, end), "");
+                    }
+                    Op::SplitAppend(i) => {
+                        let i = i % (len + 1);
+                        let (mut left, right) = rope.split_at(i);
+                        let (model_left, model_right) = model.split_at(byte_of(&model, i));
+                        check(&left, model_left);
+                        check(&right, model_right);
+                        left.append(right);
+                        rope = left;
+                    }
+                }
+                check(&rope, &model);
+            }
+        }
+
+        #[test]
+        fn slice_matches_string_model(text in "[a-zé\n]{0,3000}", a in any::<usize>(), b in any::<usize>()) {
+            let rope = Rope::new(&text);
+            let len = char_count(&text);
+            let (start, end) = { let (a, b) = (a % (len + 1), b % (len + 1)); (a.min(b), a.max(b)) };
+            prop_assert_eq!(rope.slice(start..end), &text[byte_of(&text, start)..byte_of(&text, end)]);
+        }
+    }
+
+    #[test]
+    fn snapshots_are_unaffected_by_edits() {
+        let mut rope = Rope::new(&"line\n".repeat(1000));
+        let snapshot = rope.snapshot();
+
+        rope.remove(0..2000);
+        rope.insert(0, "edited\n");
+
+        let handle = std::thread::spawn(move || snapshot.lines().filter(|line| line == "line").count());
+        assert_eq!(handle.join().unwrap(), 1000);
+        assert_eq!(rope.line(0), "edited");
+    }
+
+    #[test]
+    fn iterators_match_contents() {
+        let text = "héllo\n\nwörld\n".repeat(500);
+        let rope = Rope::new(&text);
+
+        assert_eq!(rope.chunks().collect::<String>(), text);
+        assert_eq!(rope.chars().collect::<String>(), text);
+        assert_eq!(rope.lines().collect::<Vec<_>>(), text.split('\n').collect::<Vec<_>>());
+        assert_eq!(Rope::new("").lines().collect::<Vec<_>>(), vec![""]);
+    }
+
+    #[test]
+    fn sequential_typing_stays_balanced() {
+        let mut rope = Rope::new("");
+        let mut model = String::new();
+        for i in 0..5_000 {
+            let c = if i % 40 == 0 { '\n' } else { 'x' };
+            let at = (i * 7919) % (char_count(&model) + 1);
+            rope.insert(at, c.encode_utf8(&mut [0; 4]));
+            model.insert(byte_of(&model, at), c);
+        }
+        check(&rope, &model);
     }
 }
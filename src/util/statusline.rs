@@ -0,0 +1,227 @@
+use color_eyre::{eyre::eyre, Report};
+use std::path::Path;
+
+/// Format of the status line when none is given.
+pub const DEFAULT_FORMAT: &str = " %M  %f %m%r%y%=%S   %e  %E  %l:%c  %p%% ";
+
+/// Part of a status line, written as a `%` item in its format.
+enum Segment {
+    Text(String),
+    /// `%M`: the mode, in the current window only.
+    Mode,
+    /// `%f`: the file name, or `[No Name]`.
+    Filename,
+    /// `%m`: `[+]` when the buffer has changes not written.
+    Modified,
+    /// `%r`: `[RO]` when the file cannot be written.
+    Readonly,
+    /// `%y`: the type of the file as told by its extension, as in `[rust]`.
+    Filetype,
+    /// `%e`: the encoding files are read and written in.
+    Encoding,
+    /// `%E`: the line break the file is written with, `unix` or `dos`.
+    LineEnding,
+    /// `%l` and `%c`: the line and column of the cursor, counting from 1.
+    Line,
+    Column,
+    /// `%L`: the number of lines.
+    Lines,
+    /// `%p`: how far down the buffer the cursor line is, in percent.
+    Percentage,
+    /// `%S`: the keys and count typed so far of a command, in the current window only.
+    PendingKeys,
+    /// `%=`: where the left-aligned part ends and the right-aligned part starts.
+    Align,
+}
+
+/// What the status line of a window shows.
+pub struct StatusInfo<'a> {
+    /// Empty for windows other than the current one.
+    pub mode: &'a str,
+    pub filename: Option<&'a str>,
+    pub modified: bool,
+    pub readonly: bool,
+    pub line_ending: &'a str,
+    /// Column and line of the cursor, counting from 0.
+    pub cursor: (usize, usize),
+    pub lines: usize,
+    /// Empty for windows other than the current one.
+    pub pending: &'a str,
+}
+
+/// Segments drawn in the status line at the bottom of each window.
+pub struct StatusLine {
+    segments: Vec<Segment>,
+}
+
+impl Default for StatusLine {
+    fn default() -> Self {
+        Self::parse(DEFAULT_FORMAT).unwrap()
+    }
+}
+
+/// Type of a file named `filename`, told by its extension.
+fn filetype(filename: &str) -> Option<&'static str> {
+    let extension = Path::new(filename).extension()?.to_str()?;
+    let filetype = match extension {
+        "rs" => "rust",
+        "c" | "h" => "c",
+        "cc" | "cpp" | "hpp" => "cpp",
+        "go" => "go",
+        "java" => "java",
+        "js" | "mjs" => "javascript",
+        "ts" => "typescript",
+        "py" => "python",
+        "rb" => "ruby",
+        "lua" => "lua",
+        "sh" | "bash" => "sh",
+        "nix" => "nix",
+        "html" => "html",
+        "css" => "css",
+        "json" => "json",
+        "toml" => "toml",
+        "yaml" | "yml" => "yaml",
+        "md" => "markdown",
+        "txt" => "text",
+        _ => return None,
+    };
+    Some(filetype)
+}
+
+impl StatusLine {
+    /// Reads a format such as `%f %m%=%l:%c`, where `%` items are replaced by what they stand for and everything else
+    /// is shown as it is. Items: `%M` mode, `%f` file name, `%m` modified, `%r` readonly, `%y` filetype, `%e`
+    /// encoding, `%E` line ending, `%l` line, `%c` column, `%L` number of lines, `%p` percentage, `%S` pending keys
+    /// and count, `%=` right-align what follows, and `%%` for `%` itself.
+    pub fn parse(format: &str) -> Result<Self, Report> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = format.chars();
+
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                text.push(c);
+                continue;
+            }
+
+            let segment = match chars.next() {
+                Some('%') => {
+                    text.push('%');
+                    continue;
+                }
+                Some('M') => Segment::Mode,
+                Some('f') => Segment::Filename,
+                Some('m') => Segment::Modified,
+                Some('r') => Segment::Readonly,
+                Some('y') => Segment::Filetype,
+                Some('e') => Segment::Encoding,
+                Some('E') => Segment::LineEnding,
+                Some('l') => Segment::Line,
+                Some('c') => Segment::Column,
+                Some('L') => Segment::Lines,
+                Some('p') => Segment::Percentage,
+                Some('S') => Segment::PendingKeys,
+                Some('=') => Segment::Align,
+                Some(item) => return Err(eyre!("Unknown status line item: %{}", item)),
+                None => return Err(eyre!("Status line format ends with %")),
+            };
+
+            if !text.is_empty() {
+                segments.push(Segment::Text(std::mem::take(&mut text)));
+            }
+            segments.push(segment);
+        }
+
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Self { segments })
+    }
+
+    /// Text of the status line for a window `width` columns wide, with what follows `%=` at its right edge. The
+    /// left part is cut short when both do not fit.
+    pub fn render(&self, info: &StatusInfo, width: usize) -> String {
+        let (mut left, mut right) = (String::new(), String::new());
+        let mut aligned = false;
+        let (column, line) = info.cursor;
+
+        for segment in &self.segments {
+            let text = match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Mode => info.mode.to_string(),
+                Segment::Filename => info.filename.unwrap_or("[No Name]").to_string(),
+                Segment::Modified => if info.modified { "[+]" } else { "" }.to_string(),
+                Segment::Readonly => if info.readonly { "[RO]" } else { "" }.to_string(),
+                Segment::Filetype => {
+                    info.filename.and_then(filetype).map_or(String::new(), |filetype| format!("[{}]", filetype))
+                }
+                Segment::Encoding => "utf-8".to_string(),
+                Segment::LineEnding => info.line_ending.to_string(),
+                Segment::Line => (line + 1).to_string(),
+                Segment::Column => (column + 1).to_string(),
+                Segment::Lines => info.lines.to_string(),
+                Segment::Percentage => ((line + 1) * 100 / info.lines.max(1)).to_string(),
+                Segment::PendingKeys => info.pending.to_string(),
+                Segment::Align => {
+                    aligned = true;
+                    continue;
+                }
+            };
+
+            match aligned {
+                true => right.push_str(&text),
+                false => left.push_str(&text),
+            }
+        }
+
+        let right_width = right.chars().count().min(width);
+        let left = left.chars().take(width - right_width).collect::<String>();
+        format!("{:<fill$}{}", left, right, fill = width - right_width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> StatusInfo<'static> {
+        StatusInfo {
+            mode: "NORMAL",
+            filename: Some("src/main.rs"),
+            modified: true,
+            readonly: false,
+            line_ending: "unix",
+            cursor: (4, 9),
+            lines: 40,
+            pending: "2d",
+        }
+    }
+
+    fn render(format: &str, width: usize) -> String {
+        StatusLine::parse(format).unwrap().render(&info(), width)
+    }
+
+    #[test]
+    fn parses_items() {
+        assert!(StatusLine::parse(DEFAULT_FORMAT).is_ok());
+        assert!(StatusLine::parse("").unwrap().segments.is_empty());
+        assert!(StatusLine::parse("%x").is_err());
+        assert!(StatusLine::parse("50%").is_err());
+        assert!(matches!(&StatusLine::parse("%%").unwrap().segments[..], [Segment::Text(text)] if text == "%"));
+    }
+
+    #[test]
+    fn renders_segments() {
+        assert_eq!(render("%M %f%m%r %y", 30), "NORMAL src/main.rs[+] [rust]  ");
+        assert_eq!(render("%l:%c/%L %p%% %e %E %S", 27), "10:5/40 25% utf-8 unix 2d  ");
+
+        let info = StatusInfo { filename: None, modified: false, readonly: true, ..info() };
+        assert_eq!(StatusLine::parse("%f%m%r%y").unwrap().render(&info, 13), "[No Name][RO]");
+    }
+
+    #[test]
+    fn aligns_right_part() {
+        assert_eq!(render("%f%=%l:%c", 20), "src/main.rs     10:5");
+        assert_eq!(render("%f%=%l:%c", 10), "src/ma10:5");
+    }
+}